@@ -0,0 +1,359 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+
+// acoustic echo cancellation for duplex clients
+//
+// a client that plays the server's audio on speakers picks it up again on its microphone and
+// sends it back. whatever the local output actually plays is kept as the reference, and an
+// adaptive (nlms) filter learns how the room turns that into echo on the capture, which is then
+// subtracted before the block is sent. adaptation pauses while the near end talks over the far end
+// so the filter doesn't learn the talker instead of the room
+//
+// the reference goes from the output callback to the capture callback through a lock free ring,
+// neither side waits on the other
+
+// reference held for the capture side, anything older can't line up with the microphone anymore
+static REFERENCE_BACKLOG: Duration = Duration::from_millis(40);
+// room in the ring for the output to run ahead of the capture between two of its callbacks
+static REFERENCE_CAPACITY: Duration = Duration::from_millis(250);
+// a near end this loud compared to the far end is someone talking, not echo (geigel detector)
+static DOUBLE_TALK_THRESHOLD: f32 = 0.5;
+// how long adaptation stays paused after double talk was last detected
+static DOUBLE_TALK_HANGOVER: Duration = Duration::from_millis(30);
+
+#[derive(Clone, Copy, Debug)]
+pub struct EchoConfig {
+    // longest echo path the filter covers, from the output callback to the microphone callback.
+    // the filter costs this many multiply-adds twice per captured sample and channel
+    pub filter_length: Duration,
+    // nlms step size in (0, 1], larger adapts faster but leaves more residual echo
+    pub step_size: f32,
+}
+
+impl Default for EchoConfig {
+    fn default() -> Self {
+        EchoConfig {
+            filter_length: Duration::from_millis(64),
+            step_size: 0.3,
+        }
+    }
+}
+
+// what the local output played, shared between the output device and the capture path
+#[derive(Clone, Default)]
+pub struct EchoReference {
+    // only the capture callback takes it, apart from an output device (re)opening
+    reader: Arc<Mutex<ReferenceReader>>,
+}
+
+#[derive(Default)]
+struct ReferenceReader {
+    // mono, at the output device's rate. `None` until an output opens
+    consumer: Option<HeapConsumer<f32>>,
+    sample_rate: u32,
+    // position between the first two queued samples when resampling to the capture rate
+    phase: f64,
+}
+
+// the output device's end of the reference, owned by the tap that plays it
+pub struct ReferenceWriter {
+    producer: HeapProducer<f32>,
+}
+
+impl ReferenceWriter {
+    // a block as played, already mixed down to mono. a full ring means nothing is capturing, the
+    // block is dropped
+    pub fn push(&mut self, block: &[f32]) {
+        self.producer.push_slice(block);
+    }
+}
+
+impl EchoReference {
+    // the output device (re)opened at `sample_rate`, what the old one queued is dropped with its ring
+    pub fn writer(&self, sample_rate: u32) -> ReferenceWriter {
+        let capacity = ((REFERENCE_CAPACITY.as_secs_f32() * sample_rate as f32) as usize).max(8192);
+        let (producer, consumer) = HeapRb::new(capacity).split();
+
+        *self.reader.lock().unwrap() = ReferenceReader { consumer: Some(consumer), sample_rate, phase: 0.0 };
+
+        ReferenceWriter { producer }
+    }
+
+    // fill `out` with the reference at `sample_rate`, silence where nothing has been played
+    pub fn take(&self, out: &mut [f32], sample_rate: u32) {
+        // only contended while an output device opens, the capture goes without a reference for
+        // that block rather than wait
+        let mut reader = match self.reader.try_lock() {
+            Ok(reader) => reader,
+            Err(_) => {
+                out.fill(0.0);
+                return;
+            }
+        };
+        let reader = &mut *reader;
+        let ratio = reader.sample_rate as f64 / sample_rate.max(1) as f64;
+
+        let consumer = match &mut reader.consumer {
+            Some(consumer) => consumer,
+            None => {
+                out.fill(0.0);
+                return;
+            }
+        };
+
+        // whatever is older than the backlog plus this block can't line up anymore
+        let backlog = (REFERENCE_BACKLOG.as_secs_f32() * reader.sample_rate as f32) as usize + (out.len() as f64 * ratio).ceil() as usize + 1;
+        if consumer.len() > backlog {
+            consumer.skip(consumer.len() - backlog);
+        }
+
+        for sample in out.iter_mut() {
+            let (a, b) = {
+                let mut queued = consumer.iter();
+                match (queued.next(), queued.next()) {
+                    (Some(a), Some(b)) => (*a, *b),
+                    _ => {
+                        *sample = 0.0;
+                        continue;
+                    }
+                }
+            };
+            *sample = a + (b - a) * reader.phase as f32;
+
+            reader.phase += ratio;
+            while reader.phase >= 1.0 && consumer.len() > 1 {
+                consumer.skip(1);
+                reader.phase -= 1.0;
+            }
+        }
+    }
+}
+
+pub struct EchoCanceller {
+    channels: usize,
+    length: usize,
+    step_size: f32,
+    // one filter per capture channel, tap `k` weighs the reference from `k` samples ago
+    weights: Vec<Vec<f32>>,
+    // the reference twice over so the newest `length` samples are always one contiguous slice
+    // starting at `position`, newest first
+    history: Vec<f32>,
+    position: usize,
+    energy: f64,
+    // decaying peak of the reference, what double talk is measured against
+    peak: f32,
+    peak_decay: f32,
+    hangover: usize,
+    hangover_samples: usize,
+    // smoothed power of the capture and of what's left of it, while the far end plays
+    near_power: f32,
+    residual_power: f32,
+}
+
+impl EchoCanceller {
+    pub fn new(config: EchoConfig, channels: u16, sample_rate: u32) -> Self {
+        let channels = channels.max(1) as usize;
+        let length = ((config.filter_length.as_secs_f32() * sample_rate as f32) as usize).max(1);
+
+        EchoCanceller {
+            channels,
+            length,
+            step_size: config.step_size.clamp(0.0, 1.0),
+            weights: vec![vec![0.0; length]; channels],
+            history: vec![0.0; 2 * length],
+            position: 0,
+            energy: 0.0,
+            peak: 0.0,
+            // falls to a tenth over the length of the filter
+            peak_decay: 0.1f32.powf(1.0 / length as f32),
+            hangover: 0,
+            hangover_samples: (DOUBLE_TALK_HANGOVER.as_secs_f32() * sample_rate as f32) as usize,
+            near_power: 0.0,
+            residual_power: 0.0,
+        }
+    }
+
+    // remove the echo of `reference` (mono, one sample per frame) from interleaved `capture`
+    pub fn process(&mut self, capture: &mut [f32], reference: &[f32]) {
+        let channels = self.channels;
+        let length = self.length;
+
+        for (frame, far) in capture.chunks_mut(channels).zip(reference.iter().copied()) {
+            self.position = match self.position {
+                0 => length - 1,
+                position => position - 1
+            };
+
+            let oldest = self.history[self.position];
+            self.energy = (self.energy + (far * far) as f64 - (oldest * oldest) as f64).max(0.0);
+            self.history[self.position] = far;
+            self.history[self.position + length] = far;
+
+            self.peak = far.abs().max(self.peak * self.peak_decay);
+
+            let near_peak = frame.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+            if near_peak > DOUBLE_TALK_THRESHOLD * self.peak {
+                self.hangover = self.hangover_samples;
+            }
+            let adapting = self.hangover == 0 && self.peak > f32::EPSILON;
+            self.hangover = self.hangover.saturating_sub(1);
+
+            let window = &self.history[self.position..self.position + length];
+            let normalisation = self.step_size / (self.energy as f32 + length as f32 * 1e-6);
+
+            for (sample, weights) in frame.iter_mut().zip(self.weights.iter_mut()) {
+                let estimate: f32 = weights.iter().zip(window).map(|(w, x)| w * x).sum();
+                let error = *sample - estimate;
+
+                if adapting {
+                    let gain = normalisation * error;
+                    for (w, x) in weights.iter_mut().zip(window) {
+                        *w += gain * x;
+                    }
+                }
+
+                if self.peak > f32::EPSILON {
+                    self.near_power = 0.999 * self.near_power + 0.001 * *sample * *sample;
+                    self.residual_power = 0.999 * self.residual_power + 0.001 * error * error;
+                }
+
+                *sample = error;
+            }
+        }
+    }
+
+    // how much quieter the capture is after cancellation, in db, while the far end plays
+    pub fn echo_return_loss_enhancement(&self) -> f32 {
+        10.0 * ((self.near_power + 1e-12) / (self.residual_power + 1e-12)).log10()
+    }
+}
+
+// the canceller as the capture path of a duplex client runs it
+pub struct CaptureEcho {
+    canceller: EchoCanceller,
+    reference: EchoReference,
+    enabled: Arc<AtomicBool>,
+    sample_rate: u32,
+    far: Vec<f32>,
+    near: Vec<f32>,
+}
+
+impl CaptureEcho {
+    pub fn new(config: EchoConfig, channels: u16, sample_rate: u32, reference: EchoReference, enabled: Arc<AtomicBool>) -> Self {
+        CaptureEcho {
+            canceller: EchoCanceller::new(config, channels, sample_rate),
+            reference,
+            enabled,
+            sample_rate,
+            far: Vec::with_capacity(8192),
+            near: Vec::with_capacity(8192),
+        }
+    }
+
+    // the block to send in place of `data`
+    pub fn process<'a>(&'a mut self, data: &'a [f32]) -> &'a [f32] {
+        // the reference is taken even when disabled so it stays in step with the capture
+        self.far.resize(data.len() / self.canceller.channels, 0.0);
+        self.reference.take(&mut self.far, self.sample_rate);
+
+        if !self.enabled.load(Ordering::Relaxed) {
+            return data;
+        }
+
+        self.near.clear();
+        self.near.extend_from_slice(data);
+        self.canceller.process(&mut self.near, &self.far);
+
+        &self.near
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SAMPLE_RATE: u32 = 8_000;
+    // the echo path: 10ms late and 12db down, quiet enough not to pass for double talk
+    static DELAY: usize = 80;
+    static ECHO_GAIN: f32 = 0.25;
+
+    // deterministic noise in [-1, 1)
+    fn noise(frames: usize, seed: u32) -> Vec<f32> {
+        let mut state = seed;
+        (0..frames)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 8) as f32 / (1 << 23) as f32 - 1.0
+            })
+            .collect()
+    }
+
+    fn echo_of(far: &[f32]) -> Vec<f32> {
+        (0..far.len()).map(|n| if n >= DELAY { far[n - DELAY] * ECHO_GAIN } else { 0.0 }).collect()
+    }
+
+    fn canceller() -> EchoCanceller {
+        EchoCanceller::new(EchoConfig { filter_length: Duration::from_millis(32), step_size: 0.5 }, 1, SAMPLE_RATE)
+    }
+
+    #[test]
+    fn delayed_attenuated_echo_is_cancelled() {
+        let far = noise(3 * SAMPLE_RATE as usize, 1);
+        let mut capture = echo_of(&far);
+
+        let mut canceller = canceller();
+        for (capture, far) in capture.chunks_mut(160).zip(far.chunks(160)) {
+            canceller.process(capture, far);
+        }
+
+        let erle = canceller.echo_return_loss_enhancement();
+        assert!(erle > 30.0, "echo only attenuated by {:.1} db", erle);
+
+        // what's left of the last second is next to nothing
+        let tail = &capture[2 * SAMPLE_RATE as usize..];
+        let residual = tail.iter().map(|sample| sample * sample).sum::<f32>() / tail.len() as f32;
+        assert!(residual < 1e-4, "residual power {}", residual);
+    }
+
+    #[test]
+    fn adaptation_freezes_while_the_near_end_talks() {
+        let far = noise(2 * SAMPLE_RATE as usize, 7);
+        let mut capture = echo_of(&far);
+
+        let mut canceller = canceller();
+        let (converging, talking) = capture.split_at_mut(SAMPLE_RATE as usize);
+        canceller.process(converging, &far[..SAMPLE_RATE as usize]);
+        let learned = canceller.weights.clone();
+
+        // a talker louder than the far end, the filter must not learn it as echo
+        let talker: Vec<f32> = (0..talking.len()).map(|n| if (n / 20) % 2 == 0 { 1.5 } else { -1.5 }).collect();
+        for (sample, talk) in talking.iter_mut().zip(talker.iter()) {
+            *sample += talk;
+        }
+        canceller.process(talking, &far[SAMPLE_RATE as usize..]);
+
+        assert_eq!(canceller.weights, learned);
+        // the echo is still taken out from under the talker
+        let residual = talking.iter().zip(talker.iter()).map(|(out, talk)| (out - talk) * (out - talk)).sum::<f32>() / talking.len() as f32;
+        assert!(residual < 1e-4, "residual power {}", residual);
+    }
+
+    #[test]
+    fn reference_reaches_the_capture_side_in_order() {
+        let reference = EchoReference::default();
+        let mut silence = [1.0; 4];
+        reference.take(&mut silence, SAMPLE_RATE);
+        assert_eq!(silence, [0.0; 4]);
+
+        let mut writer = reference.writer(SAMPLE_RATE);
+        let played = noise(64, 3);
+        writer.push(&played);
+
+        let mut taken = vec![0.0; 32];
+        reference.take(&mut taken, SAMPLE_RATE);
+        assert_eq!(taken, played[..32]);
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::time::{Duration, Instant};
+
+use cpal::StreamConfig;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::capture::Captures;
+use crate::stats::StreamCounters;
+
+// one stream played to any number of listeners
+//
+// a stream is either the server's input device or the audio of a connected client. the source
+// only copies its audio into a lock free queue, a task drains it and does the rest: each block is
+// serialized once and the same encoding is handed to every listener's connection, so a room full
+// of listeners costs one encode per block. a listener that falls behind loses blocks instead of
+// holding up the others. one that stays behind is moved to a reduced stream, mono at half the
+// sample rate, and gets the full one back once it keeps up for a while. one that can't even keep
+// up with that is cut off
+
+// name of the server's input device stream, what `BinListen` subscribes to
+pub static INPUT_STREAM: &str = "input";
+
+// blocks queued per listener before new ones are dropped
+static LISTENER_BACKLOG: usize = 64;
+// samples queued between the source and the fan out task, over half a second of stereo at 48khz
+static PUBLISH_QUEUE: usize = 1 << 16;
+// how often the fan out task hands what was published to the listeners
+static FAN_OUT_INTERVAL: Duration = Duration::from_millis(5);
+// a listener dropping blocks for this long gets the reduced stream
+static DOWNGRADE_AFTER: Duration = Duration::from_secs(1);
+// a listener on the reduced stream that keeps up this long gets the full one back
+static UPGRADE_AFTER: Duration = Duration::from_secs(10);
+// a listener still dropping blocks this long after it was downgraded is disconnected
+static SLOW_LISTENER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// a block of audio as it goes out to every listener
+pub struct Block {
+    pub samples: Vec<f32>,
+    // `BinData` of `samples`, for transports that send it as is
+    pub encoded: Vec<u8>,
+    pub frames: u64,
+}
+
+impl Block {
+    fn new(samples: Vec<f32>, channels: usize) -> Option<Block> {
+        let message = crate::BinMessages::BinData(samples);
+        let encoded = bincode::serialize(&message).ok()?;
+        let samples = match message {
+            crate::BinMessages::BinData(samples) => samples,
+            _ => unreachable!()
+        };
+
+        Some(Block { frames: (samples.len() / channels.max(1)) as u64, samples, encoded })
+    }
+}
+
+pub enum Cast {
+    // the stream's format changed, comes before any block in the new format
+    Config(StreamConfig),
+    Block(Arc<Block>),
+}
+
+// what slow listeners get instead, mono at half the rate
+fn reduced_config(config: &StreamConfig) -> StreamConfig {
+    StreamConfig {
+        channels: 1,
+        sample_rate: cpal::SampleRate((config.sample_rate.0 / 2).max(1)),
+        buffer_size: config.buffer_size,
+    }
+}
+
+struct Subscriber {
+    id: u64,
+    sender: mpsc::Sender<Cast>,
+    stats: Arc<StreamCounters>,
+    device_errors: SyncSender<cpal::StreamError>,
+    // on the reduced stream
+    reduced: bool,
+    // the config it should get before its next block, set when the format or its stream changed
+    config_pending: bool,
+    // when this listener last started dropping blocks
+    behind_since: Option<Instant>,
+    // when this listener last started keeping up
+    keeping_up_since: Instant,
+}
+
+impl Subscriber {
+    // hand over a block of the stream it is on, `false` once it should be cut off
+    fn deliver(&mut self, config: &StreamConfig, full: &Arc<Block>, reduced: &Option<Arc<Block>>, now: Instant) -> bool {
+        let (block, config) = match (self.reduced, reduced) {
+            (true, Some(reduced)) => (reduced, reduced_config(config)),
+            _ => (full, config.clone()),
+        };
+        let channels = config.channels.max(1) as usize;
+
+        let sent = match self.config_pending {
+            true => self.sender.try_send(Cast::Config(config)).map(|()| self.config_pending = false),
+            false => Ok(())
+        }.and_then(|()| self.sender.try_send(Cast::Block(Arc::clone(block))));
+
+        match sent {
+            Ok(()) => {
+                self.stats.meter(&block.samples, channels);
+                if self.behind_since.take().is_some() {
+                    self.keeping_up_since = now;
+                }
+
+                if self.reduced && now.duration_since(self.keeping_up_since) >= UPGRADE_AFTER {
+                    info!(listener = self.id, "listener caught up, back to the full stream");
+                    self.reduced = false;
+                    self.config_pending = true;
+                }
+                true
+            },
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+            // a full queue means the listener's connection can't keep up
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.stats.overrun();
+                let behind = now.duration_since(*self.behind_since.get_or_insert(now));
+
+                match self.reduced {
+                    true => behind < SLOW_LISTENER_TIMEOUT,
+                    false => {
+                        if behind >= DOWNGRADE_AFTER {
+                            info!(listener = self.id, "listener is falling behind, moving it to the reduced stream");
+                            self.reduced = true;
+                            self.config_pending = true;
+                            self.behind_since = Some(now);
+                        }
+                        true
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct BroadcastState {
+    // unknown until the source has said what it sends. what the fan out task is at, formats the
+    // source changed to since are in `changes`
+    config: Option<StreamConfig>,
+    // formats waiting on the audio published before them, by the sample count they start at
+    changes: VecDeque<(u64, StreamConfig)>,
+    subscribers: Vec<Subscriber>,
+    // taken by the fan out task while it runs, which is while anyone listens
+    consumer: Option<HeapConsumer<f32>>,
+}
+
+pub struct Broadcast {
+    state: Mutex<BroadcastState>,
+    // only the source's thread takes it, never waited on
+    producer: Mutex<HeapProducer<f32>>,
+    // samples pushed into the queue so far
+    published: AtomicU64,
+    // anyone listening, nothing is queued otherwise
+    live: AtomicBool,
+    next_id: AtomicU64,
+}
+
+impl Default for Broadcast {
+    fn default() -> Self {
+        let (producer, consumer) = HeapRb::new(PUBLISH_QUEUE).split();
+
+        Broadcast {
+            state: Mutex::new(BroadcastState { config: None, changes: VecDeque::new(), subscribers: vec![], consumer: Some(consumer) }),
+            producer: Mutex::new(producer),
+            published: AtomicU64::new(0),
+            live: AtomicBool::new(false),
+            next_id: AtomicU64::new(0),
+        }
+    }
+}
+
+// one listener's share of a stream, unsubscribes when dropped
+pub struct Subscription {
+    id: u64,
+    // config of the first blocks, a `Cast::Config` comes through `receiver` whenever it changes
+    pub config: StreamConfig,
+    // closed when the listener was cut off for falling behind
+    pub receiver: mpsc::Receiver<Cast>,
+    broadcast: Arc<Broadcast>,
+    // set for the input stream, whose device closes with its last listener
+    pub(crate) captures: Option<Captures>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.broadcast.unsubscribe(self.id);
+
+        if let Some(captures) = self.captures.take() {
+            captures.release();
+        }
+    }
+}
+
+impl Broadcast {
+    pub fn subscribe(self: &Arc<Self>, stats: Arc<StreamCounters>, device_errors: SyncSender<cpal::StreamError>) -> Result<Subscription, Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+        let config = state.config.clone().ok_or("stream has not started")?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel(LISTENER_BACKLOG);
+        state.subscribers.push(Subscriber {
+            id,
+            sender,
+            stats,
+            device_errors,
+            reduced: false,
+            config_pending: false,
+            behind_since: None,
+            keeping_up_since: Instant::now(),
+        });
+
+        // the first listener starts the fan out
+        if let Some(mut consumer) = state.consumer.take() {
+            // left from before anyone listened
+            consumer.clear();
+            self.published.store(0, Ordering::Relaxed);
+            tokio::spawn(Arc::clone(self).fan_out(consumer));
+        }
+        self.live.store(true, Ordering::Relaxed);
+
+        Ok(Subscription {
+            id,
+            config,
+            receiver,
+            broadcast: Arc::clone(self),
+            captures: None,
+        })
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.subscribers.retain(|subscriber| subscriber.id != id);
+        self.live.store(!state.subscribers.is_empty(), Ordering::Relaxed);
+    }
+
+    pub fn listeners(&self) -> usize {
+        self.state.lock().unwrap().subscribers.len()
+    }
+
+    pub fn config(&self) -> Option<StreamConfig> {
+        self.state.lock().unwrap().config.clone()
+    }
+
+    // what the source sends from now on, current listeners are told before the first block of it
+    pub fn set_config(&self, config: &StreamConfig) {
+        let mut state = self.state.lock().unwrap();
+
+        match state.consumer.is_some() {
+            // nobody listening, nothing queued to wait on
+            true => state.config = Some(config.clone()),
+            false => {
+                let at = self.published.load(Ordering::Relaxed);
+                state.changes.push_back((at, config.clone()));
+            }
+        }
+    }
+
+    // queue a block for the listeners, interleaved in the stream's config. safe to call from the
+    // audio callback, a block that doesn't fit is dropped
+    pub fn publish(&self, data: &[f32]) {
+        if !self.live.load(Ordering::Relaxed) {
+            return;
+        }
+
+        // a second source at once only happens while a client's connection is being replaced
+        let mut producer = match self.producer.try_lock() {
+            Ok(producer) => producer,
+            Err(_) => return
+        };
+
+        if producer.free_len() >= data.len() {
+            producer.push_slice(data);
+            self.published.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    // runs while anyone listens, hands what was published to every listener
+    async fn fan_out(self: Arc<Self>, mut consumer: HeapConsumer<f32>) {
+        let mut consumed = 0u64;
+        // a mono frame waiting for the next one to be averaged with, for the reduced stream
+        let mut reduced_carry: Option<f32> = None;
+
+        loop {
+            tokio::time::sleep(FAN_OUT_INTERVAL).await;
+
+            let mut state = self.state.lock().unwrap();
+            if state.subscribers.is_empty() {
+                state.consumer = Some(consumer);
+                state.changes.clear();
+                return;
+            }
+
+            while let Some(config) = state.config.clone() {
+                let channels = config.channels.max(1) as usize;
+
+                // audio up to the next format change, whole frames of it
+                let until_change = state.changes.front().map(|(at, _)| at.saturating_sub(consumed) as usize);
+                let available = until_change.unwrap_or(usize::MAX).min(consumer.len());
+                let available = available - available % channels;
+
+                if available > 0 {
+                    let mut samples = vec![0.0; available];
+                    consumer.pop_slice(&mut samples);
+                    consumed += available as u64;
+
+                    let reduced = match state.subscribers.iter().any(|subscriber| subscriber.reduced) {
+                        true => Block::new(reduce(&samples, channels, &mut reduced_carry), 1).map(Arc::new),
+                        false => None
+                    };
+                    let full = match Block::new(samples, channels) {
+                        Some(block) => Arc::new(block),
+                        None => continue
+                    };
+
+                    let now = Instant::now();
+                    state.subscribers.retain_mut(|subscriber| subscriber.deliver(&config, &full, &reduced, now));
+                }
+
+                // changes come between blocks, never inside a frame
+                let left = state.changes.front().map(|(at, _)| at.saturating_sub(consumed));
+                match left {
+                    Some(left) if left < channels as u64 && consumer.len() as u64 >= left => {
+                        consumed += consumer.skip(left as usize) as u64;
+                        let (_, config) = state.changes.pop_front().expect("change was just looked at!");
+                        state.config = Some(config);
+                        reduced_carry = None;
+
+                        for subscriber in state.subscribers.iter_mut() {
+                            subscriber.config_pending = true;
+                        }
+                    },
+                    _ => break
+                }
+            }
+
+            self.live.store(!state.subscribers.is_empty(), Ordering::Relaxed);
+        }
+    }
+
+    // the source's device failed, logged by each listener's connection task
+    pub fn device_error(&self, e: &cpal::StreamError) {
+        for subscriber in self.state.lock().unwrap().subscribers.iter() {
+            subscriber.stats.device_error();
+            let _ = subscriber.device_errors.try_send(crate::logging::copy_stream_error(e));
+        }
+    }
+}
+
+// mixes interleaved `samples` down to mono and averages every two frames into one. `carry` is an
+// odd frame left from the last block
+fn reduce(samples: &[f32], channels: usize, carry: &mut Option<f32>) -> Vec<f32> {
+    let mut reduced = Vec::with_capacity(samples.len() / channels / 2 + 1);
+
+    for frame in samples.chunks_exact(channels) {
+        let mono = frame.iter().sum::<f32>() / channels as f32;
+
+        match carry.take() {
+            Some(previous) => reduced.push((previous + mono) / 2.0),
+            None => *carry = Some(mono)
+        }
+    }
+
+    reduced
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct StreamInfo {
+    pub name: String,
+    // id of the client sending it, `None` for the server's input
+    pub source: Option<String>,
+    // the only room it can be heard from
+    pub room: Option<String>,
+    pub listeners: usize,
+}
+
+// the streams of connected clients, by room and client id. a stream outlives its source for as
+// long as anyone listens, so a client that reconnects to the same room picks its listeners back up
+type Streams = HashMap<(String, String), Weak<Broadcast>>;
+
+#[derive(Clone, Default)]
+pub struct Broadcasts {
+    streams: Arc<Mutex<Streams>>,
+}
+
+impl Broadcasts {
+    // the stream published by `id` in `room`, created on first use
+    pub fn stream(&self, room: &str, id: &str) -> Arc<Broadcast> {
+        let mut streams = self.streams.lock().unwrap();
+        streams.retain(|_, stream| stream.strong_count() > 0);
+
+        let key = (room.to_owned(), id.to_owned());
+        if let Some(stream) = streams.get(&key).and_then(Weak::upgrade) {
+            return stream;
+        }
+
+        let stream = Arc::new(Broadcast::default());
+        streams.insert(key, Arc::downgrade(&stream));
+
+        stream
+    }
+
+    pub fn find(&self, room: &str, id: &str) -> Option<Arc<Broadcast>> {
+        self.streams.lock().unwrap().get(&(room.to_owned(), id.to_owned())).and_then(Weak::upgrade)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(channels: u16, sample_rate: u32) -> StreamConfig {
+        StreamConfig { channels, sample_rate: cpal::SampleRate(sample_rate), buffer_size: cpal::BufferSize::Default }
+    }
+
+    fn subscribe(broadcast: &Arc<Broadcast>) -> Subscription {
+        let (device_errors, _) = crate::logging::device_error_channel();
+        broadcast.subscribe(Arc::new(StreamCounters::default()), device_errors).unwrap()
+    }
+
+    #[tokio::test]
+    async fn format_changes_reach_listeners_between_blocks() {
+        let broadcast = Arc::new(Broadcast::default());
+        broadcast.set_config(&config(2, 48_000));
+        let mut subscription = subscribe(&broadcast);
+
+        broadcast.publish(&[0.1; 960]);
+        broadcast.set_config(&config(1, 24_000));
+        broadcast.publish(&[0.2; 480]);
+        tokio::time::sleep(FAN_OUT_INTERVAL * 4).await;
+
+        match subscription.receiver.try_recv() {
+            Ok(Cast::Block(block)) => assert_eq!((block.samples.len(), block.frames), (960, 480)),
+            _ => panic!("expected the stereo block first")
+        }
+        match subscription.receiver.try_recv() {
+            Ok(Cast::Config(config)) => assert_eq!((config.channels, config.sample_rate.0), (1, 24_000)),
+            _ => panic!("expected the new format before its first block")
+        }
+        match subscription.receiver.try_recv() {
+            Ok(Cast::Block(block)) => assert_eq!(block.samples, vec![0.2; 480]),
+            _ => panic!("expected the mono block")
+        }
+    }
+
+    #[tokio::test]
+    async fn listener_that_stays_behind_gets_the_reduced_stream() {
+        let broadcast = Arc::new(Broadcast::default());
+        broadcast.set_config(&config(2, 48_000));
+        let mut slow = subscribe(&broadcast);
+        let mut fast = subscribe(&broadcast);
+
+        // the slow listener never reads, its queue fills and stays full past the downgrade
+        let started = Instant::now();
+        while started.elapsed() < DOWNGRADE_AFTER + Duration::from_millis(800) {
+            broadcast.publish(&[0.5; 96]);
+            tokio::time::sleep(FAN_OUT_INTERVAL + Duration::from_millis(1)).await;
+            while fast.receiver.try_recv().is_ok() {}
+        }
+        while slow.receiver.try_recv().is_ok() {}
+
+        broadcast.publish(&[0.5; 96]);
+        tokio::time::sleep(FAN_OUT_INTERVAL * 4).await;
+
+        match slow.receiver.try_recv() {
+            Ok(Cast::Config(config)) => assert_eq!((config.channels, config.sample_rate.0), (1, 24_000)),
+            _ => panic!("expected the reduced format")
+        }
+        match slow.receiver.try_recv() {
+            Ok(Cast::Block(block)) => assert_eq!(block.samples, vec![0.5; 24]),
+            _ => panic!("expected a reduced block")
+        }
+
+        // the other listener never noticed
+        match fast.receiver.try_recv() {
+            Ok(Cast::Block(block)) => assert_eq!(block.samples.len(), 96),
+            _ => panic!("expected a full block")
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use futures::{SinkExt, StreamExt};
+use serde::{Serialize, Deserialize};
+use tokio::net::TcpStream;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+use crate::broadcast::Block;
+use crate::identity::BinClientIdentity;
+use crate::quic::TransportError;
+
+// browsers as clients, with no app to install
+//
+// a browser can't speak bincode comfortably, so websockets opened on `BROWSER_PATH` of the server's
+// address get a framing of their own: control messages are json text frames (`BrowserMessage`)
+// and audio is binary frames of interleaved little endian f32 samples, what a `Float32Array` reads
+// straight off the wire. the transport translates both ways, so past the handshake a browser is a
+// client like any other, listening or sending. the pages that use it are served on the rest
+// endpoint, see `LISTEN_PAGE` and `SEND_PAGE`
+
+// websockets opened on this path of the server's address speak the browser framing
+pub static BROWSER_PATH: &str = "/browser";
+
+// plays a stream in the browser with WebAudio, served as `/listen`. `{{server_port}}` is filled in
+// with the port of the server's address
+pub static LISTEN_PAGE: &str = include_str!("web/listen.html");
+
+// sends the browser's microphone as a client, served as `/send`. browsers only hand out the
+// microphone to pages on https or localhost
+pub static SEND_PAGE: &str = include_str!("web/send.html");
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BrowserMessage {
+    Identify { id: String, name: String },
+    Join { room: String },
+    Listen,
+    Subscribe { stream: String },
+    Config { channels: u16, sample_rate: u32 },
+    Ping { nonce: u64 },
+    Pong { nonce: u64 },
+}
+
+impl BrowserMessage {
+    fn from_bin(message: &crate::BinMessages) -> Option<Self> {
+        Some(match message {
+            crate::BinMessages::BinIdentify(identity) => BrowserMessage::Identify { id: identity.id.clone(), name: identity.name.clone() },
+            crate::BinMessages::BinJoin(room) => BrowserMessage::Join { room: room.clone() },
+            crate::BinMessages::BinListen => BrowserMessage::Listen,
+            crate::BinMessages::BinSubscribe(stream) => BrowserMessage::Subscribe { stream: stream.clone() },
+            crate::BinMessages::BinConfig(config) => BrowserMessage::Config { channels: config.channels, sample_rate: config.sample_rate },
+            crate::BinMessages::BinPing(nonce) => BrowserMessage::Ping { nonce: *nonce },
+            crate::BinMessages::BinPong(nonce) => BrowserMessage::Pong { nonce: *nonce },
+            _ => return None
+        })
+    }
+
+    fn into_bin(self) -> crate::BinMessages {
+        match self {
+            BrowserMessage::Identify { id, name } => crate::BinMessages::BinIdentify(BinClientIdentity { id, name }),
+            BrowserMessage::Join { room } => crate::BinMessages::BinJoin(room),
+            BrowserMessage::Listen => crate::BinMessages::BinListen,
+            BrowserMessage::Subscribe { stream } => crate::BinMessages::BinSubscribe(stream),
+            BrowserMessage::Config { channels, sample_rate } => crate::BinMessages::BinConfig(crate::BinStreamConfig { channels, sample_rate, buffer_size: 4096 }),
+            BrowserMessage::Ping { nonce } => crate::BinMessages::BinPing(nonce),
+            BrowserMessage::Pong { nonce } => crate::BinMessages::BinPong(nonce),
+        }
+    }
+}
+
+pub fn encode_samples(samples: &[f32]) -> Vec<u8> {
+    samples.iter().flat_map(|sample| sample.to_le_bytes()).collect()
+}
+
+pub fn decode_samples(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4).map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]])).collect()
+}
+
+pub struct BrowserTransport {
+    websocket: WebSocketStream<TcpStream>,
+}
+
+impl BrowserTransport {
+    pub fn new(websocket: WebSocketStream<TcpStream>) -> Self {
+        BrowserTransport { websocket }
+    }
+
+    // the next message as bincode, like every other transport hands them on
+    pub async fn recv(&mut self) -> Option<Result<Vec<u8>, TransportError>> {
+        loop {
+            let message = match self.websocket.next().await? {
+                Ok(Message::Text(text)) => match serde_json::from_str::<BrowserMessage>(&text) {
+                    Ok(message) => message.into_bin(),
+                    Err(e) => return Some(Err(format!("unreadable browser message {} due to {}", text, e).into()))
+                },
+                Ok(Message::Binary(bytes)) => crate::BinMessages::BinData(decode_samples(&bytes)),
+                Ok(Message::Close(_)) => return None,
+                // websocket level pings are answered by tungstenite
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e.into()))
+            };
+
+            return Some(bincode::serialize(&message).map_err(|e| e.into()));
+        }
+    }
+
+    // messages a browser has no use for are dropped
+    pub async fn send(&mut self, message: &crate::BinMessages) -> Result<(), TransportError> {
+        let frame = match message {
+            crate::BinMessages::BinData(samples) => Message::binary(encode_samples(samples)),
+            message => match BrowserMessage::from_bin(message) {
+                Some(message) => Message::text(serde_json::to_string(&message)?),
+                None => return Ok(())
+            }
+        };
+
+        self.websocket.send(frame).await?;
+        Ok(())
+    }
+
+    pub async fn send_block(&mut self, block: &Block) -> Result<(), TransportError> {
+        self.websocket.send(Message::binary(encode_samples(&block.samples))).await?;
+        Ok(())
+    }
+
+    pub async fn close(&mut self, reason: Option<&str>) {
+        let frame = reason.map(|reason| CloseFrame { code: CloseCode::Away, reason: reason.to_owned().into() });
+        let _ = self.websocket.close(frame).await;
+    }
+}
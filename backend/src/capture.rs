@@ -0,0 +1,109 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::SyncSender;
+use std::time::Duration;
+
+use cpal::StreamConfig;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use tracing::{debug, info};
+
+use crate::broadcast::{Broadcast, Subscription};
+use crate::devices::DeviceId;
+use crate::stats::StreamCounters;
+
+// the server's input device, played back by clients that connect to listen
+//
+// the device is opened when the first listener subscribes and closed when the last one leaves,
+// everything it captures is published as the input stream
+
+struct Capture {
+    config: StreamConfig,
+    stream: cpal::Stream,
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = self.stream.pause();
+        debug!("closing input device");
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Captures {
+    open: Arc<Mutex<Option<Capture>>>,
+    broadcast: Arc<Broadcast>,
+    // `None` captures from the default input device
+    device: Arc<Mutex<Option<DeviceId>>>,
+}
+
+unsafe impl Send for Captures {}
+unsafe impl Sync for Captures {}
+
+impl Captures {
+    pub fn subscribe(&self, stats: Arc<StreamCounters>, device_errors: SyncSender<cpal::StreamError>) -> Result<Subscription, Box<dyn Error>> {
+        self.open()?;
+
+        let mut subscription = self.broadcast.subscribe(stats, device_errors)?;
+        subscription.captures = Some(self.clone());
+
+        Ok(subscription)
+    }
+
+    pub fn listeners(&self) -> usize {
+        self.broadcast.listeners()
+    }
+
+    // capture from `device` from now on, current listeners are moved over with a new config
+    pub fn set_device(&self, device: Option<DeviceId>) -> Result<(), Box<dyn Error>> {
+        *self.device.lock().unwrap() = device;
+
+        if self.open.lock().unwrap().take().is_none() {
+            return Ok(());
+        }
+
+        self.open()?;
+
+        Ok(())
+    }
+
+    // a listener left, the device closes with the last one
+    pub(crate) fn release(&self) {
+        // closing waits on the audio thread, which may be waiting on the listeners
+        if self.broadcast.listeners() == 0 && self.open.lock().unwrap().take().is_some() {
+            info!("closed input device, nobody is listening");
+        }
+    }
+
+    // the running capture's config, opening the device first if nobody was listening
+    fn open(&self) -> Result<StreamConfig, Box<dyn Error>> {
+        let mut open = self.open.lock().unwrap();
+        if let Some(capture) = open.as_ref() {
+            return Ok(capture.config.clone());
+        }
+
+        let device = match self.device.lock().unwrap().as_ref() {
+            Some(id) => crate::devices::find_input_device(id)?,
+            None => cpal::default_host().default_input_device().ok_or("could not find default input device!")?
+        };
+        let config: StreamConfig = device.default_input_config()?.into();
+        // listeners already subscribed hear about the new device before its first block
+        self.broadcast.set_config(&config);
+
+        let stream_broadcast = Arc::clone(&self.broadcast);
+        let error_broadcast = Arc::clone(&self.broadcast);
+
+        let stream = device.build_input_stream(
+            &config,
+            move |data: &[f32], _| stream_broadcast.publish(data),
+            // logged by each listener's connection task, never from the audio thread
+            move |e| error_broadcast.device_error(&e),
+            Some(Duration::from_secs(5)),
+        )?;
+        stream.play()?;
+
+        info!(device = %device.name().unwrap_or_default(), channels = config.channels, sample_rate = config.sample_rate.0, "opened input device");
+        *open = Some(Capture { config: config.clone(), stream });
+
+        Ok(config)
+    }
+}
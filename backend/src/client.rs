@@ -1,24 +1,30 @@
-use std::fmt::Display;
 use std::time::Duration;
-use std::{convert::TryInto, slice::from_mut};
 
 use std::sync::{Arc};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use tokio::sync::Mutex;
 use cpal::StreamConfig;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use bytes::Bytes;
+use cpal::traits::{DeviceTrait, StreamTrait};
 use futures::stream::{SplitSink, SplitStream};
-use serde::Serialize;
 use tokio::net::TcpStream;
-use tokio_tungstenite::tungstenite::handshake::client::Response;
 use tokio_tungstenite::{connect_async, WebSocketStream, MaybeTlsStream};
-use tokio_tungstenite::{accept_async, tungstenite::Message};
-use futures::sink::Send;
+use tokio_tungstenite::tungstenite::Message;
 
 
 use futures::{StreamExt, SinkExt};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::aec::{CaptureEcho, EchoConfig, EchoReference};
+use crate::fec::{FecConfig, Received};
+use crate::heartbeat::{Heartbeat, HeartbeatConfig};
+use crate::identity::{BinClientIdentity, Instance};
+use crate::mixer::{Mixers, Tap, TapSource};
+use crate::quic::{QuicReceiver, QuicTransport, TransportError, QUIC_SCHEME};
+use crate::routing::RouteOutput;
+use crate::stats::{StreamCounters, StreamStats};
 
 
 
@@ -28,52 +34,722 @@ pub trait Client {
     async fn change_source_device(&mut self, new_device: cpal::Device) -> Result<(), Box<dyn std::error::Error>>;
     async fn is_alive(&mut self) -> bool;
     async fn name(&mut self) -> String;
+    async fn stats(&mut self) -> StreamStats;
     
 }
 
 pub struct Connection {
     url: String,
-    writer: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
-    reader: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    transport: ClientTransport,
+    channels: usize,
+    // cleared once the server stops answering or the transport fails
+    is_alive: Arc<AtomicBool>,
+    stats: Arc<StreamCounters>,
+    device_error_sender: std::sync::mpsc::SyncSender<cpal::StreamError>,
+    device_errors: std::sync::mpsc::Receiver<cpal::StreamError>,
+    // server url and input device, the connection's tasks log inside it
+    span: tracing::Span,
+}
+
+impl Connection {
+    // send and count it
+    pub async fn send(&mut self, message: &crate::BinMessages) -> Result<(), TransportError> {
+        let frames = match message {
+            crate::BinMessages::BinData(data) => (data.len() / self.channels.max(1)) as u64,
+            _ => 0
+        };
+
+        self.transport.send(message).await?;
+        self.stats.sent(bincode::serialized_size(message).unwrap_or(0), frames);
+
+        Ok(())
+    }
+
+    fn stats(&self, rtt: Option<Duration>) -> StreamStats {
+        let mut stats = self.stats.snapshot();
+        stats.rtt_ms = rtt.map(|rtt| rtt.as_secs_f32() * 1_000.0);
+        stats.fec = match &self.transport {
+            ClientTransport::Quic(quic) => Some(quic.fec_stats()),
+            _ => None
+        };
+
+        stats
+    }
+}
+
+// the wire a client side connection sends frames on, picked from the url scheme in `connect`
+pub enum ClientTransport {
+    WebSocket {
+        writer: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+    },
+    Quic(QuicTransport),
+}
+
+// what the server sends back, read separately so it never waits on the audio callback
+pub enum ClientReceiver {
+    WebSocket(SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>),
+    Quic(QuicReceiver),
+}
+
+impl ClientReceiver {
+    pub async fn recv(&mut self) -> Option<Result<Received, TransportError>> {
+        match self {
+            ClientReceiver::WebSocket(reader) => match reader.next().await? {
+                // the server said why it let us go, e.g. a refused relay
+                Ok(Message::Close(Some(frame))) => Some(Err(format!("closed by server, {}", frame.reason).into())),
+                Ok(message) => Some(Ok(Received::Message(message.into_data()))),
+                Err(e) => Some(Err(e.into()))
+            },
+            ClientReceiver::Quic(receiver) => receiver.recv().await
+        }
+    }
+}
+
+impl ClientTransport {
+    pub async fn open(url: &str) -> Result<(ClientTransport, ClientReceiver), Box<dyn std::error::Error>> {
+        if url.starts_with(QUIC_SCHEME) {
+            let mut quic = QuicTransport::connect(url).await?;
+            let receiver = quic.take_receiver().ok_or("quic receiver already taken!")?;
+
+            return Ok((ClientTransport::Quic(quic), ClientReceiver::Quic(receiver)));
+        }
+
+        let (socket, _) = connect_async(url).await?;
+        let (writer, reader) = socket.split();
+
+        Ok((ClientTransport::WebSocket { writer }, ClientReceiver::WebSocket(reader)))
+    }
+
+    pub async fn send(&mut self, message: &crate::BinMessages) -> Result<(), TransportError> {
+        match self {
+            ClientTransport::WebSocket { writer } => {
+                writer.send(Message::binary(bincode::serialize(message)?)).await?;
+                Ok(())
+            },
+            ClientTransport::Quic(quic) => quic.send(message).await
+        }
+    }
+
+    // an already encoded block, as relays pass them on
+    pub async fn send_block(&mut self, block: &crate::broadcast::Block) -> Result<(), TransportError> {
+        match self {
+            ClientTransport::WebSocket { writer } => {
+                writer.send(Message::binary(block.encoded.clone())).await?;
+                Ok(())
+            },
+            ClientTransport::Quic(quic) => quic.send_data(&block.samples)
+        }
+    }
+
+    pub async fn close(&mut self) -> Result<(), TransportError> {
+        match self {
+            ClientTransport::WebSocket { writer } => writer.close().await?,
+            ClientTransport::Quic(quic) => quic.close_gracefully("client closed").await
+        };
+
+        Ok(())
+    }
 }
 pub struct Batch<T> {
     pub repr: Vec<T>
 }
 
 pub struct Metal2RemoteStream {
-    stream: cpal::Stream,
+    // held so the capture keeps running
+    _stream: cpal::Stream,
     liveness: Arc<AtomicBool>,
 }
 
+// where the main capture stream's audio goes, moved into its callback
+struct Capture {
+    connection: Arc<Mutex<Connection>>,
+    // cleared once the connection can't take any more audio
+    liveness: Arc<AtomicBool>,
+    device_errors: std::sync::mpsc::SyncSender<cpal::StreamError>,
+    stats: Arc<StreamCounters>,
+    echo: Option<CaptureEcho>,
+    // swapped whole on add and remove, the callback only loads it
+    links: Arc<ArcSwap<Vec<Arc<Link>>>>,
+}
+
+// another capture device sent over the same connection, see `sources`
+struct ExtraSource {
+    id: u16,
+    device: cpal::Device,
+    // running while connected
+    stream: Option<cpal::Stream>,
+}
+
+unsafe impl std::marker::Send for ExtraSource {}
+
 pub struct Metal2RemoteClient {
     connection: Option<Arc<Mutex<Connection>>>,
     stream: Option<Metal2RemoteStream>,
-    device: cpal::Device
+    device: cpal::Device,
+    // the server's input played back over the same connection, for talkback
+    playback: Option<Arc<std::sync::Mutex<Playback>>>,
+    // what the playback plays, cancelled out of the microphone while `echo_cancellation` is set
+    echo_reference: EchoReference,
+    echo_cancellation: Arc<AtomicBool>,
+    echo_config: EchoConfig,
+    // the default room unless set
+    room: Option<String>,
+    fec: FecConfig,
+    heartbeat_config: HeartbeatConfig,
+    heartbeat: Option<Arc<std::sync::Mutex<Heartbeat>>>,
+    identity: BinClientIdentity,
+    // keeps the instance number in `identity` ours while this client lives
+    _instance: Instance,
+    // more servers the same capture goes to, each connected on its own
+    links: Arc<ArcSwap<Vec<Arc<Link>>>>,
+    sources: Vec<ExtraSource>,
+    next_source: u16,
 }
 
 unsafe impl std::marker::Send for Metal2RemoteClient {}
 
 impl Metal2RemoteClient {
     pub fn new(device: cpal::Device) -> Self {
+        let (identity, instance) = BinClientIdentity::claim("sender");
         Self {
             connection: None,
             stream: None,
             device,
+            playback: None,
+            echo_reference: EchoReference::default(),
+            echo_cancellation: Arc::new(AtomicBool::new(false)),
+            echo_config: EchoConfig::default(),
+            room: None,
+            fec: FecConfig::default(),
+            heartbeat_config: HeartbeatConfig::default(),
+            heartbeat: None,
+            identity,
+            _instance: instance,
+            links: Arc::new(ArcSwap::from_pointee(vec![])),
+            sources: vec![],
+            // 0 is the main stream
+            next_source: 1,
+        }
+    }
+
+    pub fn identity(&self) -> &BinClientIdentity {
+        &self.identity
+    }
+
+    // shown by the server instead of the peer address, takes effect on the next `connect`
+    pub fn set_name(&mut self, name: &str) {
+        self.identity.name = name.to_owned();
+    }
+
+    // takes effect on the next `connect`
+    pub fn set_heartbeat_config(&mut self, config: HeartbeatConfig) {
+        self.heartbeat_config = config;
+    }
+
+    // the room to join on the server, takes effect on the next `connect`
+    pub fn set_room(&mut self, room: Option<&str>) {
+        self.room = room.map(str::to_owned);
+    }
+
+    // round trip to the server, measured from the last answered ping
+    pub fn rtt(&self) -> Option<Duration> {
+        self.heartbeat.as_ref()?.lock().unwrap().rtt()
+    }
+
+    // play the server's input on `device` over the same connection, making it duplex. switching
+    // devices applies right away, turning it on or off takes effect on the next `connect`
+    pub fn set_return_device(&mut self, device: Option<cpal::Device>) -> Result<(), Box<dyn std::error::Error>> {
+        match (device, &self.playback) {
+            (Some(device), Some(playback)) => {
+                let mut playback = playback.lock().unwrap();
+                playback.device = device;
+                playback.start()?;
+            },
+            (Some(device), None) => {
+                let mut playback = Playback::new(device);
+                playback.echo_reference = Some(self.echo_reference.clone());
+                self.playback = Some(Arc::new(std::sync::Mutex::new(playback)));
+            },
+            (None, _) => self.playback = None
+        }
+
+        Ok(())
+    }
+
+    // cancel the echo of the server's audio out of the microphone, only duplex connections have
+    // any. applies right away
+    pub fn set_echo_cancellation(&mut self, enabled: bool) {
+        self.echo_cancellation.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn echo_cancellation(&self) -> bool {
+        self.echo_cancellation.load(Ordering::Relaxed)
+    }
+
+    // takes effect on the next `connect` or input device change
+    pub fn set_echo_config(&mut self, config: EchoConfig) {
+        self.echo_config = config;
+    }
+
+    // the canceller for a capture at `config`, when there is a playback to cancel
+    fn capture_echo(&self, config: &cpal::SupportedStreamConfig) -> Option<CaptureEcho> {
+        self.playback.as_ref()?;
+
+        Some(CaptureEcho::new(self.echo_config, config.channels(), config.sample_rate().0, self.echo_reference.clone(), Arc::clone(&self.echo_cancellation)))
+    }
+
+    // also send the capture to `url`, with its own settings. the link reconnects by itself
+    // whenever that server goes away, and carries audio while the client is connected
+    pub fn add_server(&mut self, url: &str, config: LinkConfig) -> Result<(), Box<dyn std::error::Error>> {
+        let mut links = Vec::clone(&self.links.load());
+        if links.iter().any(|link| link.url == url) {
+            return Err(format!("already sending to {}", url).into());
+        }
+
+        let stream_config = self.device.default_input_config()?.config();
+        links.push(Arc::new(Link::new(url, config, stream_config, self.identity.clone(), self.room.clone())));
+        self.links.store(Arc::new(links));
+
+        Ok(())
+    }
+
+    pub fn remove_server(&mut self, url: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut links = Vec::clone(&self.links.load());
+        let position = links.iter().position(|link| link.url == url).ok_or(format!("not sending to {}", url))?;
+        links.remove(position);
+        self.links.store(Arc::new(links));
+
+        Ok(())
+    }
+
+    // per server added with `add_server`
+    pub fn link_stats(&self) -> Vec<(String, StreamStats)> {
+        self.links.load().iter().map(|link| (link.url.clone(), link.stats())).collect()
+    }
+
+    // capture `device` too, sent over the same connection as a source of its own. starts right
+    // away when connected, otherwise on `connect`. the id names it to `remove_source`
+    pub async fn add_source(&mut self, device: cpal::Device) -> Result<u16, Box<dyn std::error::Error>> {
+        let mut source = ExtraSource { id: self.next_source, device, stream: None };
+        self.next_source += 1;
+
+        if let Some(connection) = &self.connection {
+            ClientHelper::start_source(connection, &mut source).await?;
+        }
+
+        let id = source.id;
+        self.sources.push(source);
+
+        Ok(id)
+    }
+
+    pub async fn remove_source(&mut self, id: u16) -> Result<(), Box<dyn std::error::Error>> {
+        let position = self.sources.iter().position(|source| source.id == id).ok_or(format!("no source {}", id))?;
+        self.sources.remove(position);
+
+        if let Some(connection) = &self.connection {
+            connection.lock().await.send(&crate::BinMessages::BinSourceClose(id)).await.map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    // switch a source to another device, the server is told its new config
+    pub async fn change_source_device_of(&mut self, id: u16, device: cpal::Device) -> Result<(), Box<dyn std::error::Error>> {
+        let source = self.sources.iter_mut().find(|source| source.id == id).ok_or(format!("no source {}", id))?;
+        source.stream = None;
+        source.device = device;
+
+        if let Some(connection) = &self.connection {
+            ClientHelper::start_source(connection, source).await?;
+        }
+
+        Ok(())
+    }
+
+    // id and device name of every extra source
+    pub fn sources(&self) -> Vec<(u16, String)> {
+        self.sources.iter().map(|source| (source.id, source.device.name().unwrap_or_default())).collect()
+    }
+
+    // parity protection for lossy transports, only QUIC connections are affected
+    pub async fn set_fec_config(&mut self, config: FecConfig) {
+        self.fec = config;
+
+        if let Some(connection) = &self.connection {
+            if let ClientTransport::Quic(quic) = &connection.lock().await.transport {
+                quic.set_fec_config(config);
+            }
         }
     }
 }
 
 impl Drop for Metal2RemoteClient {
     fn drop(&mut self) {
-        match &self.connection {
-            Some(connection) => {
-                let spawn_connection = Arc::clone(connection);
-                tokio::task::spawn(async move {
-                    let mut ul_connection = spawn_connection.lock().await;
-                    ul_connection.writer.close().await.expect("could not close websocket!");
-                });
+        if let Some(connection) = &self.connection {
+            let spawn_connection = Arc::clone(connection);
+            tokio::task::spawn(async move {
+                let mut ul_connection = spawn_connection.lock().await;
+                // the server drops the connection on its own once it stops hearing from us
+                if let Err(e) = ul_connection.transport.close().await {
+                    warn!("could not close connection due to {}", e);
+                }
+            });
+        }
+    }
+}
+
+// where a listening client's audio goes, shared with its receiver task
+struct Playback {
+    device: cpal::Device,
+    // the server's capture config, known once it has been received
+    config: Option<StreamConfig>,
+    tap: Option<Arc<Tap>>,
+    mixers: Mixers,
+    stats: Arc<StreamCounters>,
+    concealed_frames: Arc<AtomicU64>,
+    device_errors: std::sync::mpsc::SyncSender<cpal::StreamError>,
+    echo_reference: Option<EchoReference>,
+}
+
+unsafe impl std::marker::Send for Playback {}
+
+impl Playback {
+    fn new(device: cpal::Device) -> Self {
+        let (device_errors, _) = crate::logging::device_error_channel();
+
+        Playback {
+            device,
+            config: None,
+            tap: None,
+            mixers: Mixers::default(),
+            stats: Arc::new(StreamCounters::default()),
+            concealed_frames: Arc::new(AtomicU64::new(0)),
+            device_errors,
+            echo_reference: None,
+        }
+    }
+
+    // a fresh connection starts from nothing, the server sends its config first
+    fn reset(&mut self, stats: Arc<StreamCounters>, device_errors: std::sync::mpsc::SyncSender<cpal::StreamError>) {
+        if let Some(tap) = self.tap.take() {
+            self.mixers.detach(&tap);
+        }
+
+        self.config = None;
+        self.stats = stats;
+        self.device_errors = device_errors;
+    }
+
+    fn stats(&self) -> StreamStats {
+        let mut stats = self.stats.snapshot();
+        stats.concealed_frames = self.concealed_frames.load(Ordering::Relaxed);
+
+        stats
+    }
+
+    // (re)open the output for the current device and config
+    fn start(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(tap) = self.tap.take() {
+            self.mixers.detach(&tap);
+        }
+
+        let config = match &self.config {
+            Some(config) => config.clone(),
+            None => return Ok(())
+        };
+
+        let source = TapSource {
+            config,
+            primary: true,
+            stats: Arc::clone(&self.stats),
+            concealed_frames: Arc::clone(&self.concealed_frames),
+            fade_out: Arc::new(AtomicBool::new(false)),
+            device_errors: self.device_errors.clone(),
+            echo_reference: self.echo_reference.clone(),
+        };
+        // one device at a time in a mixer of its own, nothing to tell it apart from
+        self.tap = Some(self.mixers.attach_device(None, &self.device, &RouteOutput::default(), &source)?);
+
+        Ok(())
+    }
+
+    // audio the transport lost on the way, concealed where it would have played
+    fn conceal(&self, samples: usize) {
+        if let Some(tap) = &self.tap {
+            tap.push_missing(samples);
+        }
+    }
+
+    // queue audio from the server, `bytes` is what it took on the wire
+    fn play(&mut self, data: &[f32], bytes: u64) {
+        let config = match (&self.tap, &self.config) {
+            (Some(tap), Some(config)) => {
+                tap.push(data);
+                config
             },
-            None => {}
+            _ => {
+                self.stats.received(bytes, 0);
+                return;
+            }
+        };
+
+        let frames = data.len() / config.channels.max(1) as usize;
+        self.stats.received(bytes, frames as u64);
+        self.stats.arrival(Duration::from_secs_f32(frames as f32 / config.sample_rate.0.max(1) as f32));
+    }
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        if let Some(tap) = self.tap.take() {
+            self.mixers.detach(&tap);
+        }
+    }
+}
+
+// settings of an extra server the capture is also sent to, see `Metal2RemoteClient::add_server`
+#[derive(Clone, Copy, Debug)]
+pub struct LinkConfig {
+    pub fec: FecConfig,
+    pub heartbeat: HeartbeatConfig,
+    // audio allowed to queue for a slow server before new blocks are dropped
+    pub max_latency: Duration,
+    // wait between attempts while the server is away
+    pub reconnect_interval: Duration,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            fec: FecConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            max_latency: Duration::from_millis(200),
+            reconnect_interval: Duration::from_secs(2),
+        }
+    }
+}
+
+// audio a capture callback may queue for its connection before new blocks are dropped
+static CAPTURE_QUEUE: Duration = Duration::from_millis(200);
+
+// how often a link's task sends what the input callback queued
+static LINK_SEND_INTERVAL: Duration = Duration::from_millis(5);
+
+type HeartbeatSlot = Arc<std::sync::Mutex<Option<Arc<std::sync::Mutex<Heartbeat>>>>>;
+
+// one extra server, fed from the input callback and (re)connected by its own task. dropping it
+// closes the connection
+struct Link {
+    url: String,
+    // written by the input callback, which only ever tries the lock. sized for `max_latency`, a
+    // slow server drops new blocks instead of growing it
+    samples: std::sync::Mutex<HeapProducer<f32>>,
+    // a new format and the queue sized for it, the task sends what's left of the old one first
+    configs: tokio::sync::mpsc::UnboundedSender<(StreamConfig, HeapConsumer<f32>)>,
+    max_latency: Duration,
+    stats: Arc<StreamCounters>,
+    heartbeat: HeartbeatSlot,
+}
+
+// what a link's task owns, see `Link::run`
+struct LinkTask {
+    url: String,
+    config: LinkConfig,
+    stream_config: StreamConfig,
+    identity: BinClientIdentity,
+    room: Option<String>,
+    configs: tokio::sync::mpsc::UnboundedReceiver<(StreamConfig, HeapConsumer<f32>)>,
+    samples: HeapConsumer<f32>,
+    stats: Arc<StreamCounters>,
+    heartbeat: HeartbeatSlot,
+}
+
+impl Link {
+    fn new(url: &str, config: LinkConfig, stream_config: StreamConfig, identity: BinClientIdentity, room: Option<String>) -> Self {
+        let (sender, configs) = tokio::sync::mpsc::unbounded_channel();
+        let (producer, samples) = Link::queue(config.max_latency, &stream_config);
+        let stats = Arc::new(StreamCounters::default());
+        let heartbeat = Arc::new(std::sync::Mutex::new(None));
+
+        let span = info_span!("link", server = %url, client = %identity.name);
+        let task = LinkTask {
+            url: url.to_owned(),
+            config,
+            stream_config,
+            identity,
+            room,
+            configs,
+            samples,
+            stats: Arc::clone(&stats),
+            heartbeat: Arc::clone(&heartbeat),
+        };
+        tokio::spawn(task.run().instrument(span));
+
+        Link {
+            url: url.to_owned(),
+            samples: std::sync::Mutex::new(producer),
+            configs: sender,
+            max_latency: config.max_latency,
+            stats,
+            heartbeat,
+        }
+    }
+
+    fn queue(max_latency: Duration, config: &StreamConfig) -> (HeapProducer<f32>, HeapConsumer<f32>) {
+        let samples = max_latency.as_secs_f32() * config.sample_rate.0 as f32 * config.channels as f32;
+        HeapRb::new((samples as usize).max(1)).split()
+    }
+
+    // the input device changed, sent ahead of its first block
+    fn set_config(&self, config: &StreamConfig) {
+        let (producer, consumer) = Link::queue(self.max_latency, config);
+        *self.samples.lock().unwrap() = producer;
+        let _ = self.configs.send((config.clone(), consumer));
+    }
+
+    // called from the input callback, never waits on the server or allocates
+    fn feed(&self, data: &[f32], channels: usize) {
+        let mut samples = match self.samples.try_lock() {
+            Ok(samples) => samples,
+            // the format is being swapped, this block belonged to the old one anyway
+            Err(_) => return
+        };
+
+        if samples.free_len() < data.len() {
+            self.stats.overrun();
+            return;
+        }
+
+        samples.push_slice(data);
+        self.stats.meter(data, channels);
+    }
+
+    fn stats(&self) -> StreamStats {
+        let mut stats = self.stats.snapshot();
+        stats.rtt_ms = self.heartbeat.lock().unwrap().as_ref()
+            .and_then(|heartbeat| heartbeat.lock().unwrap().rtt())
+            .map(|rtt| rtt.as_secs_f32() * 1_000.0);
+
+        stats
+    }
+
+    // everything queued so far, in blocks of at most `buffer`
+    async fn flush(connection: &Arc<Mutex<Connection>>, samples: &mut HeapConsumer<f32>, buffer: &mut Vec<f32>) -> Result<(), String> {
+        loop {
+            buffer.resize(samples.capacity(), 0.0);
+            let count = samples.pop_slice(buffer);
+            if count == 0 {
+                return Ok(());
+            }
+
+            let message = crate::BinMessages::BinData(buffer[..count].to_vec());
+            connection.lock().await.send(&message).await.map_err(|e| e.to_string())?;
+        }
+    }
+
+    async fn connect(url: &str, config: &LinkConfig, stream_config: &StreamConfig, identity: &BinClientIdentity, room: &Option<String>, stats: &Arc<StreamCounters>) -> Result<(Arc<Mutex<Connection>>, Arc<std::sync::Mutex<Heartbeat>>, Arc<AtomicBool>), String> {
+        let (mut transport, receiver) = ClientTransport::open(url).await.map_err(|e| e.to_string())?;
+        if let ClientTransport::Quic(quic) = &transport {
+            quic.set_fec_config(config.fec);
+        }
+
+        transport.send(&crate::BinMessages::BinIdentify(identity.clone())).await.map_err(|e| e.to_string())?;
+        if let Some(room) = room {
+            transport.send(&crate::BinMessages::BinJoin(room.clone())).await.map_err(|e| e.to_string())?;
+        }
+        transport.send(&crate::BinMessages::BinConfig(crate::BinStreamConfig::from(stream_config))).await.map_err(|e| e.to_string())?;
+
+        let is_alive = Arc::new(AtomicBool::new(true));
+        let (device_error_sender, device_errors) = crate::logging::device_error_channel();
+        let connection = Arc::new(Mutex::new(Connection {
+            url: url.to_owned(),
+            transport,
+            channels: stream_config.channels as usize,
+            is_alive: Arc::clone(&is_alive),
+            stats: Arc::clone(stats),
+            device_error_sender,
+            device_errors,
+            span: tracing::Span::current(),
+        }));
+
+        let heartbeat = Arc::new(std::sync::Mutex::new(Heartbeat::new(config.heartbeat)));
+        ClientHelper::spawn_receiver(receiver, Arc::clone(&connection), Arc::clone(&heartbeat), Arc::clone(&is_alive), None, tracing::Span::current());
+        ClientHelper::spawn_heartbeat(Arc::clone(&connection), Arc::clone(&heartbeat), Arc::clone(&is_alive), tracing::Span::current());
+
+        Ok((connection, heartbeat, is_alive))
+    }
+}
+
+impl LinkTask {
+    // connect, send until the server goes away, wait and connect again. ends once the link is
+    // dropped
+    async fn run(self) {
+        let LinkTask { url, config, mut stream_config, identity, room, mut configs, mut samples, stats, heartbeat: heartbeat_slot } = self;
+        let mut buffer = vec![];
+
+        loop {
+            match Link::connect(&url, &config, &stream_config, &identity, &room, &stats).await {
+                Ok((connection, heartbeat, is_alive)) => {
+                    info!("connected");
+                    *heartbeat_slot.lock().unwrap() = Some(heartbeat);
+                    let mut send = tokio::time::interval(LINK_SEND_INTERVAL);
+
+                    loop {
+                        let sent = tokio::select! {
+                            change = configs.recv() => match change {
+                                Some((config, consumer)) => {
+                                    let flushed = Link::flush(&connection, &mut samples, &mut buffer).await;
+                                    samples = consumer;
+                                    connection.lock().await.channels = config.channels as usize;
+                                    stream_config = config;
+
+                                    match flushed {
+                                        Ok(_) => connection.lock().await.send(&crate::BinMessages::BinConfig(crate::BinStreamConfig::from(&stream_config))).await.map_err(|e| e.to_string()),
+                                        Err(e) => Err(e)
+                                    }
+                                },
+                                None => {
+                                    is_alive.store(false, Ordering::Relaxed);
+                                    let _ = connection.lock().await.transport.close().await;
+                                    info!("removed");
+                                    return;
+                                }
+                            },
+                            _ = send.tick() => Link::flush(&connection, &mut samples, &mut buffer).await
+                        };
+
+                        if let Err(e) = sent {
+                            warn!("could not send to server due to {}", e);
+                            break;
+                        }
+                        if !is_alive.load(Ordering::Relaxed) {
+                            break;
+                        }
+                    }
+
+                    is_alive.store(false, Ordering::Relaxed);
+                    let _ = connection.lock().await.transport.close().await;
+                    *heartbeat_slot.lock().unwrap() = None;
+                    warn!("lost server, reconnecting in {:?}", config.reconnect_interval);
+                },
+                Err(e) => warn!("could not connect due to {}, retrying in {:?}", e, config.reconnect_interval)
+            }
+
+            tokio::time::sleep(config.reconnect_interval).await;
+
+            // what piled up meanwhile is stale, only the format still matters
+            loop {
+                match configs.try_recv() {
+                    Ok((config, consumer)) => {
+                        stream_config = config;
+                        samples = consumer;
+                    },
+                    Err(tokio::sync::mpsc::error::TryRecvError::Empty) => break,
+                    Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => return
+                }
+            }
+            samples.clear();
         }
     }
 }
@@ -81,27 +757,225 @@ impl Drop for Metal2RemoteClient {
 struct ClientHelper {}
 
 impl ClientHelper {
-    fn build_input_stream(device: &cpal::Device, config: cpal::SupportedStreamConfig, input_stream_connection: Arc<Mutex<Connection>>, stream_liveness: Arc<AtomicBool>) -> Result<cpal::Stream, Box<dyn std::error::Error>> {
+    // answer the server's pings and keep track of when it was last heard from, listening clients
+    // also play the audio that comes with them
+    fn spawn_receiver(mut receiver: ClientReceiver, connection: Arc<Mutex<Connection>>, heartbeat: Arc<std::sync::Mutex<Heartbeat>>, is_alive: Arc<AtomicBool>, playback: Option<Arc<std::sync::Mutex<Playback>>>, span: tracing::Span) {
+        tokio::spawn(async move {
+            let stats = Arc::clone(&connection.lock().await.stats);
+
+            while let Some(message) = receiver.recv().await {
+                let message = match message {
+                    Ok(Received::Message(message)) => message,
+                    // lost on the way, concealed so the audio after it stays in time
+                    Ok(Received::Missing(samples)) => {
+                        if let Some(playback) = &playback {
+                            playback.lock().unwrap().conceal(samples);
+                        }
+                        continue;
+                    },
+                    Err(e) => {
+                        warn!("error reading from server due to {}", e);
+                        break;
+                    }
+                };
+
+                let deserialized = match bincode::deserialize(&message) {
+                    Ok(deserialized) => deserialized,
+                    Err(_) => {
+                        stats.received(message.len() as u64, 0);
+                        continue;
+                    }
+                };
+
+                match (&deserialized, &playback) {
+                    // counted by the playback, apart from the audio going out on a duplex connection
+                    (crate::BinMessages::BinData(data), Some(playback)) => playback.lock().unwrap().play(data, message.len() as u64),
+                    (crate::BinMessages::BinConfig(config), Some(playback)) => {
+                        stats.received(message.len() as u64, 0);
+                        info!(channels = config.channels, sample_rate = config.sample_rate, "received config");
+
+                        let mut playback = playback.lock().unwrap();
+                        playback.config = Some(StreamConfig {
+                            channels: config.channels,
+                            sample_rate: cpal::SampleRate(config.sample_rate),
+                            buffer_size: cpal::BufferSize::Default,
+                        });
+                        if let Err(e) = playback.start() {
+                            error!("could not start playback due to {}", e);
+                        }
+                    },
+                    _ => stats.received(message.len() as u64, 0)
+                };
+
+                let reply = heartbeat.lock().unwrap().on_message(&deserialized);
+                if let Some(reply) = reply {
+                    if let Err(e) = connection.lock().await.send(&reply).await {
+                        warn!("could not answer server ping due to {}", e);
+                        break;
+                    }
+                }
+            }
+
+            is_alive.store(false, Ordering::Relaxed);
+        }.instrument(span));
+    }
+
+    // ping the server on an interval and give up on it once it stays silent past the timeout
+    fn spawn_heartbeat(connection: Arc<Mutex<Connection>>, heartbeat: Arc<std::sync::Mutex<Heartbeat>>, is_alive: Arc<AtomicBool>, span: tracing::Span) {
+        tokio::spawn(async move {
+            let interval = heartbeat.lock().unwrap().config().interval;
+
+            loop {
+                tokio::time::sleep(interval).await;
+
+                if !is_alive.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                let (expired, ping) = {
+                    let heartbeat = heartbeat.lock().unwrap();
+                    (heartbeat.is_expired(), heartbeat.ping())
+                };
+
+                let mut ul_connection = connection.lock().await;
+
+                while let Ok(e) = ul_connection.device_errors.try_recv() {
+                    error!("audio device error: {}", e);
+                }
+
+                if expired {
+                    warn!("server stopped answering, closing connection");
+                    is_alive.store(false, Ordering::Relaxed);
+                    let _ = ul_connection.transport.close().await;
+                    return;
+                }
+
+                if let Err(e) = ul_connection.send(&ping).await {
+                    warn!("could not ping server due to {}", e);
+                    is_alive.store(false, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }.instrument(span));
+    }
+
+    // tell the server about `source` and start capturing it
+    async fn start_source(connection: &Arc<Mutex<Connection>>, source: &mut ExtraSource) -> Result<(), Box<dyn std::error::Error>> {
+        let config = source.device.default_input_config()?;
+        let label = source.device.name().unwrap_or_else(|_| source.id.to_string());
+
+        let device_errors = {
+            let mut ul_connection = connection.lock().await;
+            ul_connection.send(&crate::BinMessages::BinSourceConfig(crate::sources::BinSource {
+                id: source.id,
+                label,
+                config: crate::BinStreamConfig::from(&config.config()),
+            })).await.map_err(|e| e.to_string())?;
+
+            ul_connection.device_error_sender.clone()
+        };
+
+        source.stream = Some(ClientHelper::build_source_stream(&source.device, config, source.id, Arc::clone(connection), device_errors)?);
+
+        Ok(())
+    }
+
+    fn build_source_stream(device: &cpal::Device, config: cpal::SupportedStreamConfig, id: u16, connection: Arc<Mutex<Connection>>, device_errors: std::sync::mpsc::SyncSender<cpal::StreamError>) -> Result<cpal::Stream, Box<dyn std::error::Error>> {
+        let liveness = Arc::new(AtomicBool::new(true));
+        let (mut samples, wake) = ClientHelper::spawn_sender(connection, Arc::clone(&liveness), &config, move |data| crate::BinMessages::BinSourceData(id, data));
+
+        let stream = device.build_input_stream(
+            &config.into(),
+            move |data: &[f32], _| {
+                if !liveness.load(Ordering::Relaxed) || samples.free_len() < data.len() {
+                    return;
+                }
+
+                samples.push_slice(data);
+                let _ = wake.try_send(());
+            },
+            move |err| {
+                let _ = device_errors.try_send(err);
+            },
+            Some(Duration::from_secs(5))
+        )?;
+
+        stream.play()?;
+        Ok(stream)
+    }
+
+    // the sending half of a capture stream. its callback pushes into the returned queue, sized
+    // up front for `CAPTURE_QUEUE`, and wakes the task, which sends what's there wrapped by
+    // `message`. ends, clearing `liveness`, once the connection is gone or the stream dropped
+    fn spawn_sender(connection: Arc<Mutex<Connection>>, liveness: Arc<AtomicBool>, config: &cpal::SupportedStreamConfig, message: impl Fn(Vec<f32>) -> crate::BinMessages + std::marker::Send + 'static) -> (HeapProducer<f32>, tokio::sync::mpsc::Sender<()>) {
+        let capacity = CAPTURE_QUEUE.as_secs_f32() * config.sample_rate().0 as f32 * config.channels() as f32;
+        let (producer, mut samples) = HeapRb::new((capacity as usize).max(1)).split();
+        let (wake, mut woken) = tokio::sync::mpsc::channel(1);
+
+        tokio::spawn(async move {
+            let mut buffer = vec![0.0; samples.capacity()];
+
+            while woken.recv().await.is_some() {
+                let count = samples.pop_slice(&mut buffer);
+                if count == 0 {
+                    continue;
+                }
+
+                let mut connection = connection.lock().await;
+                if !connection.is_alive.load(Ordering::Relaxed) || connection.send(&message(buffer[..count].to_vec())).await.is_err() {
+                    break;
+                }
+            }
+
+            liveness.store(false, Ordering::Relaxed);
+        });
+
+        (producer, wake)
+    }
+
+    fn build_input_stream(device: &cpal::Device, config: cpal::SupportedStreamConfig, capture: Capture) -> Result<cpal::Stream, Box<dyn std::error::Error>> {
+        let Capture { connection, liveness: stream_liveness, device_errors, stats, mut echo, links } = capture;
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0 as f32;
+        let (mut samples, wake) = ClientHelper::spawn_sender(connection, Arc::clone(&stream_liveness), &config, crate::BinMessages::BinData);
+        let stream_stats = Arc::clone(&stats);
+
         let stream = device
             .build_input_stream(
                 &config.into(),
                 move |data: &[f32], _| {
-                    let runtime = tokio::runtime::Runtime::new().expect("could not create runtime!");
-                    
-                    let mut connection = input_stream_connection.blocking_lock();
-            
-                    let message = bincode::serialize(&crate::BinMessages::BinData(data.to_vec())).expect("could not serialize data");
-
-                    runtime.block_on(async {
-                        match connection.writer.send(Message::binary(message)).await {
-                            Ok(_) => {},
-                            Err(e) => {
-                                stream_liveness.store(false, Ordering::Relaxed);
-                            },
-                        } 
-                    })
+                    let data = match &mut echo {
+                        Some(echo) => echo.process(data),
+                        None => data
+                    };
+
+                    // the other servers keep getting audio whatever happens to this one
+                    for link in links.load().iter() {
+                        link.feed(data, channels);
+                    }
+
+                    // nobody is listening anymore, don't queue for a dead transport
+                    if !stream_liveness.load(Ordering::Relaxed) {
+                        return;
+                    }
+
+                    // capture cadence, a late callback here means a late packet on the other end
+                    stream_stats.arrival(Duration::from_secs_f32((data.len() / channels.max(1)) as f32 / sample_rate));
+
+                    if samples.free_len() < data.len() {
+                        stream_stats.overrun();
+                        return;
+                    }
+
+                    samples.push_slice(data);
+                    stream_stats.meter(data, channels);
+                    let _ = wake.try_send(());
+                },
+                // logged by the heartbeat task, never from the audio thread
+                move |err| {
+                    stats.device_error();
+                    let _ = device_errors.try_send(err);
                 },
-                |err| eprintln!("errored in input stream {}", err),
                 Some(Duration::from_secs(5))
             ).unwrap();
 
@@ -119,32 +993,42 @@ impl Client for Metal2RemoteClient {
 
         //send new config to server
 
-        let bin_config_struct = crate::BinStreamConfig {
-            channels:  config.channels(),
-            sample_rate: config.sample_rate().0,
-            buffer_size: 4096
+        let bin_config = crate::BinMessages::BinConfig(crate::BinStreamConfig::from(&config.config()));
+        let (device_errors, stats) = match &mut self.connection {
+            Some(connection) => {
+                let mut ul_connection = connection.lock().await;
+                ul_connection.channels = config.channels() as usize;
+                ul_connection.send(&bin_config).await.expect("error sending config!");
+
+                ul_connection.span.record("device", self.device.name().unwrap_or_default().as_str());
+                ul_connection.span.in_scope(|| info!("changed input device"));
+
+                (ul_connection.device_error_sender.clone(), Arc::clone(&ul_connection.stats))
+            },
+            None => return Err("not connected".into())
         };
-    
-        let bin_config = bincode::serialize(&crate::BinMessages::BinConfig(bin_config_struct))?;
-        {
-            match &mut self.connection {
-                Some(connection) => {
-                    let mut ul_connection = connection.lock().await;
-                    ul_connection.writer.send(Message::binary(bin_config)).await.expect("error sending config!");
-                },
-                None => {}
-            }            
-        }
         // establish new stream
         
         let input_stream_connection = Arc::clone(self.connection.as_ref().unwrap());
         let liveness: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
         let stream_liveness: Arc<AtomicBool> = Arc::clone(&liveness);
 
-        let stream = ClientHelper::build_input_stream(&self.device, config, input_stream_connection, stream_liveness).expect("could not build input stream!");
+        for link in self.links.load().iter() {
+            link.set_config(&config.config());
+        }
+
+        let capture = Capture {
+            connection: input_stream_connection,
+            liveness: stream_liveness,
+            device_errors,
+            stats,
+            echo: self.capture_echo(&config),
+            links: Arc::clone(&self.links),
+        };
+        let stream = ClientHelper::build_input_stream(&self.device, config, capture).expect("could not build input stream!");
 
         self.stream = Some(Metal2RemoteStream {
-            stream,
+            _stream: stream,
             liveness
         });
 
@@ -157,51 +1041,99 @@ impl Client for Metal2RemoteClient {
     async fn connect(&mut self, url: &str) -> Result<(), Box<dyn std::error::Error>> {
        
         // first establish connection
-        let (socket, resp) = connect_async(url).await?;
-        let (mut writer, reader) = socket.split();
+        let (mut transport, receiver) = ClientTransport::open(url).await?;
+        if let ClientTransport::Quic(quic) = &transport {
+            quic.set_fec_config(self.fec);
+        }
 
         //then send config
         let config: cpal::SupportedStreamConfig = self.device.default_input_config().expect("could not get default input device!");
 
 
-        let bin_config_struct = crate::BinStreamConfig {
-            channels:  config.channels(),
-            sample_rate: config.sample_rate().0,
-            buffer_size: 4096
-        };
-    
-        let bin_config = bincode::serialize(&crate::BinMessages::BinConfig(bin_config_struct))?;
+        let bin_config = crate::BinMessages::BinConfig(crate::BinStreamConfig::from(&config.config()));
     
-        writer.send(Message::binary(bin_config)).await.expect("error sending config!");
+        transport.send(&crate::BinMessages::BinIdentify(self.identity.clone())).await.map_err(|e| e.to_string())?;
+        if let Some(room) = &self.room {
+            transport.send(&crate::BinMessages::BinJoin(room.clone())).await.map_err(|e| e.to_string())?;
+        }
+        transport.send(&bin_config).await.expect("error sending config!");
 
         // remember to store connection in self
-        let mut connection = Connection {
+        let connection_is_alive = Arc::new(AtomicBool::new(true));
+        let (device_error_sender, device_errors) = crate::logging::device_error_channel();
+        let span = info_span!("connection", server = %url, client = %self.identity.name, device = %self.device.name().unwrap_or_default());
+        let stats = Arc::new(StreamCounters::default());
+
+        // duplex, audio coming back is counted apart from audio going out
+        if let Some(playback) = &self.playback {
+            playback.lock().unwrap().reset(Arc::new(StreamCounters::default()), device_error_sender.clone());
+            transport.send(&crate::BinMessages::BinListen).await.map_err(|e| e.to_string())?;
+        }
+        let stream_device_errors = device_error_sender.clone();
+        let stream_stats = Arc::clone(&stats);
+
+        let connection = Connection {
             url: url.to_owned(),
-            writer,
-            reader
+            transport,
+            channels: config.channels() as usize,
+            is_alive: Arc::clone(&connection_is_alive),
+            stats,
+            device_error_sender,
+            device_errors,
+            span: span.clone(),
         };
 
         let self_connection = Arc::new(Mutex::new(connection));
         let input_stream_connection = Arc::clone(&self_connection);
 
+        let heartbeat = Arc::new(std::sync::Mutex::new(Heartbeat::new(self.heartbeat_config)));
+        ClientHelper::spawn_receiver(receiver, Arc::clone(&self_connection), Arc::clone(&heartbeat), Arc::clone(&connection_is_alive), self.playback.clone(), span.clone());
+        ClientHelper::spawn_heartbeat(Arc::clone(&self_connection), Arc::clone(&heartbeat), connection_is_alive, span.clone());
+        span.in_scope(|| info!("connected"));
+        self.heartbeat = Some(heartbeat);
+
         self.connection = Some(self_connection);
 
         let liveness: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
         let stream_liveness: Arc<AtomicBool> = Arc::clone(&liveness);
 
         //then set up stream
-        let stream = ClientHelper::build_input_stream(&self.device, config, input_stream_connection, stream_liveness).expect("could not build input stream!");
+        let capture = Capture {
+            connection: input_stream_connection,
+            liveness: stream_liveness,
+            device_errors: stream_device_errors,
+            stats: stream_stats,
+            echo: self.capture_echo(&config),
+            links: Arc::clone(&self.links),
+        };
+        let stream = ClientHelper::build_input_stream(&self.device, config, capture).expect("could not build input stream!");
 
         self.stream = Some(Metal2RemoteStream {
-            stream,
+            _stream: stream,
             liveness
         });
 
+        let connection = Arc::clone(self.connection.as_ref().expect("connection was just set!"));
+        for source in self.sources.iter_mut() {
+            if let Err(e) = ClientHelper::start_source(&connection, source).await {
+                warn!(parent: &span, source = source.id, "could not start source due to {}", e);
+            }
+        }
+
         Ok(())
     }
 
     async fn is_alive(&mut self) -> bool {
 
+        let connection_alive = match &self.connection {
+            Some(connection) => connection.lock().await.is_alive.load(Ordering::Relaxed),
+            None => false
+        };
+
+        if !connection_alive {
+            return false;
+        }
+
         match &self.stream {
             Some(stream) => stream.liveness.load(Ordering::Relaxed),
             None => false
@@ -212,8 +1144,181 @@ impl Client for Metal2RemoteClient {
     async fn name(&mut self) -> String {
         let device_name = match self.device.name() {
             Ok(name) => name,
-            Err(e) => "N/A".to_owned()
+            Err(_) => "N/A".to_owned()
+        };
+
+        let url = match &self.connection {
+            Some(connection) => connection.lock().await.url.clone(),
+            None => "N/A".to_owned()
+        };
+
+        format!("{device_name}:{url}")
+    }
+
+    async fn stats(&mut self) -> StreamStats {
+        let mut stats = match &self.connection {
+            Some(connection) => connection.lock().await.stats(self.rtt()),
+            None => return StreamStats::default()
+        };
+
+        if let Some(playback) = &self.playback {
+            let mut reverse = playback.lock().unwrap().stats();
+            reverse.rtt_ms = stats.rtt_ms;
+            stats.reverse = Some(Box::new(reverse));
+        }
+
+        stats
+    }
+}
+
+// plays what a server captures, the other way around from `Metal2RemoteClient`
+pub struct Remote2MetalClient {
+    connection: Option<Arc<Mutex<Connection>>>,
+    playback: Arc<std::sync::Mutex<Playback>>,
+    // the server's input unless set, see `broadcast`
+    stream: Option<String>,
+    // the default room unless set
+    room: Option<String>,
+    heartbeat_config: HeartbeatConfig,
+    heartbeat: Option<Arc<std::sync::Mutex<Heartbeat>>>,
+    identity: BinClientIdentity,
+    _instance: Instance,
+}
+
+unsafe impl std::marker::Send for Remote2MetalClient {}
+
+impl Remote2MetalClient {
+    pub fn new(device: cpal::Device) -> Self {
+        let (identity, instance) = BinClientIdentity::claim("listener");
+        Self {
+            connection: None,
+            playback: Arc::new(std::sync::Mutex::new(Playback::new(device))),
+            stream: None,
+            room: None,
+            heartbeat_config: HeartbeatConfig::default(),
+            heartbeat: None,
+            identity,
+            _instance: instance,
+        }
+    }
+
+    pub fn identity(&self) -> &BinClientIdentity {
+        &self.identity
+    }
+
+    // shown by the server instead of the peer address, takes effect on the next `connect`
+    pub fn set_name(&mut self, name: &str) {
+        self.identity.name = name.to_owned();
+    }
+
+    // takes effect on the next `connect`
+    pub fn set_heartbeat_config(&mut self, config: HeartbeatConfig) {
+        self.heartbeat_config = config;
+    }
+
+    // round trip to the server, measured from the last answered ping
+    pub fn rtt(&self) -> Option<Duration> {
+        self.heartbeat.as_ref()?.lock().unwrap().rtt()
+    }
+
+    // frames of audio playback had to make up because nothing arrived in time
+    pub fn concealed_frames(&self) -> u64 {
+        self.playback.lock().unwrap().concealed_frames.load(Ordering::Relaxed)
+    }
+
+    // play another client's audio (by its name or id) instead of the server's input, takes effect
+    // on the next `connect`
+    pub fn set_stream(&mut self, stream: Option<&str>) {
+        self.stream = stream.map(str::to_owned);
+    }
+
+    // the room to join on the server, only its clients' streams can be heard. takes effect on
+    // the next `connect`
+    pub fn set_room(&mut self, room: Option<&str>) {
+        self.room = room.map(str::to_owned);
+    }
+}
+
+impl Drop for Remote2MetalClient {
+    fn drop(&mut self) {
+        if let Some(connection) = &self.connection {
+            let spawn_connection = Arc::clone(connection);
+            tokio::task::spawn(async move {
+                let _ = spawn_connection.lock().await.transport.close().await;
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl Client for Remote2MetalClient {
+    // for a listener the source is the output device it plays on
+    async fn change_source_device(&mut self, new_device: cpal::Device) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(connection) = &self.connection {
+            let ul_connection = connection.lock().await;
+            ul_connection.span.record("device", new_device.name().unwrap_or_default().as_str());
+            ul_connection.span.in_scope(|| info!("changed output device"));
+        }
+
+        let mut playback = self.playback.lock().unwrap();
+        playback.device = new_device;
+        playback.start()
+    }
+
+    async fn connect(&mut self, url: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let (mut transport, receiver) = ClientTransport::open(url).await?;
+
+        let listen = match &self.stream {
+            Some(stream) => crate::BinMessages::BinSubscribe(stream.clone()),
+            None => crate::BinMessages::BinListen
+        };
+
+        transport.send(&crate::BinMessages::BinIdentify(self.identity.clone())).await.map_err(|e| e.to_string())?;
+        if let Some(room) = &self.room {
+            transport.send(&crate::BinMessages::BinJoin(room.clone())).await.map_err(|e| e.to_string())?;
+        }
+        transport.send(&listen).await.map_err(|e| e.to_string())?;
+
+        let connection_is_alive = Arc::new(AtomicBool::new(true));
+        let (device_error_sender, device_errors) = crate::logging::device_error_channel();
+        let device_name = self.playback.lock().unwrap().device.name().unwrap_or_default();
+        let span = info_span!("connection", server = %url, client = %self.identity.name, device = %device_name);
+        let stats = Arc::new(StreamCounters::default());
+
+        self.playback.lock().unwrap().reset(Arc::clone(&stats), device_error_sender.clone());
+
+        let connection = Connection {
+            url: url.to_owned(),
+            transport,
+            channels: 0,
+            is_alive: Arc::clone(&connection_is_alive),
+            stats,
+            device_error_sender,
+            device_errors,
+            span: span.clone(),
         };
+        let self_connection = Arc::new(Mutex::new(connection));
+
+        let heartbeat = Arc::new(std::sync::Mutex::new(Heartbeat::new(self.heartbeat_config)));
+        ClientHelper::spawn_receiver(receiver, Arc::clone(&self_connection), Arc::clone(&heartbeat), Arc::clone(&connection_is_alive), Some(Arc::clone(&self.playback)), span.clone());
+        ClientHelper::spawn_heartbeat(Arc::clone(&self_connection), Arc::clone(&heartbeat), connection_is_alive, span.clone());
+        span.in_scope(|| info!("listening"));
+
+        self.heartbeat = Some(heartbeat);
+        self.connection = Some(self_connection);
+
+        Ok(())
+    }
+
+    async fn is_alive(&mut self) -> bool {
+        match &self.connection {
+            Some(connection) => connection.lock().await.is_alive.load(Ordering::Relaxed),
+            None => false
+        }
+    }
+
+    async fn name(&mut self) -> String {
+        let device_name = self.playback.lock().unwrap().device.name().unwrap_or_else(|_| "N/A".to_owned());
 
         let url = match &self.connection {
             Some(connection) => connection.lock().await.url.clone(),
@@ -222,4 +1327,14 @@ impl Client for Metal2RemoteClient {
 
         format!("{device_name}:{url}")
     }
+
+    async fn stats(&mut self) -> StreamStats {
+        let mut stats = match &self.connection {
+            Some(connection) => connection.lock().await.stats(self.rtt()),
+            None => return StreamStats::default()
+        };
+        stats.concealed_frames = self.concealed_frames();
+
+        stats
+    }
 }
\ No newline at end of file
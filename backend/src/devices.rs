@@ -0,0 +1,174 @@
+use std::fmt;
+use std::error::Error;
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use serde::{Serialize, Deserialize};
+
+// audio device enumeration and lookup
+//
+// devices are identified by host, name and the position among devices sharing that name, so two
+// identical interfaces can still be told apart. cpal exposes nothing more stable than the name, so
+// if a device's position shifts but its name is unique it is still found
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DeviceId {
+    pub host: String,
+    pub name: String,
+    // 0 for the first device with this name on this host, 1 for the second and so on
+    pub index: usize,
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}#{}", self.host, self.name, self.index)
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceDirection {
+    Input,
+    Output,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DeviceConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DeviceInfo {
+    pub id: DeviceId,
+    pub name: String,
+    pub is_default: bool,
+    pub configs: Vec<DeviceConfigRange>,
+}
+
+#[derive(Debug)]
+pub enum DeviceError {
+    // was there once, isn't anymore (unplugged, renamed, host gone)
+    NotFound(DeviceId),
+    Backend(String),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::NotFound(id) => write!(f, "device {} is not available anymore", id),
+            DeviceError::Backend(e) => write!(f, "could not query audio devices: {}", e),
+        }
+    }
+}
+
+impl Error for DeviceError {}
+
+fn backend_error(e: impl fmt::Display) -> DeviceError {
+    DeviceError::Backend(e.to_string())
+}
+
+// every device in one direction on every available host, paired with its id
+fn enumerate(direction: DeviceDirection) -> Result<Vec<(DeviceId, cpal::Device, bool)>, DeviceError> {
+    let mut found = vec![];
+
+    for host_id in cpal::available_hosts() {
+        let host = cpal::host_from_id(host_id).map_err(backend_error)?;
+
+        let (devices, default) = match direction {
+            DeviceDirection::Input => (
+                host.input_devices().map_err(backend_error)?.collect::<Vec<cpal::Device>>(),
+                host.default_input_device(),
+            ),
+            DeviceDirection::Output => (
+                host.output_devices().map_err(backend_error)?.collect::<Vec<cpal::Device>>(),
+                host.default_output_device(),
+            ),
+        };
+        let default_name = default.and_then(|device| device.name().ok());
+
+        let mut seen: Vec<String> = vec![];
+        for device in devices {
+            // a device that can't even report its name is not one we can address
+            let name = match device.name() {
+                Ok(name) => name,
+                Err(_) => continue
+            };
+
+            let index = seen.iter().filter(|other| **other == name).count();
+            seen.push(name.clone());
+
+            let is_default = index == 0 && default_name.as_deref() == Some(name.as_str());
+            let id = DeviceId { host: host_id.name().to_owned(), name, index };
+
+            found.push((id, device, is_default));
+        }
+    }
+
+    Ok(found)
+}
+
+fn configs(device: &cpal::Device, direction: DeviceDirection) -> Vec<DeviceConfigRange> {
+    let ranges = match direction {
+        DeviceDirection::Input => device.supported_input_configs().map(|configs| configs.collect::<Vec<_>>()),
+        DeviceDirection::Output => device.supported_output_configs().map(|configs| configs.collect::<Vec<_>>()),
+    };
+
+    ranges
+        .unwrap_or_default()
+        .into_iter()
+        .map(|range| DeviceConfigRange {
+            channels: range.channels(),
+            min_sample_rate: range.min_sample_rate().0,
+            max_sample_rate: range.max_sample_rate().0,
+            sample_format: format!("{:?}", range.sample_format()),
+        })
+        .collect()
+}
+
+pub fn list_devices(direction: DeviceDirection) -> Result<Vec<DeviceInfo>, DeviceError> {
+    Ok(enumerate(direction)?
+        .into_iter()
+        .map(|(id, device, is_default)| DeviceInfo {
+            name: id.name.clone(),
+            configs: configs(&device, direction),
+            id,
+            is_default,
+        })
+        .collect())
+}
+
+pub fn input_devices() -> Result<Vec<DeviceInfo>, DeviceError> {
+    list_devices(DeviceDirection::Input)
+}
+
+pub fn output_devices() -> Result<Vec<DeviceInfo>, DeviceError> {
+    list_devices(DeviceDirection::Output)
+}
+
+pub fn find_device(id: &DeviceId, direction: DeviceDirection) -> Result<cpal::Device, DeviceError> {
+    let mut same_name = enumerate(direction)?
+        .into_iter()
+        .filter(|(other, _, _)| other.host == id.host && other.name == id.name)
+        .collect::<Vec<(DeviceId, cpal::Device, bool)>>();
+
+    if let Some(position) = same_name.iter().position(|(other, _, _)| other == id) {
+        return Ok(same_name.swap_remove(position).1);
+    }
+
+    // the one device with this name moved, nothing else it could be confused with
+    if same_name.len() == 1 {
+        return Ok(same_name.remove(0).1);
+    }
+
+    Err(DeviceError::NotFound(id.clone()))
+}
+
+pub fn find_input_device(id: &DeviceId) -> Result<cpal::Device, DeviceError> {
+    find_device(id, DeviceDirection::Input)
+}
+
+pub fn find_output_device(id: &DeviceId) -> Result<cpal::Device, DeviceError> {
+    find_device(id, DeviceDirection::Output)
+}
+
@@ -0,0 +1,478 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+// forward error correction for datagram audio
+//
+// every datagram carries a sequence number. when enabled, the sender follows each group of
+// `group_size` frames with one XOR parity frame, which lets the receiver rebuild any single frame
+// lost from that group. the receiver reports its loss rate back so the sender can switch parity
+// on and off by itself. a frame nothing can rebuild is delivered as `Delivered::Missing`, so
+// playback conceals it in place instead of closing the gap
+
+// how far the newest frame may run ahead of a hole before the hole is given up on
+static REORDER_WINDOW: u64 = 3;
+// frames kept around after delivery so late parity can still use them
+static HISTORY_LEN: u64 = 64;
+// parity groups a frame may jump ahead before the decoder stops concealing the gap frame by frame
+// and starts over from it
+static RESYNC_GROUPS: u64 = 4;
+// received + lost frames per loss report
+pub static LOSS_REPORT_INTERVAL: u64 = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BinFrame {
+    pub seq: u64,
+    pub data: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BinParity {
+    pub first_seq: u64,
+    pub count: u16,
+    // XOR of the frame lengths, so a rebuilt frame gets its length back too
+    pub len_xor: u32,
+    // XOR of the sample bit patterns, zero padded to the longest frame
+    pub parity: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct BinLossReport {
+    pub loss_rate: f32,
+}
+
+// what the decoder hands on, in sequence order
+#[derive(Debug, PartialEq)]
+pub enum Delivered {
+    Frame(Vec<f32>),
+    // samples lost for good, as many as the last frame that arrived had
+    Missing(usize),
+}
+
+// what a transport hands on: a message from the peer, still serialized, or samples a lossy
+// transport lost on the way that nothing could rebuild. those never go over the wire, playback
+// conceals them in place
+#[derive(Debug, PartialEq)]
+pub enum Received {
+    Message(Vec<u8>),
+    Missing(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FecMode {
+    Off,
+    On,
+    // on while the reported loss rate is above `enable_above`, off again below `disable_below`
+    Auto,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FecConfig {
+    pub mode: FecMode,
+    // frames per parity frame, overhead is 1 / group_size
+    pub group_size: u16,
+    pub enable_above: f32,
+    pub disable_below: f32,
+}
+
+impl Default for FecConfig {
+    fn default() -> Self {
+        FecConfig {
+            mode: FecMode::Auto,
+            group_size: 4,
+            enable_above: 0.02,
+            disable_below: 0.005,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+pub struct FecStats {
+    // sending side
+    pub enabled: bool,
+    pub parity_sent: u64,
+    // loss rate the peer last reported for what we send
+    pub reported_loss_rate: f32,
+
+    // receiving side
+    pub frames_received: u64,
+    // holes that could not be rebuilt and were left for concealment
+    pub frames_lost: u64,
+    pub frames_recovered: u64,
+    pub parity_received: u64,
+    // loss rate measured on what we receive, before recovery
+    pub loss_rate: f32,
+}
+
+impl FecStats {
+    pub fn combine(sent: FecStats, received: FecStats) -> FecStats {
+        FecStats {
+            enabled: sent.enabled,
+            parity_sent: sent.parity_sent,
+            reported_loss_rate: sent.reported_loss_rate,
+            ..received
+        }
+    }
+}
+
+fn xor_into(parity: &mut Vec<u32>, data: &[f32]) {
+    if parity.len() < data.len() {
+        parity.resize(data.len(), 0);
+    }
+
+    for (p, d) in parity.iter_mut().zip(data) {
+        *p ^= d.to_bits();
+    }
+}
+
+pub struct FecEncoder {
+    config: FecConfig,
+    enabled: bool,
+    next_seq: u64,
+    group: Option<BinParity>,
+    stats: FecStats,
+}
+
+impl FecEncoder {
+    pub fn new(config: FecConfig) -> Self {
+        FecEncoder {
+            config,
+            enabled: config.mode == FecMode::On,
+            next_seq: 0,
+            group: None,
+            stats: FecStats { enabled: config.mode == FecMode::On, ..Default::default() },
+        }
+    }
+
+    pub fn set_config(&mut self, config: FecConfig) {
+        self.config = config;
+        match config.mode {
+            FecMode::On => self.set_enabled(true),
+            FecMode::Off => self.set_enabled(false),
+            FecMode::Auto => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.group = None;
+        }
+        self.enabled = enabled;
+        self.stats.enabled = enabled;
+    }
+
+    // number the next frame, and hand back the parity frame if it closes a group
+    pub fn encode(&mut self, data: Vec<f32>) -> (BinFrame, Option<BinParity>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        if !self.enabled {
+            return (BinFrame { seq, data }, None);
+        }
+
+        let group = self.group.get_or_insert_with(|| BinParity { first_seq: seq, count: 0, len_xor: 0, parity: vec![] });
+        xor_into(&mut group.parity, &data);
+        group.len_xor ^= data.len() as u32;
+        group.count += 1;
+
+        let parity = if group.count >= self.config.group_size.max(1) {
+            self.stats.parity_sent += 1;
+            self.group.take()
+        } else {
+            None
+        };
+
+        (BinFrame { seq, data }, parity)
+    }
+
+    pub fn on_loss_report(&mut self, report: BinLossReport) {
+        self.stats.reported_loss_rate = report.loss_rate;
+
+        if self.config.mode != FecMode::Auto {
+            return;
+        }
+
+        if !self.enabled && report.loss_rate > self.config.enable_above {
+            tracing::info!("loss rate {:.3}, enabling fec", report.loss_rate);
+            self.set_enabled(true);
+        } else if self.enabled && report.loss_rate < self.config.disable_below {
+            tracing::info!("loss rate {:.3}, disabling fec", report.loss_rate);
+            self.set_enabled(false);
+        }
+    }
+
+    pub fn stats(&self) -> FecStats {
+        self.stats
+    }
+}
+
+#[derive(Default)]
+pub struct FecDecoder {
+    next_seq: Option<u64>,
+    newest_seq: u64,
+    // size of the last parity group seen, holes wait this much longer for their parity
+    group_span: u64,
+    // frames not delivered yet plus recently delivered ones, keyed by seq
+    frames: BTreeMap<u64, Vec<f32>>,
+    parities: BTreeMap<u64, BinParity>,
+    window_received: u64,
+    window_lost: u64,
+    // length of the last frame delivered, what a lost one is assumed to have had
+    last_len: usize,
+    stats: FecStats,
+}
+
+impl FecDecoder {
+    // frames that are ready to play, in order
+    pub fn push_frame(&mut self, frame: BinFrame) -> Vec<Delivered> {
+        let next_seq = *self.next_seq.get_or_insert(frame.seq);
+
+        // late duplicate, or a frame that was already given up on
+        if frame.seq < next_seq || self.frames.contains_key(&frame.seq) {
+            return vec![];
+        }
+
+        if frame.seq - next_seq > self.resync_gap() {
+            return self.resync(frame);
+        }
+
+        self.stats.frames_received += 1;
+        self.window_received += 1;
+        self.newest_seq = self.newest_seq.max(frame.seq);
+        self.frames.insert(frame.seq, frame.data);
+
+        self.drain()
+    }
+
+    pub fn push_parity(&mut self, parity: BinParity) -> Vec<Delivered> {
+        if parity.count == 0 {
+            return vec![];
+        }
+
+        // parity for frames far past the gap protects nothing that is still waiting, its frames
+        // resync the decoder when they arrive
+        let last_seq = parity.first_seq.saturating_add(parity.count as u64 - 1);
+        match self.next_seq {
+            Some(next_seq) if last_seq.saturating_sub(next_seq) <= self.resync_gap() => {},
+            _ => return vec![],
+        }
+
+        self.stats.parity_received += 1;
+        self.group_span = parity.count as u64;
+        self.newest_seq = self.newest_seq.max(last_seq);
+        self.parities.insert(parity.first_seq, parity);
+
+        self.drain()
+    }
+
+    // loss rate since the last report, once enough frames have gone by
+    pub fn loss_report(&mut self) -> Option<BinLossReport> {
+        let total = self.window_received + self.window_lost;
+        if total < LOSS_REPORT_INTERVAL {
+            return None;
+        }
+
+        let loss_rate = self.window_lost as f32 / total as f32;
+        self.window_received = 0;
+        self.window_lost = 0;
+        self.stats.loss_rate = loss_rate;
+
+        Some(BinLossReport { loss_rate })
+    }
+
+    pub fn stats(&self) -> FecStats {
+        self.stats
+    }
+
+    // how far past the next frame to deliver a frame may be and still have the gap concealed
+    fn resync_gap(&self) -> u64 {
+        let group_span = self.group_span.max(FecConfig::default().group_size as u64);
+        REORDER_WINDOW + RESYNC_GROUPS * group_span
+    }
+
+    // hand on what arrived before the jump and carry on from `frame`, the frames skipped over are
+    // left out rather than handed on as one `Delivered::Missing` each
+    fn resync(&mut self, frame: BinFrame) -> Vec<Delivered> {
+        tracing::debug!("sequence jumped from {:?} to {}, resyncing", self.next_seq, frame.seq);
+
+        let mut ready = vec![];
+        for (_, data) in self.frames.split_off(&self.next_seq.unwrap_or_default()) {
+            self.last_len = data.len();
+            ready.push(Delivered::Frame(data));
+        }
+
+        self.stats.frames_received += 1;
+        self.window_received += 1;
+        self.frames.clear();
+        self.parities.clear();
+        self.next_seq = Some(frame.seq);
+        self.newest_seq = frame.seq;
+        self.frames.insert(frame.seq, frame.data);
+
+        ready.extend(self.drain());
+        ready
+    }
+
+    fn drain(&mut self) -> Vec<Delivered> {
+        let mut ready = vec![];
+
+        while let Some(next_seq) = self.next_seq {
+            if let Some(frame) = self.frames.get(&next_seq) {
+                self.last_len = frame.len();
+                ready.push(Delivered::Frame(frame.clone()));
+            } else if let Some(frame) = self.recover(next_seq) {
+                self.stats.frames_recovered += 1;
+                // still lost on the wire, so it counts toward the reported loss rate
+                self.window_lost += 1;
+                self.last_len = frame.len();
+                ready.push(Delivered::Frame(frame.clone()));
+                self.frames.insert(next_seq, frame);
+            } else if self.newest_seq > next_seq.saturating_add(REORDER_WINDOW + self.group_span) {
+                // gave up waiting, the playback side conceals the hole
+                self.stats.frames_lost += 1;
+                self.window_lost += 1;
+                ready.push(Delivered::Missing(self.last_len));
+            } else {
+                break;
+            }
+
+            // past the last possible seq the next frame starts over, like the first one did
+            self.next_seq = next_seq.checked_add(1);
+        }
+
+        if let Some(next_seq) = self.next_seq {
+            let oldest = next_seq.saturating_sub(HISTORY_LEN);
+            self.frames = self.frames.split_off(&oldest);
+            self.parities = self.parities.split_off(&oldest);
+        }
+
+        ready
+    }
+
+    fn recover(&self, seq: u64) -> Option<Vec<f32>> {
+        let (_, parity) = self.parities.range(..=seq).next_back()?;
+        let end = parity.first_seq.saturating_add(parity.count as u64);
+        if seq >= end {
+            return None;
+        }
+
+        let mut bits = parity.parity.clone();
+        let mut len = parity.len_xor;
+
+        for other in parity.first_seq..end {
+            if other == seq {
+                continue;
+            }
+
+            // more than one frame missing from the group, nothing to rebuild from
+            let frame = self.frames.get(&other)?;
+            xor_into(&mut bits, frame);
+            len ^= frame.len() as u32;
+        }
+
+        bits.truncate(len as usize);
+        Some(bits.into_iter().map(f32::from_bits).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(count: usize) -> Vec<Vec<f32>> {
+        (0..count).map(|i| (0..8).map(|j| (i * 8 + j) as f32 / 100.0).collect()).collect()
+    }
+
+    // the frames of one parity group and its parity frame
+    fn group(data: &[Vec<f32>]) -> (Vec<BinFrame>, BinParity) {
+        let mut encoder = FecEncoder::new(FecConfig { mode: FecMode::On, group_size: data.len() as u16, ..Default::default() });
+        let mut sent = vec![];
+        let mut parity = None;
+
+        for frame in data {
+            let (frame, group_parity) = encoder.encode(frame.clone());
+            sent.push(frame);
+            parity = parity.or(group_parity);
+        }
+
+        (sent, parity.expect("a full group makes a parity frame!"))
+    }
+
+    #[test]
+    fn single_loss_in_a_group_is_rebuilt() {
+        let data = frames(4);
+        let (sent, parity) = group(&data);
+        let mut decoder = FecDecoder::default();
+
+        let mut delivered = vec![];
+        for frame in sent.into_iter().filter(|frame| frame.seq != 2) {
+            delivered.extend(decoder.push_frame(frame));
+        }
+        delivered.extend(decoder.push_parity(parity));
+
+        assert_eq!(delivered, data.into_iter().map(Delivered::Frame).collect::<Vec<Delivered>>());
+        assert_eq!(decoder.stats().frames_recovered, 1);
+        assert_eq!(decoder.stats().frames_lost, 0);
+    }
+
+    #[test]
+    fn two_losses_in_a_group_are_handed_on_as_missing() {
+        let data = frames(12);
+        let (first, parity) = group(&data[..4]);
+        let mut decoder = FecDecoder::default();
+
+        let mut delivered = vec![];
+        for frame in first.into_iter().filter(|frame| frame.seq != 1 && frame.seq != 2) {
+            delivered.extend(decoder.push_frame(frame));
+        }
+        delivered.extend(decoder.push_parity(parity));
+
+        // later frames push the holes out of the reorder window
+        for (seq, frame) in data.iter().enumerate().skip(4) {
+            delivered.extend(decoder.push_frame(BinFrame { seq: seq as u64, data: frame.clone() }));
+        }
+
+        assert_eq!(delivered[0], Delivered::Frame(data[0].clone()));
+        assert_eq!(delivered[1], Delivered::Missing(8));
+        assert_eq!(delivered[2], Delivered::Missing(8));
+        assert_eq!(delivered[3], Delivered::Frame(data[3].clone()));
+        assert_eq!(decoder.stats().frames_recovered, 0);
+        assert_eq!(decoder.stats().frames_lost, 2);
+    }
+
+    #[test]
+    fn reordered_frames_are_delivered_in_order() {
+        let data = frames(3);
+        let mut decoder = FecDecoder::default();
+
+        let mut delivered = decoder.push_frame(BinFrame { seq: 0, data: data[0].clone() });
+        delivered.extend(decoder.push_frame(BinFrame { seq: 2, data: data[2].clone() }));
+        delivered.extend(decoder.push_frame(BinFrame { seq: 1, data: data[1].clone() }));
+
+        assert_eq!(delivered, data.into_iter().map(Delivered::Frame).collect::<Vec<Delivered>>());
+    }
+
+    #[test]
+    fn a_long_jump_resyncs_instead_of_reporting_every_skipped_frame() {
+        let data = frames(3);
+        let mut decoder = FecDecoder::default();
+
+        let mut delivered = decoder.push_frame(BinFrame { seq: 0, data: data[0].clone() });
+        delivered.extend(decoder.push_frame(BinFrame { seq: 1_000_000, data: data[1].clone() }));
+        delivered.extend(decoder.push_frame(BinFrame { seq: 1_000_001, data: data[2].clone() }));
+
+        assert_eq!(delivered, data.into_iter().map(Delivered::Frame).collect::<Vec<Delivered>>());
+    }
+
+    #[test]
+    fn sequence_numbers_at_the_end_of_the_range_do_not_overflow() {
+        let data = frames(2);
+        let mut decoder = FecDecoder::default();
+
+        let mut delivered = decoder.push_frame(BinFrame { seq: u64::MAX - 1, data: data[0].clone() });
+        delivered.extend(decoder.push_parity(BinParity { first_seq: u64::MAX, count: u16::MAX, len_xor: 0, parity: vec![] }));
+        delivered.extend(decoder.push_frame(BinFrame { seq: u64::MAX, data: data[1].clone() }));
+
+        assert_eq!(delivered, data.into_iter().map(Delivered::Frame).collect::<Vec<Delivered>>());
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+
+// ping/pong keepalive shared by both ends of a connection
+//
+// each side pings on an interval and answers the other side's pings. anything heard from the
+// peer counts as a sign of life, and a peer that stays silent past the timeout is torn down, which
+// catches half open connections the transport itself never reports
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+pub struct Heartbeat {
+    config: HeartbeatConfig,
+    started: Instant,
+    last_heard: Instant,
+    rtt: Option<Duration>,
+}
+
+impl Heartbeat {
+    pub fn new(config: HeartbeatConfig) -> Self {
+        Heartbeat {
+            config,
+            started: Instant::now(),
+            last_heard: Instant::now(),
+            rtt: None,
+        }
+    }
+
+    pub fn config(&self) -> HeartbeatConfig {
+        self.config
+    }
+
+    // the ping carries our own clock, so the pong is all that is needed to measure the round trip
+    pub fn ping(&self) -> crate::BinMessages {
+        crate::BinMessages::BinPing(self.started.elapsed().as_micros() as u64)
+    }
+
+    // note that the peer is alive, and answer if the message was a ping
+    pub fn on_message(&mut self, message: &crate::BinMessages) -> Option<crate::BinMessages> {
+        self.last_heard = Instant::now();
+
+        match message {
+            crate::BinMessages::BinPing(nonce) => Some(crate::BinMessages::BinPong(*nonce)),
+            crate::BinMessages::BinPong(sent) => {
+                let now = self.started.elapsed().as_micros() as u64;
+                self.rtt = Some(Duration::from_micros(now.saturating_sub(*sent)));
+                None
+            },
+            _ => None
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.last_heard.elapsed() > self.config.timeout
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+}
@@ -0,0 +1,107 @@
+use bytes::Bytes;
+use cpal::StreamConfig;
+use futures::{Stream, StreamExt};
+use tokio::sync::watch;
+
+use crate::broadcast::{Cast, Subscription};
+
+// streams as plain http audio, for media players that don't speak the protocol
+//
+// `GET /audio/<stream>` on the rest endpoint answers with a wav file that never ends: a header
+// claiming the largest size wav allows, then the stream's audio as 16 bit pcm for as long as the
+// player keeps reading. vlc, mpv and ffmpeg play it like internet radio. it is the same stream
+// listeners subscribe to, named and scoped to a room the same way, and counts as one of its
+// listeners. wav can't change format halfway, so the response ends when the stream's config does
+// and the player reconnects to get the new one. only wav is served, ogg or mp3 would need an encoder
+
+pub static WAV_CONTENT_TYPE: &str = "audio/wav";
+
+// sizes a live wav claims, players read until the connection closes
+static UNKNOWN_SIZE: u32 = u32::MAX;
+
+pub fn wav_header(config: &StreamConfig) -> Vec<u8> {
+    let channels = config.channels;
+    let sample_rate = config.sample_rate.0;
+    let block_align = channels.saturating_mul(2);
+    // no real stream comes close, but the field is 32 bit
+    let byte_rate = u32::try_from(sample_rate as u64 * block_align as u64).unwrap_or(u32::MAX);
+
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    // integer pcm
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+
+    header
+}
+
+// interleaved samples as the wav's 16 bit little endian pcm
+pub fn wav_samples(samples: &[f32]) -> Vec<u8> {
+    samples.iter()
+        .flat_map(|sample| ((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+        .collect()
+}
+
+// the body of an audio response, ends when the stream changes format, the listener is cut off for
+// falling behind or the server shuts down
+pub fn wav_body(subscription: Subscription, shutdown: watch::Receiver<bool>) -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+    let header = Bytes::from(wav_header(&subscription.config));
+
+    let audio = futures::stream::unfold((subscription, shutdown), |(mut subscription, mut shutdown)| async move {
+        loop {
+            let cast = tokio::select! {
+                _ = shutdown.changed() => return None,
+                cast = subscription.receiver.recv() => cast?
+            };
+
+            match cast {
+                Cast::Block(block) => return Some((Ok(Bytes::from(wav_samples(&block.samples))), (subscription, shutdown))),
+                Cast::Config(config) if config.channels == subscription.config.channels && config.sample_rate == subscription.config.sample_rate => continue,
+                Cast::Config(_) => return None
+            }
+        }
+    });
+
+    futures::stream::once(async move { Ok(header) }).chain(audio)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::{SampleFormat, WavReader};
+
+    #[test]
+    fn header_round_trips() {
+        let config = StreamConfig { channels: 2, sample_rate: cpal::SampleRate(44_100), buffer_size: cpal::BufferSize::Default };
+        let samples = [0.0, 0.5, -0.5, 0.25];
+
+        let mut reader = WavReader::default();
+        let mut read = reader.push(&wav_header(&config)).unwrap();
+        read.extend(reader.push(&wav_samples(&samples)).unwrap());
+
+        let format = reader.format.unwrap();
+        assert_eq!((format.channels, format.sample_rate, format.sample_format), (2, 44_100, SampleFormat::Int16));
+        assert_eq!(read.len(), samples.len());
+        assert!(read.iter().zip(samples).all(|(read, sample)| (read - sample).abs() < 1e-4));
+    }
+
+    #[test]
+    fn byte_rate_saturates() {
+        let config = StreamConfig { channels: 8, sample_rate: cpal::SampleRate(u32::MAX), buffer_size: cpal::BufferSize::Default };
+
+        let header = wav_header(&config);
+        assert_eq!(u32::from_le_bytes(header[28..32].try_into().unwrap()), u32::MAX);
+    }
+}
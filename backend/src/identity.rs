@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::path::PathBuf;
+
+use serde::{Serialize, Deserialize};
+
+// who a client is, independent of the address it happens to connect from
+//
+// the id is generated once per machine and kept on disk so the server recognises the client again
+// after a reconnect, each client in a process or on the machine adds its role and an instance number
+// so two of them never look like the same client reconnecting, the name is only for people looking
+// at a list of clients
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BinClientIdentity {
+    pub id: String,
+    pub name: String,
+}
+
+// how many instances of one role are tried before falling back to the process id
+const MAX_INSTANCES: usize = 64;
+
+// holds on to an instance number for as long as the client or server that claimed it is alive
+#[derive(Debug)]
+pub struct Instance {
+    _lock: Option<File>,
+}
+
+impl BinClientIdentity {
+    // the stored id (created on first use) with the role and the first free instance number, and
+    // the machine's host name
+    pub fn claim(role: &str) -> (Self, Instance) {
+        let (id, instance) = claim_id("client_id", role);
+        let identity = BinClientIdentity {
+            id,
+            name: default_name(),
+        };
+
+        (identity, instance)
+    }
+}
+
+// the id kept in `file` under `config_dir()` (created on first use) with `role` and the first free
+// instance number, stable across restarts as long as as many instances run as before
+pub(crate) fn claim_id(file: &str, role: &str) -> (String, Instance) {
+    let (suffix, instance) = claim_instance(role);
+
+    (format!("{}-{}", load_or_create_id(file), suffix), instance)
+}
+
+// where client and server settings are kept, `~/.remoteio`
+pub fn config_dir() -> PathBuf {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_default();
+
+    home.join(".remoteio")
+}
+
+fn load_or_create_id(file: &str) -> String {
+    let path = config_dir().join(file);
+
+    if let Ok(id) = std::fs::read_to_string(&path) {
+        let id = id.trim();
+        if !id.is_empty() {
+            return id.to_owned();
+        }
+    }
+
+    let id = format!("{:032x}", rand::random::<u128>());
+
+    // still usable for this run, it just won't survive a restart
+    let stored = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&path, &id));
+    if let Err(e) = stored {
+        tracing::warn!("could not store id in {} due to {}", path.display(), e);
+    }
+
+    id
+}
+
+// instance n of a role is taken while someone holds the lock on `instances/{role}-{n}.lock`, the
+// first instance keeps the bare role so a lone client always comes back with the same id
+fn claim_instance(role: &str) -> (String, Instance) {
+    let dir = config_dir().join("instances");
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        tracing::warn!("could not create {} due to {}", dir.display(), e);
+    }
+
+    for n in 0..MAX_INSTANCES {
+        let path = dir.join(format!("{}-{}.lock", role, n));
+        let file = match File::options().create(true).truncate(false).write(true).open(&path) {
+            Ok(file) => file,
+            Err(_) => break,
+        };
+
+        if file.try_lock().is_ok() {
+            let suffix = if n == 0 { role.to_owned() } else { format!("{}-{}", role, n) };
+            return (suffix, Instance { _lock: Some(file) });
+        }
+    }
+
+    // no lock to hold, the process id still tells this client apart from the others
+    (format!("{}-p{}", role, std::process::id()), Instance { _lock: None })
+}
+
+fn default_name() -> String {
+    let name = gethostname::gethostname().to_string_lossy().trim().to_owned();
+    if !name.is_empty() {
+        return name;
+    }
+
+    std::fs::read_to_string("/etc/hostname")
+        .map(|name| name.trim().to_owned())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "remoteio client".to_owned())
+}
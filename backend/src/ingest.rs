@@ -0,0 +1,548 @@
+use std::pin::Pin;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use futures::{Stream, StreamExt};
+use hyper::body::HttpBody;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{info, warn, Instrument};
+
+use crate::identity::BinClientIdentity;
+use crate::quic::TransportError;
+
+// remote http audio as a client of the server
+//
+// an ingest pulls a stream off an http url, internet radio or another system's output, and hands
+// it to the server as if a client had connected and sent it: it goes through the handshake, gets a
+// route, can be listened to and shows up in `list_clients` under its id. a task fetches and decodes
+// the stream and queues what the connection reads, so the connection outlives the remote one: when
+// the stream fails or ends the task reconnects, answering pings meanwhile so the server keeps the
+// client around. disconnecting the client stops the ingest. only plain http is fetched, https would
+// need a tls connector. wav is read as it comes, anything else (ogg, opus, mp3, ...) only through a
+// decoder command set in the config, e.g. ffmpeg, which gets the stream on its stdin and writes wav
+// to its stdout
+
+// how long to wait before fetching the stream again after it failed or ended
+pub static INGEST_RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+// a file served all at once is played in real time, at most this far ahead of the clock
+static MAX_AHEAD: Duration = Duration::from_millis(200);
+// messages queued for the connection
+static INGEST_BACKLOG: usize = 64;
+// read from a decoder's stdout at a time
+static DECODER_READ: usize = 8192;
+
+// what the wav reader is fed, the response body or the decoder's output
+type Chunks = Pin<Box<dyn Stream<Item = Result<Vec<u8>, String>> + Send>>;
+
+#[derive(Clone, Debug)]
+pub struct IngestConfig {
+    pub url: String,
+    // what the client is known as, the url unless set
+    pub id: String,
+    pub name: String,
+    // `None` joins the default room
+    pub room: Option<String>,
+    pub reconnect_interval: Duration,
+    // a program and its arguments that every response is piped through, started per request, e.g.
+    // `ffmpeg -i pipe:0 -f wav pipe:1`. streams that aren't wav are refused without one
+    pub decoder: Option<Vec<String>>,
+}
+
+impl IngestConfig {
+    pub fn new(url: &str) -> Self {
+        IngestConfig {
+            url: url.to_owned(),
+            id: url.to_owned(),
+            name: url.to_owned(),
+            room: None,
+            reconnect_interval: INGEST_RECONNECT_INTERVAL,
+            decoder: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SampleFormat {
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl SampleFormat {
+    fn bytes(&self) -> usize {
+        match self {
+            SampleFormat::Int16 => 2,
+            SampleFormat::Int24 => 3,
+            SampleFormat::Int32 | SampleFormat::Float32 => 4,
+        }
+    }
+
+    fn decode(&self, sample: &[u8]) -> f32 {
+        match self {
+            SampleFormat::Int16 => i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32_768.0,
+            SampleFormat::Int24 => i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) as f32 / 2_147_483_648.0,
+            SampleFormat::Int32 => i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f32 / 2_147_483_648.0,
+            SampleFormat::Float32 => f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct WavFormat {
+    pub(crate) channels: u16,
+    pub(crate) sample_rate: u32,
+    pub(crate) sample_format: SampleFormat,
+}
+
+// reads a wav as it comes in, live streams claim any size so the data chunk is read to the end
+#[derive(Default)]
+pub(crate) struct WavReader {
+    buffer: Vec<u8>,
+    pub(crate) format: Option<WavFormat>,
+    // past the header, everything from here on is samples
+    in_data: bool,
+}
+
+impl WavReader {
+    // the samples in `bytes`, interleaved. part of a frame is kept for the next call
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> Result<Vec<f32>, String> {
+        self.buffer.extend_from_slice(bytes);
+
+        if !self.in_data && !self.read_header()? {
+            return Ok(vec![]);
+        }
+
+        let format = self.format.expect("data chunk read before its format!");
+        let frame_bytes = format.sample_format.bytes() * format.channels as usize;
+        let whole = self.buffer.len() / frame_bytes * frame_bytes;
+
+        let samples = self.buffer[..whole].chunks_exact(format.sample_format.bytes()).map(|sample| format.sample_format.decode(sample)).collect();
+        self.buffer.drain(..whole);
+
+        Ok(samples)
+    }
+
+    // `false` while more of the header is needed
+    fn read_header(&mut self) -> Result<bool, String> {
+        if self.buffer.len() < 12 {
+            return Ok(false);
+        }
+        if &self.buffer[0..4] != b"RIFF" || &self.buffer[8..12] != b"WAVE" {
+            return Err("not a wav stream".to_owned());
+        }
+
+        let mut offset = 12;
+        while self.buffer.len() >= offset + 8 {
+            let id = &self.buffer[offset..offset + 4];
+            let size = u32::from_le_bytes(self.buffer[offset + 4..offset + 8].try_into().unwrap()) as usize;
+
+            if id == b"data" {
+                if self.format.is_none() {
+                    return Err("wav stream has no format before its data".to_owned());
+                }
+
+                self.buffer.drain(..offset + 8);
+                self.in_data = true;
+                return Ok(true);
+            }
+
+            // chunks are padded to an even size
+            let end = offset + 8 + size + size % 2;
+            if self.buffer.len() < end {
+                return Ok(false);
+            }
+
+            if id == b"fmt " {
+                self.format = Some(WavReader::read_format(&self.buffer[offset + 8..offset + 8 + size])?);
+            }
+            offset = end;
+        }
+
+        Ok(false)
+    }
+
+    fn read_format(chunk: &[u8]) -> Result<WavFormat, String> {
+        if chunk.len() < 16 {
+            return Err("wav format chunk is too short".to_owned());
+        }
+
+        let mut tag = u16::from_le_bytes([chunk[0], chunk[1]]);
+        let channels = u16::from_le_bytes([chunk[2], chunk[3]]);
+        let sample_rate = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        let bits = u16::from_le_bytes([chunk[14], chunk[15]]);
+
+        // extensible formats keep the actual tag in their sub format
+        if tag == 0xfffe && chunk.len() >= 26 {
+            tag = u16::from_le_bytes([chunk[24], chunk[25]]);
+        }
+
+        let sample_format = match (tag, bits) {
+            (1, 16) => SampleFormat::Int16,
+            (1, 24) => SampleFormat::Int24,
+            (1, 32) => SampleFormat::Int32,
+            (3, 32) => SampleFormat::Float32,
+            _ => return Err(format!("can't read {} bit wav samples with format {}", bits, tag))
+        };
+        if channels == 0 {
+            return Err("wav stream has no channels".to_owned());
+        }
+
+        Ok(WavFormat { channels, sample_rate, sample_format })
+    }
+}
+
+// the connection side of an ingest, reads what the fetching task queued
+pub struct IngestTransport {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    // pongs go out the same way the audio does
+    sender: mpsc::Sender<Vec<u8>>,
+    task: JoinHandle<()>,
+}
+
+impl IngestTransport {
+    // starts fetching, the handshake is queued right away and the config once the stream answers
+    pub fn open(config: IngestConfig) -> Result<Self, TransportError> {
+        let uri: hyper::Uri = config.url.parse()?;
+        if uri.scheme_str() != Some("http") {
+            return Err(format!("can't ingest {}, only http urls are supported", config.url).into());
+        }
+        if config.decoder.as_ref().is_some_and(Vec::is_empty) {
+            return Err("the decoder command is empty".into());
+        }
+
+        let (sender, receiver) = mpsc::channel(INGEST_BACKLOG);
+
+        let mut handshake = vec![crate::BinMessages::BinIdentify(BinClientIdentity { id: config.id.clone(), name: config.name.clone() })];
+        if let Some(room) = &config.room {
+            handshake.push(crate::BinMessages::BinJoin(room.clone()));
+        }
+        for message in handshake.iter() {
+            sender.try_send(bincode::serialize(message)?)?;
+        }
+
+        let span = tracing::info_span!("ingest", url = %config.url);
+        let task = tokio::spawn(fetch(config, uri, sender.clone()).instrument(span));
+
+        Ok(IngestTransport { receiver, sender, task })
+    }
+
+    pub async fn recv(&mut self) -> Option<Result<Vec<u8>, TransportError>> {
+        self.receiver.recv().await.map(Ok)
+    }
+
+    // there's nobody to send to, pings are answered here and everything else is dropped
+    pub fn send(&mut self, message: &crate::BinMessages) -> Result<(), TransportError> {
+        if let crate::BinMessages::BinPing(nonce) = message {
+            // a full queue is audio coming in, that keeps the connection alive too
+            let _ = self.sender.try_send(bincode::serialize(&crate::BinMessages::BinPong(*nonce))?);
+        }
+
+        Ok(())
+    }
+
+    pub fn close(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for IngestTransport {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// fetch the stream until the connection goes away, reconnecting whenever it fails or ends
+async fn fetch(config: IngestConfig, uri: hyper::Uri, sender: mpsc::Sender<Vec<u8>>) {
+    let client = hyper::Client::new();
+    let mut format = None;
+
+    loop {
+        match fetch_once(&client, &uri, config.decoder.as_deref(), &sender, &mut format).await {
+            // the connection is gone, nobody to fetch for
+            Ok(()) if sender.is_closed() => return,
+            Ok(()) => warn!("stream ended, reconnecting in {:?}", config.reconnect_interval),
+            Err(e) => warn!("could not read stream due to {}, reconnecting in {:?}", e, config.reconnect_interval),
+        }
+
+        tokio::time::sleep(config.reconnect_interval).await;
+    }
+}
+
+// one request, until the stream ends or the connection goes away. `format` is what the connection
+// was last told, a config is only queued when it changes
+async fn fetch_once(client: &hyper::Client<hyper::client::HttpConnector>, uri: &hyper::Uri, decoder: Option<&[String]>, sender: &mpsc::Sender<Vec<u8>>, format: &mut Option<WavFormat>) -> Result<(), String> {
+    let response = client.get(uri.clone()).await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("server answered {}", response.status()));
+    }
+
+    let content_type = response.headers().get(hyper::header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default().to_owned();
+    if decoder.is_none() && ["ogg", "opus", "mpeg", "mp3", "aac"].iter().any(|codec| content_type.contains(codec)) {
+        return Err(format!("can't decode {} without a decoder, only wav streams are read directly", content_type));
+    }
+    info!(content_type = %content_type, "connected to stream");
+
+    let mut chunks = match decoder {
+        Some(command) => decode(command, response.into_body())?,
+        None => body_chunks(response.into_body())
+    };
+    let mut reader = WavReader::default();
+    let started = Instant::now();
+    let mut played = Duration::ZERO;
+
+    while let Some(bytes) = chunks.next().await {
+        let samples = reader.push(&bytes?)?;
+        let current = match reader.format {
+            Some(current) => current,
+            None => continue
+        };
+
+        if *format != Some(current) {
+            info!(channels = current.channels, sample_rate = current.sample_rate, format = ?current.sample_format, "stream format");
+            let config = crate::BinMessages::BinConfig(crate::BinStreamConfig { channels: current.channels, sample_rate: current.sample_rate, buffer_size: 4096 });
+
+            if sender.send(bincode::serialize(&config).map_err(|e| e.to_string())?).await.is_err() {
+                return Ok(());
+            }
+            *format = Some(current);
+        }
+
+        if samples.is_empty() {
+            continue;
+        }
+
+        played += Duration::from_secs_f64(samples.len() as f64 / (current.channels as f64 * current.sample_rate.max(1) as f64));
+        let message = bincode::serialize(&crate::BinMessages::BinData(samples)).map_err(|e| e.to_string())?;
+        if sender.send(message).await.is_err() {
+            return Ok(());
+        }
+
+        // live streams come in at the pace they play, files come in as fast as the network goes
+        if let Some(ahead) = played.checked_sub(started.elapsed() + MAX_AHEAD) {
+            tokio::time::sleep(ahead).await;
+        }
+    }
+
+    Ok(())
+}
+
+fn body_chunks(body: hyper::Body) -> Chunks {
+    Box::pin(futures::stream::unfold(body, |mut body| async move {
+        let chunk = body.data().await?;
+        Some((chunk.map(|bytes| bytes.to_vec()).map_err(|e| e.to_string()), body))
+    }))
+}
+
+// `body` piped through `command`, whose stdout is read as wav. the decoder is killed once its
+// output is dropped
+fn decode(command: &[String], body: hyper::Body) -> Result<Chunks, String> {
+    let mut child = tokio::process::Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("could not start decoder {} due to {}", command[0], e))?;
+    let mut stdin = child.stdin.take().expect("decoder was started with piped stdin!");
+    let stdout = child.stdout.take().expect("decoder was started with piped stdout!");
+
+    // ends with the body, or when the decoder is gone and writing fails
+    tokio::spawn(async move {
+        let mut body = body;
+        while let Some(Ok(bytes)) = body.data().await {
+            if stdin.write_all(&bytes).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok(Box::pin(futures::stream::unfold((child, stdout), |(child, mut stdout)| async move {
+        let mut buffer = vec![0; DECODER_READ];
+        match stdout.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((Ok(buffer), (child, stdout)))
+            },
+            Err(e) => Some((Err(format!("could not read decoder output due to {}", e)), (child, stdout)))
+        }
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::extract::State;
+    use axum::http::header;
+    use axum::routing::get;
+    use axum::Router;
+
+    use super::*;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+
+        chunk
+    }
+
+    fn format(tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+
+        let mut format = vec![];
+        format.extend_from_slice(&tag.to_le_bytes());
+        format.extend_from_slice(&channels.to_le_bytes());
+        format.extend_from_slice(&sample_rate.to_le_bytes());
+        format.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        format.extend_from_slice(&block_align.to_le_bytes());
+        format.extend_from_slice(&bits.to_le_bytes());
+
+        format
+    }
+
+    // a live wav, the data chunk claiming any size
+    fn wav(chunks: &[Vec<u8>], data: &[u8]) -> Vec<u8> {
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&u32::MAX.to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        for chunk in chunks {
+            wav.extend_from_slice(chunk);
+        }
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&u32::MAX.to_le_bytes());
+        wav.extend_from_slice(data);
+
+        wav
+    }
+
+    fn int16(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|sample| sample.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn header_split_anywhere() {
+        let wav = wav(&[chunk(b"fmt ", &format(1, 2, 8_000, 16))], &int16(&[0, 16_384, -16_384, 8_192]));
+
+        let mut reader = WavReader::default();
+        let mut samples = vec![];
+        for byte in wav.iter() {
+            samples.extend(reader.push(&[*byte]).unwrap());
+        }
+
+        assert_eq!(reader.format, Some(WavFormat { channels: 2, sample_rate: 8_000, sample_format: SampleFormat::Int16 }));
+        assert_eq!(samples, vec![0.0, 0.5, -0.5, 0.25]);
+    }
+
+    #[test]
+    fn odd_chunks_are_padded() {
+        let wav = wav(&[chunk(b"LIST", b"abc"), chunk(b"fmt ", &format(1, 1, 8_000, 16)), chunk(b"junk", b"x")], &int16(&[16_384]));
+
+        let mut reader = WavReader::default();
+        assert_eq!(reader.push(&wav).unwrap(), vec![0.5]);
+    }
+
+    #[test]
+    fn reads_24_bit() {
+        // 0x400000 and -0x400000, half scale
+        let wav = wav(&[chunk(b"fmt ", &format(1, 1, 8_000, 24))], &[0x00, 0x00, 0x40, 0x00, 0x00, 0xc0]);
+
+        let mut reader = WavReader::default();
+        assert_eq!(reader.push(&wav).unwrap(), vec![0.5, -0.5]);
+        assert_eq!(reader.format.unwrap().sample_format, SampleFormat::Int24);
+    }
+
+    #[test]
+    fn reads_extensible() {
+        // cb size, valid bits, channel mask, then the float sub format guid
+        let mut extensible = format(0xfffe, 1, 8_000, 32);
+        extensible.extend_from_slice(&22u16.to_le_bytes());
+        extensible.extend_from_slice(&32u16.to_le_bytes());
+        extensible.extend_from_slice(&4u32.to_le_bytes());
+        extensible.extend_from_slice(&3u16.to_le_bytes());
+        extensible.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71]);
+
+        let wav = wav(&[chunk(b"fmt ", &extensible)], &0.75f32.to_le_bytes());
+
+        let mut reader = WavReader::default();
+        assert_eq!(reader.push(&wav).unwrap(), vec![0.75]);
+        assert_eq!(reader.format.unwrap().sample_format, SampleFormat::Float32);
+    }
+
+    // serves `body` as `content_type` until dropped, counting requests
+    async fn stand_in(content_type: &'static str, body: Vec<u8>) -> (String, Arc<AtomicUsize>, tokio::sync::oneshot::Sender<()>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/stream", get(move |State((requests, body)): State<(Arc<AtomicUsize>, Vec<u8>)>| async move {
+                requests.fetch_add(1, Ordering::Relaxed);
+                ([(header::CONTENT_TYPE, content_type)], body)
+            }))
+            .with_state((Arc::clone(&requests), body));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        let (stop, stopped) = tokio::sync::oneshot::channel();
+        let server = axum::Server::from_tcp(listener).unwrap()
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(async move {
+                let _ = stopped.await;
+            });
+        tokio::spawn(server);
+
+        (url, requests, stop)
+    }
+
+    // what the connection reads until `samples` arrived, as (configs, samples)
+    async fn read(transport: &mut IngestTransport, samples: usize) -> (usize, usize) {
+        let (mut configs, mut received) = (0, 0);
+
+        while received < samples {
+            let message = tokio::time::timeout(Duration::from_secs(5), transport.recv()).await
+                .expect("ingest stalled!")
+                .expect("ingest ended!")
+                .unwrap();
+
+            match bincode::deserialize(&message).unwrap() {
+                crate::BinMessages::BinConfig(_) => configs += 1,
+                crate::BinMessages::BinData(data) => received += data.len(),
+                _ => {}
+            }
+        }
+
+        (configs, received)
+    }
+
+    #[tokio::test]
+    async fn reconnects_when_the_stream_ends() {
+        let (url, requests, _stop) = stand_in("audio/wav", wav(&[chunk(b"fmt ", &format(1, 1, 8_000, 16))], &int16(&[0; 80]))).await;
+
+        let mut config = IngestConfig::new(&url);
+        config.reconnect_interval = Duration::from_millis(50);
+        let mut transport = IngestTransport::open(config).unwrap();
+
+        // the stream ends twice on the way, its unchanged format is only sent once
+        let (configs, received) = read(&mut transport, 3 * 80).await;
+        assert_eq!((configs, received), (1, 3 * 80));
+        assert!(requests.load(Ordering::Relaxed) >= 3);
+    }
+
+    #[tokio::test]
+    async fn decodes_through_the_decoder() {
+        // `cat` stands in for ffmpeg, the stream is wav under an ogg content type
+        let (url, _requests, _stop) = stand_in("audio/ogg", wav(&[chunk(b"fmt ", &format(1, 1, 8_000, 16))], &int16(&[0; 80]))).await;
+
+        let mut config = IngestConfig::new(&url);
+        config.decoder = Some(vec!["cat".to_owned()]);
+        let mut transport = IngestTransport::open(config).unwrap();
+
+        assert_eq!(read(&mut transport, 80).await, (1, 80));
+    }
+}
@@ -2,6 +2,27 @@ use serde::{Serialize, Deserialize};
 
 pub mod client;
 pub mod server;
+pub mod quic;
+pub mod plc;
+pub mod fec;
+pub mod heartbeat;
+pub mod stats;
+pub mod metrics;
+pub mod logging;
+pub mod identity;
+pub mod devices;
+pub mod routing;
+pub mod mixer;
+pub mod capture;
+pub mod aec;
+pub mod broadcast;
+pub mod rooms;
+pub mod sources;
+pub mod relay;
+pub mod browser;
+pub mod http_stream;
+pub mod ingest;
+pub mod pipe;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BinStreamConfig {
@@ -11,6 +32,16 @@ pub struct BinStreamConfig {
 
 }
 
+impl From<&cpal::StreamConfig> for BinStreamConfig {
+    fn from(config: &cpal::StreamConfig) -> Self {
+        BinStreamConfig {
+            channels: config.channels,
+            sample_rate: config.sample_rate.0,
+            buffer_size: 4096,
+        }
+    }
+}
+
 
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -20,4 +51,27 @@ pub enum BinMessages {
     BinData(Vec<f32>),
     BinDevicesResponse(Vec<String>),
     BinDevicesRequest,
+    BinFrame(fec::BinFrame),
+    BinParity(fec::BinParity),
+    BinLossReport(fec::BinLossReport),
+    BinPing(u64),
+    BinPong(u64),
+    // first message from clients that have one, before the config
+    BinIdentify(identity::BinClientIdentity),
+    // sent instead of a config by clients that play the server's input, the server answers with
+    // its config and then streams `BinData`
+    BinListen,
+    // sent instead of a config by clients that play a named stream, the server's input or another
+    // client's audio (see `broadcast`). answered like `BinListen`
+    BinSubscribe(String),
+    // the room a client joins, sent before its config or listen request. clients that don't send
+    // one are in the default room
+    BinJoin(String),
+    // another capture device on the same connection, see `sources`
+    BinSourceConfig(sources::BinSource),
+    BinSourceData(u16, Vec<f32>),
+    BinSourceClose(u16),
+    // sent by relays after the identity, the servers the stream went through so far. sent again
+    // whenever their latencies change, see `relay`
+    BinRelay(Vec<relay::BinHop>),
 }
\ No newline at end of file
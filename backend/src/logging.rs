@@ -0,0 +1,116 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+// log setup for the binaries, the backend itself only emits `tracing` events
+//
+// every write goes through a background writer thread, so whatever thread logs never waits on
+// stdout or the disk. audio callbacks do not log at all, they hand errors to `device_error_channel`
+// and bump counters, and a task logs them later
+
+// device errors that can pile up before the callback starts dropping them
+static DEVICE_ERROR_BACKLOG: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    // human readable, coloured on a terminal
+    Pretty,
+    // one json object per line, with the enclosing connection span attached
+    Json,
+}
+
+#[derive(Clone, Debug)]
+pub enum LogOutput {
+    Stdout,
+    File(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    // `EnvFilter` directives, e.g. `info` or `remoteio_backend::server=debug,warn`
+    pub filter: String,
+    pub format: LogFormat,
+    pub output: LogOutput,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            filter: "info".to_owned(),
+            format: LogFormat::Pretty,
+            output: LogOutput::Stdout,
+        }
+    }
+}
+
+impl LogConfig {
+    // REMOTEIO_LOG (falling back to RUST_LOG), REMOTEIO_LOG_FORMAT=pretty|json and REMOTEIO_LOG_FILE
+    pub fn from_env() -> Self {
+        let default = LogConfig::default();
+
+        LogConfig {
+            filter: std::env::var("REMOTEIO_LOG")
+                .or_else(|_| std::env::var("RUST_LOG"))
+                .unwrap_or(default.filter),
+            format: match std::env::var("REMOTEIO_LOG_FORMAT").as_deref() {
+                Ok("json") => LogFormat::Json,
+                _ => default.format
+            },
+            output: match std::env::var("REMOTEIO_LOG_FILE") {
+                Ok(path) => LogOutput::File(PathBuf::from(path)),
+                Err(_) => default.output
+            },
+        }
+    }
+}
+
+// install the global subscriber, logs stop being written once the returned guard is dropped
+pub fn init(config: &LogConfig) -> Result<WorkerGuard, Box<dyn Error>> {
+    let (writer, guard) = match &config.output {
+        LogOutput::Stdout => tracing_appender::non_blocking(std::io::stdout()),
+        LogOutput::File(path) => {
+            let directory = path.parent().map(PathBuf::from).unwrap_or_default();
+            let file_name = path.file_name().ok_or("log file path has no file name")?;
+
+            tracing_appender::non_blocking(tracing_appender::rolling::never(directory, file_name))
+        }
+    };
+
+    let layer = match config.format {
+        LogFormat::Pretty => fmt::layer()
+            .with_writer(writer)
+            .with_ansi(matches!(config.output, LogOutput::Stdout))
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_new(&config.filter)?)
+        .with(layer)
+        .try_init()?;
+
+    Ok(guard)
+}
+
+// `try_send` on the bounded channel neither locks nor allocates, so audio error callbacks can use it
+pub fn device_error_channel() -> (SyncSender<cpal::StreamError>, Receiver<cpal::StreamError>) {
+    sync_channel(DEVICE_ERROR_BACKLOG)
+}
+
+// `StreamError` isn't `Clone`, a device shared by several connections reports to each of them
+pub fn copy_stream_error(e: &cpal::StreamError) -> cpal::StreamError {
+    match e {
+        cpal::StreamError::DeviceNotAvailable => cpal::StreamError::DeviceNotAvailable,
+        cpal::StreamError::BackendSpecific { err } => cpal::StreamError::BackendSpecific { err: err.clone() },
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::server::Client;
+use crate::stats::StreamStats;
+
+// server wide counters and the prometheus text rendering of them
+//
+// per client numbers come from each connection's `StreamStats`, these are the ones that have to
+// outlive a single connection
+
+// client ids remembered for spotting reconnects, the ones that connected longest ago are forgotten
+// first
+static MAX_SEEN: usize = 4096;
+
+#[derive(Default)]
+pub struct ServerMetrics {
+    connections: AtomicU64,
+    reconnects: AtomicU64,
+    device_errors: AtomicU64,
+    // client ids that have connected before, a second connection from one counts as a reconnect
+    seen: Mutex<Seen>,
+}
+
+#[derive(Default)]
+struct Seen {
+    ids: HashSet<String>,
+    // `ids` by when they were first seen
+    order: VecDeque<String>,
+}
+
+impl Seen {
+    // false if `id` was seen before
+    fn insert(&mut self, id: &str) -> bool {
+        if self.ids.contains(id) {
+            return false;
+        }
+
+        if self.order.len() >= MAX_SEEN {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(id.to_owned());
+        self.order.push_back(id.to_owned());
+
+        true
+    }
+}
+
+impl ServerMetrics {
+    pub fn connected(&self, client: &Client) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+
+        if !self.seen.lock().unwrap().insert(&client.id) {
+            self.reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // called from the output stream's error callback
+    pub fn device_error(&self) {
+        self.device_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, clients: &[(Client, StreamStats)]) -> String {
+        let mut out = String::new();
+
+        metric(&mut out, "remoteio_connected_clients", "gauge", "Clients currently connected.");
+        // a client's extra sources have stats of their own but share its connection
+        let _ = writeln!(out, "remoteio_connected_clients {}", clients.iter().filter(|(client, _)| client.parent.is_none()).count());
+
+        metric(&mut out, "remoteio_connections_total", "counter", "Connections accepted since the server started.");
+        let _ = writeln!(out, "remoteio_connections_total {}", self.connections.load(Ordering::Relaxed));
+
+        metric(&mut out, "remoteio_reconnects_total", "counter", "Connections from a client that had connected before.");
+        let _ = writeln!(out, "remoteio_reconnects_total {}", self.reconnects.load(Ordering::Relaxed));
+
+        metric(&mut out, "remoteio_output_device_errors_total", "counter", "Errors reported by output devices, all clients.");
+        let _ = writeln!(out, "remoteio_output_device_errors_total {}", self.device_errors.load(Ordering::Relaxed));
+
+        per_client(&mut out, clients, "remoteio_client_bitrate_bits_per_second", "gauge", "Incoming bitrate over the last second.", |stats| Some(stats.receive_bitrate_bps as f64));
+        per_client(&mut out, clients, "remoteio_client_received_bytes_total", "counter", "Bytes received from the client.", |stats| Some(stats.bytes_received as f64));
+        per_client(&mut out, clients, "remoteio_client_rtt_seconds", "gauge", "Heartbeat round trip time.", |stats| stats.rtt_ms.map(|rtt| rtt as f64 / 1_000.0));
+        per_client(&mut out, clients, "remoteio_client_jitter_seconds", "gauge", "Smoothed packet arrival jitter.", |stats| Some(stats.jitter_ms as f64 / 1_000.0));
+        per_client(&mut out, clients, "remoteio_client_buffer_frames", "gauge", "Frames queued for playback.", |stats| Some(stats.buffer_frames as f64));
+        per_client(&mut out, clients, "remoteio_client_buffer_seconds", "gauge", "Audio queued for playback.", |stats| Some(stats.buffer_ms as f64 / 1_000.0));
+        per_client(&mut out, clients, "remoteio_client_underruns_total", "counter", "Output callbacks that ran out of audio.", |stats| Some(stats.underruns as f64));
+        per_client(&mut out, clients, "remoteio_client_overruns_total", "counter", "Output callbacks with more than a batch queued behind them.", |stats| Some(stats.overruns as f64));
+        per_client(&mut out, clients, "remoteio_client_concealed_frames_total", "counter", "Frames filled in by loss concealment.", |stats| Some(stats.concealed_frames as f64));
+        per_client(&mut out, clients, "remoteio_client_output_device_errors_total", "counter", "Errors reported by the client's output device.", |stats| Some(stats.device_errors as f64));
+        per_client(&mut out, clients, "remoteio_client_loss_ratio", "gauge", "Datagram loss before parity recovery.", |stats| stats.fec.map(|fec| fec.loss_rate as f64));
+
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn per_client(out: &mut String, clients: &[(Client, StreamStats)], name: &str, kind: &str, help: &str, value: impl Fn(&StreamStats) -> Option<f64>) {
+    metric(out, name, kind, help);
+
+    for (client, stats) in clients {
+        if let Some(value) = value(stats) {
+            let _ = writeln!(out, "{name}{{client=\"{}\",name=\"{}\"}} {value}", escape(&client.id), escape(&client.name));
+        }
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::server::ClientRole;
+
+    fn client(id: &str, name: &str) -> Client {
+        Client {
+            url: "127.0.0.1:9000".to_owned(),
+            id: id.to_owned(),
+            name: name.to_owned(),
+            role: ClientRole::Source,
+            stream: None,
+            room: "default".to_owned(),
+            parent: None,
+            hops: vec![],
+        }
+    }
+
+    #[test]
+    fn a_second_connection_counts_as_a_reconnect() {
+        let metrics = ServerMetrics::default();
+        metrics.connected(&client("a", "a"));
+        metrics.connected(&client("b", "b"));
+        metrics.connected(&client("a", "a"));
+
+        let rendered = metrics.render(&[]);
+        assert!(rendered.contains("remoteio_connections_total 3\n"));
+        assert!(rendered.contains("remoteio_reconnects_total 1\n"));
+    }
+
+    #[test]
+    fn seen_ids_are_bounded() {
+        let mut seen = Seen::default();
+        for i in 0..MAX_SEEN + 10 {
+            assert!(seen.insert(&i.to_string()));
+        }
+
+        assert_eq!(seen.ids.len(), MAX_SEEN);
+        assert_eq!(seen.order.len(), MAX_SEEN);
+        // the oldest were forgotten, the newest are still known
+        assert!(seen.insert("0"));
+        assert!(!seen.insert(&(MAX_SEEN + 9).to_string()));
+    }
+
+    #[test]
+    fn render_writes_prometheus_text() {
+        let metrics = ServerMetrics::default();
+        metrics.device_error();
+
+        let source = client("a", "studio \"left\"");
+        let stats = StreamStats { bytes_received: 1234, rtt_ms: Some(20.0), ..Default::default() };
+        let mut extra = client("a/1", "extra");
+        extra.parent = Some("a".to_owned());
+
+        let rendered = metrics.render(&[(source, stats), (extra, StreamStats::default())]);
+
+        assert!(rendered.contains("# HELP remoteio_connected_clients Clients currently connected.\n# TYPE remoteio_connected_clients gauge\n"));
+        // extra sources share their parent's connection
+        assert!(rendered.contains("remoteio_connected_clients 1\n"));
+        assert!(rendered.contains("remoteio_output_device_errors_total 1\n"));
+        assert!(rendered.contains("remoteio_client_received_bytes_total{client=\"a\",name=\"studio \\\"left\\\"\"} 1234\n"));
+        assert!(rendered.contains("remoteio_client_rtt_seconds{client=\"a\",name=\"studio \\\"left\\\"\"} 0.02\n"));
+        // no rtt yet, no sample
+        assert!(!rendered.contains("remoteio_client_rtt_seconds{client=\"a/1\""));
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        assert_eq!(escape("a\\b\"c\nd"), "a\\\\b\\\"c\\nd");
+    }
+}
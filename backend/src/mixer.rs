@@ -0,0 +1,433 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::time::Duration;
+
+use cpal::StreamConfig;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use tracing::{debug, info};
+
+use crate::aec::{EchoReference, ReferenceWriter};
+use crate::devices::DeviceId;
+use crate::metrics::ServerMetrics;
+use crate::pipe::{PipeSink, PipeSinks, PipeWriter};
+use crate::plc::Concealer;
+use crate::routing::{ChannelLink, RouteOutput};
+use crate::stats::StreamCounters;
+
+// output devices shared between clients
+//
+// every device in use has one stream open at its own config, and each client routed to it adds a
+// tap: a queue of the client's audio with its own concealment, resampling to the device rate and
+// channel links. the device callback sums whatever every tap has into the block. an output with a
+// pipe gets a tap of its own that writes as it is pushed, see `pipe`
+
+// audio queued before playback starts, covers input and output devices not being in sync
+pub static LATENCY: Duration = Duration::from_millis(150);
+// how long playback takes to fade to silence when the server shuts down
+pub static FADE_OUT: Duration = Duration::from_millis(50);
+// a tap holds at most this many times `LATENCY`, a client outrunning its device loses the oldest
+static MAX_QUEUED: usize = 4;
+
+// what a connection hands over to play one of its outputs
+pub struct TapSource {
+    // the client's stream config
+    pub config: StreamConfig,
+    // the first output of a connection meters its audio and reports its buffer, the others would
+    // count the same audio again
+    pub primary: bool,
+    pub stats: Arc<StreamCounters>,
+    pub concealed_frames: Arc<AtomicU64>,
+    pub fade_out: Arc<AtomicBool>,
+    pub device_errors: SyncSender<cpal::StreamError>,
+    // gets what this tap plays, for cancelling its echo on a local microphone
+    pub echo_reference: Option<EchoReference>,
+}
+
+pub struct Tap {
+    // what `Mixers` finds its mixer by, `None` for the default output
+    device_id: Option<DeviceId>,
+    // name of the device or pipe it plays on
+    device: String,
+    // `None` when playing on a pipe instead
+    state: Option<Mutex<TapState>>,
+    pipe: Option<PipeWriter>,
+    primary: bool,
+    stats: Arc<StreamCounters>,
+    fade_out: Arc<AtomicBool>,
+    device_errors: SyncSender<cpal::StreamError>,
+}
+
+struct TapState {
+    queue: VecDeque<f32>,
+    channels: usize,
+    overrun_samples: usize,
+    max_queued_samples: usize,
+    concealer: Concealer,
+    // (client channel, device channel, gain)
+    links: Vec<(usize, usize, f32)>,
+    // client frames per device frame
+    ratio: f64,
+    // position of the next device frame, in client frames from the start of `input`
+    phase: f64,
+    // client frames waiting to be resampled, the first one is the last frame of the previous block
+    input: Vec<f32>,
+    incoming: Vec<f32>,
+    fade_gain: f32,
+    fade_step: f32,
+    // conceals holes the transport reported, in order with the queued audio
+    ingress: Concealer,
+    // what `ingress` hands over, before it is queued
+    received: Vec<f32>,
+    echo_reference: Option<ReferenceWriter>,
+    // this tap's share of the block mixed down to mono, for `echo_reference`
+    echo_block: Vec<f32>,
+}
+
+impl TapState {
+    // queue what `ingress` handed over, dropping the oldest audio past the cap
+    fn enqueue(&mut self) {
+        self.queue.extend(self.received.iter().copied());
+
+        if self.queue.len() > self.max_queued_samples {
+            let excess = self.queue.len() - self.max_queued_samples;
+            self.queue.drain(..excess);
+        }
+    }
+}
+
+impl Tap {
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    // queue audio from the client, interleaved in its channel layout
+    pub fn push(&self, data: &[f32]) {
+        match (&self.state, &self.pipe) {
+            (Some(state), _) => {
+                let mut state = state.lock().unwrap();
+                let state = &mut *state;
+
+                // passes the audio through, crossfading out of a hole that was just concealed
+                state.received.clear();
+                state.received.resize(data.len(), 0.0);
+                state.ingress.process(&mut state.received, Some(data));
+                state.enqueue();
+            },
+            (None, Some(pipe)) => pipe.write(data),
+            (None, None) => {}
+        }
+    }
+
+    // `samples` were lost on the way, queue a concealment of them where they would have been
+    pub fn push_missing(&self, samples: usize) {
+        let mut state = match &self.state {
+            Some(state) => state.lock().unwrap(),
+            None => return
+        };
+        let state = &mut *state;
+
+        let frames = samples / state.channels;
+        state.received.clear();
+        state.received.resize(frames * state.channels, 0.0);
+        state.ingress.process(&mut state.received, None);
+        state.enqueue();
+    }
+
+    // add this tap's share of a device block
+    fn mix(&self, data: &mut [f32], device_channels: usize) {
+        let mut state = match &self.state {
+            Some(state) => state.lock().unwrap(),
+            None => return
+        };
+        let state = &mut *state;
+        let channels = state.channels;
+        let frames = data.len() / device_channels;
+
+        if frames == 0 {
+            return;
+        }
+
+        if state.queue.len() >= state.overrun_samples && self.primary {
+            self.stats.overrun();
+        }
+
+        // client frames this block interpolates between, and how far the next block starts
+        let last = state.phase + (frames - 1) as f64 * state.ratio;
+        let consumed = (state.phase + frames as f64 * state.ratio).floor() as usize;
+        let required = (last.floor() as usize + 2).max(consumed);
+        let available = state.input.len() / channels;
+
+        if required > available {
+            let missing = (required - available) * channels;
+            let queued = missing.min(state.queue.len());
+
+            state.incoming.clear();
+            state.incoming.extend(state.queue.drain(..queued));
+            if queued < missing && self.primary {
+                self.stats.underrun();
+            }
+
+            let start = state.input.len();
+            state.input.resize(start + missing, 0.0);
+            // missing frames are concealed instead of playing silence or stale samples
+            state.concealer.process(&mut state.input[start..], Some(&state.incoming));
+
+            if self.primary {
+                self.stats.meter(&state.input[start..], channels);
+            }
+        }
+
+        if self.primary {
+            self.stats.set_buffer_frames((state.queue.len() / channels) as u64);
+        }
+
+        let fading = self.fade_out.load(Ordering::Relaxed);
+        state.echo_block.clear();
+
+        for (frame, output) in data.chunks_mut(device_channels).enumerate() {
+            let position = state.phase + frame as f64 * state.ratio;
+            let index = position.floor() as usize;
+            let fraction = (position - index as f64) as f32;
+
+            if fading {
+                state.fade_gain = (state.fade_gain - state.fade_step).max(0.0);
+            }
+
+            let mut played = 0.0;
+            for (from, to, gain) in state.links.iter() {
+                let a = state.input[index * channels + from];
+                let b = state.input[(index + 1) * channels + from];
+                let sample = (a + (b - a) * fraction) * gain * state.fade_gain;
+
+                output[*to] += sample;
+                played += sample;
+            }
+
+            if state.echo_reference.is_some() {
+                state.echo_block.push(played / device_channels as f32);
+            }
+        }
+
+        if let Some(reference) = &mut state.echo_reference {
+            reference.push(&state.echo_block);
+        }
+
+        state.input.drain(..consumed * channels);
+        state.phase += frames as f64 * state.ratio - consumed as f64;
+    }
+
+    // called from the device's error callback, handled by the connection's task
+    fn device_error(&self, e: &cpal::StreamError) {
+        self.stats.device_error();
+
+        let _ = self.device_errors.try_send(crate::logging::copy_stream_error(e));
+    }
+}
+
+// client channel -> device channel links, dropping any that point past either layout
+fn resolve_links(links: &Option<Vec<ChannelLink>>, gain: f32, channels: usize, device_channels: usize) -> Vec<(usize, usize, f32)> {
+    match links {
+        Some(links) => links
+            .iter()
+            .map(|link| (link.from as usize, link.to as usize, link.gain * gain))
+            .filter(|(from, to, _)| *from < channels && *to < device_channels)
+            .collect(),
+        None if channels == 1 => (0..device_channels).map(|to| (0, to, gain)).collect(),
+        None => (0..channels.min(device_channels)).map(|channel| (channel, channel, gain)).collect(),
+    }
+}
+
+pub struct Mixer {
+    config: StreamConfig,
+    taps: Arc<Mutex<Vec<Arc<Tap>>>>,
+    stream: cpal::Stream,
+}
+
+unsafe impl Send for Mixer {}
+unsafe impl Sync for Mixer {}
+
+impl Drop for Mixer {
+    fn drop(&mut self) {
+        let _ = self.stream.pause();
+        debug!("closing output device");
+    }
+}
+
+impl Mixer {
+    fn open(device: &cpal::Device, metrics: &Option<Arc<ServerMetrics>>) -> Result<Mixer, Box<dyn Error>> {
+        let config: StreamConfig = device.default_output_config()?.into();
+        let taps: Arc<Mutex<Vec<Arc<Tap>>>> = Arc::new(Mutex::new(vec![]));
+
+        let stream_taps = Arc::clone(&taps);
+        let error_taps = Arc::clone(&taps);
+        let error_metrics = metrics.clone();
+        let device_channels = config.channels.max(1) as usize;
+
+        let stream = device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _| {
+                data.fill(0.0);
+
+                for tap in stream_taps.lock().unwrap().iter() {
+                    tap.mix(data, device_channels);
+                }
+            },
+            move |e| {
+                if let Some(metrics) = &error_metrics {
+                    metrics.device_error();
+                }
+
+                for tap in error_taps.lock().unwrap().iter() {
+                    tap.device_error(&e);
+                }
+            },
+            Some(Duration::from_secs(5)),
+        )?;
+        stream.play()?;
+
+        Ok(Mixer { config, taps, stream })
+    }
+}
+
+// every output device currently playing, opened on first use and closed with its last tap
+#[derive(Clone, Default)]
+pub struct Mixers {
+    open: Arc<Mutex<HashMap<Option<DeviceId>, Mixer>>>,
+    // server wide device error count, clients don't keep one
+    metrics: Option<Arc<ServerMetrics>>,
+    // what route outputs with a pipe write to
+    pipes: PipeSinks,
+}
+
+impl Mixers {
+    pub fn new(metrics: Arc<ServerMetrics>, pipes: PipeSinks) -> Self {
+        Mixers {
+            open: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Some(metrics),
+            pipes,
+        }
+    }
+
+    // start playing `source` on `output`'s device, or writing it to its pipe
+    pub fn attach(&self, output: &RouteOutput, source: &TapSource) -> Result<Arc<Tap>, Box<dyn Error>> {
+        match &output.pipe {
+            Some(name) => {
+                let pipe = self.pipes.get(name).ok_or(format!("no pipe sink named {}", name))?;
+                Mixers::attach_pipe(&pipe, output, source)
+            },
+            None => {
+                let (id, device) = output.output_device()?;
+                self.attach_device(id, &device, output, source)
+            }
+        }
+    }
+
+    // pipes aren't shared, every tap opens its own
+    fn attach_pipe(pipe: &PipeSink, output: &RouteOutput, source: &TapSource) -> Result<Arc<Tap>, Box<dyn Error>> {
+        let channels = source.config.channels.max(1) as usize;
+        // as many channels as the links that apply to this client fill, the client's own without any
+        let pipe_channels = match &output.links {
+            Some(_) => resolve_links(&output.links, output.gain, channels, usize::MAX)
+                .iter()
+                .map(|(_, to, _)| to + 1)
+                .max()
+                .unwrap_or(channels),
+            None => channels,
+        };
+        let links = resolve_links(&output.links, output.gain, channels, pipe_channels);
+
+        Ok(Arc::new(Tap {
+            device_id: None,
+            device: pipe.to_string(),
+            state: None,
+            pipe: Some(PipeWriter::open(pipe, channels, pipe_channels, links, source.config.sample_rate.0, source.primary, Arc::clone(&source.stats))?),
+            primary: source.primary,
+            stats: Arc::clone(&source.stats),
+            fade_out: Arc::clone(&source.fade_out),
+            device_errors: source.device_errors.clone(),
+        }))
+    }
+
+    // same as `attach` but on a device the caller picked, `output.device` is ignored. taps with
+    // the same `id` share a mixer, `None` being the default output
+    pub fn attach_device(&self, id: Option<DeviceId>, device: &cpal::Device, output: &RouteOutput, source: &TapSource) -> Result<Arc<Tap>, Box<dyn Error>> {
+        let name = device.name()?;
+
+        let mut open = self.open.lock().unwrap();
+        if !open.contains_key(&id) {
+            open.insert(id.clone(), Mixer::open(device, &self.metrics)?);
+            info!(device = %name, "opened output device");
+        }
+        let mixer = &open[&id];
+
+        let channels = source.config.channels.max(1) as usize;
+        let device_channels = mixer.config.channels.max(1) as usize;
+        let latency = (LATENCY.as_secs_f32() * source.config.sample_rate.0 as f32) as usize * channels;
+
+        // frames concealed on every other output are the same ones, only the primary counts them
+        let concealed_frames = match source.primary {
+            true => Arc::clone(&source.concealed_frames),
+            false => Arc::new(AtomicU64::new(0)),
+        };
+
+
+        let tap = Arc::new(Tap {
+            device_id: id,
+            device: name,
+            state: Some(Mutex::new(TapState {
+                queue: std::iter::repeat_n(0.0, latency).collect(),
+                channels,
+                // more than twice the latency queued means the client is outrunning the device
+                overrun_samples: 2 * latency.max(channels),
+                max_queued_samples: MAX_QUEUED * latency.max(channels),
+                concealer: Concealer::new(source.config.channels, source.config.sample_rate.0, Arc::clone(&concealed_frames)),
+                links: resolve_links(&output.links, output.gain, channels, device_channels),
+                ratio: source.config.sample_rate.0 as f64 / mixer.config.sample_rate.0.max(1) as f64,
+                phase: 0.0,
+                input: Vec::with_capacity(8192),
+                incoming: Vec::with_capacity(8192),
+                fade_gain: 1.0,
+                fade_step: 1.0 / (FADE_OUT.as_secs_f32() * mixer.config.sample_rate.0 as f32).max(1.0),
+                ingress: Concealer::new(source.config.channels, source.config.sample_rate.0, concealed_frames),
+                received: Vec::with_capacity(8192),
+                echo_reference: source.echo_reference.as_ref().map(|reference| reference.writer(mixer.config.sample_rate.0)),
+                echo_block: Vec::with_capacity(8192),
+            })),
+            pipe: None,
+            primary: source.primary,
+            stats: Arc::clone(&source.stats),
+            fade_out: Arc::clone(&source.fade_out),
+            device_errors: source.device_errors.clone(),
+        });
+
+        mixer.taps.lock().unwrap().push(Arc::clone(&tap));
+
+        Ok(tap)
+    }
+
+    pub fn detach(&self, tap: &Arc<Tap>) {
+        // pipes close with their tap
+        if tap.state.is_none() {
+            return;
+        }
+
+        let mut open = self.open.lock().unwrap();
+
+        let unused = match open.get(&tap.device_id) {
+            Some(mixer) => {
+                let mut taps = mixer.taps.lock().unwrap();
+                taps.retain(|other| !Arc::ptr_eq(other, tap));
+                taps.is_empty()
+            },
+            None => false
+        };
+
+        if unused {
+            open.remove(&tap.device_id);
+            info!(device = %tap.device, "closed output device");
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn};
+
+use crate::stats::StreamCounters;
+
+// raw pcm out to other programs instead of a sound card
+//
+// a route output with a pipe writes the client's audio to a fifo, a file descriptor or the stdin of
+// a program the server starts, for ffmpeg, sox or speech recognition on a machine without a sound
+// card. it is written as it arrives, at the client's sample rate, in the output's channel layout
+// and sample format, with nothing in between: no mixing with other clients, no concealment. a
+// thread per pipe does the writing, a reader that can't keep up loses blocks rather than holding up
+// the connection. a config change reopens the pipe, which restarts a program
+//
+// pipes start programs and write to the server's descriptors, so they are only ever set up on the
+// server itself (`Server::add_pipe_sink`). routing rules, which anyone reaching the rest endpoint
+// can replace, only name one of those
+
+// blocks queued for a pipe before new ones are dropped
+static PIPE_BACKLOG: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PcmFormat {
+    // 32 bit float, little endian, `f32le` to ffmpeg and sox
+    F32Le,
+    S16Le,
+    S32Le,
+}
+
+impl PcmFormat {
+    fn encode(&self, sample: f32, bytes: &mut Vec<u8>) {
+        let sample = sample.clamp(-1.0, 1.0);
+
+        match self {
+            PcmFormat::F32Le => bytes.extend_from_slice(&sample.to_le_bytes()),
+            PcmFormat::S16Le => bytes.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes()),
+            PcmFormat::S32Le => bytes.extend_from_slice(&((sample as f64 * i32::MAX as f64) as i32).to_le_bytes()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PipeTarget {
+    // a fifo made with `mkfifo`, opening it waits for a reader. a regular file is appended to
+    Path(PathBuf),
+    // a descriptor the server inherited, `1` for its stdout. it is duplicated, never closed
+    Fd(i32),
+    // a program and its arguments, started with the pcm on its stdin
+    Command(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PipeSink {
+    pub target: PipeTarget,
+    pub format: PcmFormat,
+}
+
+impl fmt::Display for PipeSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.target {
+            PipeTarget::Path(path) => write!(f, "pipe:{}", path.display()),
+            PipeTarget::Fd(fd) => write!(f, "pipe:fd{}", fd),
+            PipeTarget::Command(command) => write!(f, "pipe:{}", command.join(" ")),
+        }
+    }
+}
+
+// the pipes routes may name, by name
+#[derive(Clone, Default)]
+pub struct PipeSinks {
+    sinks: Arc<Mutex<HashMap<String, PipeSink>>>,
+}
+
+impl PipeSinks {
+    pub fn add(&self, name: &str, sink: PipeSink) -> Result<(), Box<dyn Error>> {
+        if let PipeTarget::Command(command) = &sink.target {
+            if command.is_empty() {
+                return Err("pipe command has no program".into());
+            }
+        }
+
+        let mut sinks = self.sinks.lock().unwrap();
+        if sinks.contains_key(name) {
+            return Err(format!("pipe sink {} already exists", name).into());
+        }
+
+        info!(name, pipe = %sink, "pipe sink added");
+        sinks.insert(name.to_owned(), sink);
+
+        Ok(())
+    }
+
+    // routes naming it stop writing the next time they are applied
+    pub fn remove(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        match self.sinks.lock().unwrap().remove(name) {
+            Some(_) => Ok(()),
+            None => Err(format!("no pipe sink named {}", name).into())
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<PipeSink> {
+        self.sinks.lock().unwrap().get(name).cloned()
+    }
+}
+
+// one client's audio on its way into a pipe
+pub struct PipeWriter {
+    name: String,
+    sender: SyncSender<Vec<u8>>,
+    // the pipe's thread is gone, nothing is encoded for it anymore
+    closed: AtomicBool,
+    format: PcmFormat,
+    channels: usize,
+    pipe_channels: usize,
+    // (client channel, pipe channel, gain)
+    links: Vec<(usize, usize, f32)>,
+    primary: bool,
+    stats: Arc<StreamCounters>,
+}
+
+impl PipeWriter {
+    pub fn open(sink: &PipeSink, channels: usize, pipe_channels: usize, links: Vec<(usize, usize, f32)>, sample_rate: u32, primary: bool, stats: Arc<StreamCounters>) -> Result<Self, Box<dyn Error>> {
+        if let PipeTarget::Command(command) = &sink.target {
+            if command.is_empty() {
+                return Err("pipe command has no program".into());
+            }
+        }
+
+        let (sender, receiver) = sync_channel(PIPE_BACKLOG);
+        let target = sink.target.clone();
+        let name = sink.to_string();
+        let thread_name = name.clone();
+        let span = tracing::Span::current();
+
+        std::thread::Builder::new().name(name.clone()).spawn(move || {
+            let _entered = span.enter();
+            write_pipe(&thread_name, target, receiver);
+        })?;
+
+        info!(pipe = %sink, channels = pipe_channels, sample_rate, format = ?sink.format, "writing pcm to pipe");
+
+        Ok(PipeWriter {
+            name,
+            sender,
+            closed: AtomicBool::new(false),
+            format: sink.format,
+            channels: channels.max(1),
+            pipe_channels: pipe_channels.max(1),
+            links,
+            primary,
+            stats,
+        })
+    }
+
+    // false once the pipe could not be opened or its reader went away, until the route is applied
+    // again
+    fn is_open(&self) -> bool {
+        !self.closed.load(Ordering::Relaxed)
+    }
+
+    // audio from the client, interleaved in its channel layout
+    pub fn write(&self, data: &[f32]) {
+        let frames = data.len() / self.channels;
+        if self.primary {
+            self.stats.meter(data, self.channels);
+        }
+
+        if !self.is_open() {
+            return;
+        }
+
+        let mut mixed = vec![0.0; frames * self.pipe_channels];
+        for (frame, output) in mixed.chunks_mut(self.pipe_channels).enumerate() {
+            for (from, to, gain) in self.links.iter() {
+                output[*to] += data[frame * self.channels + from] * gain;
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(mixed.len() * 4);
+        for sample in mixed {
+            self.format.encode(sample, &mut bytes);
+        }
+
+        match self.sender.try_send(bytes) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => {
+                if self.primary {
+                    self.stats.overrun();
+                }
+            },
+            Err(TrySendError::Disconnected(_)) => {
+                if !self.closed.swap(true, Ordering::Relaxed) {
+                    warn!(pipe = %self.name, "pipe is closed, dropping the client's audio for it");
+                }
+            }
+        }
+    }
+}
+
+enum Opened {
+    File(File),
+    Child(Child),
+}
+
+fn open_target(target: PipeTarget) -> Result<Opened, Box<dyn Error>> {
+    Ok(match target {
+        PipeTarget::Path(path) => Opened::File(OpenOptions::new().create(true).append(true).open(path)?),
+        PipeTarget::Fd(fd) => Opened::File(File::from(duplicate_fd(fd)?)),
+        PipeTarget::Command(command) => Opened::Child(Command::new(&command[0]).args(&command[1..]).stdin(Stdio::piped()).spawn()?),
+    })
+}
+
+#[cfg(unix)]
+fn duplicate_fd(fd: i32) -> Result<std::os::fd::OwnedFd, Box<dyn Error>> {
+    // the descriptor belongs to whoever started the server, only the duplicate is ever closed
+    Ok(unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?)
+}
+
+#[cfg(not(unix))]
+fn duplicate_fd(_fd: i32) -> Result<File, Box<dyn Error>> {
+    Err("file descriptor pipes are only supported on unix".into())
+}
+
+// runs on the pipe's thread until the writer is dropped or the reader goes away
+fn write_pipe(name: &str, target: PipeTarget, receiver: Receiver<Vec<u8>>) {
+    let mut opened = match open_target(target) {
+        Ok(opened) => opened,
+        Err(e) => {
+            warn!(pipe = %name, "could not open pipe due to {}", e);
+            return;
+        }
+    };
+
+    let mut written = Ok(());
+    {
+        let output: &mut dyn Write = match &mut opened {
+            Opened::File(file) => file,
+            Opened::Child(child) => child.stdin.as_mut().expect("child was started with piped stdin!"),
+        };
+
+        for bytes in receiver.iter() {
+            written = output.write_all(&bytes);
+            if written.is_err() {
+                break;
+            }
+        }
+    }
+
+    if let Err(e) = written {
+        warn!(pipe = %name, "stopped writing to pipe due to {}", e);
+    }
+
+    // the program gets end of file and is left to finish what it was doing
+    if let Opened::Child(mut child) = opened {
+        drop(child.stdin.take());
+
+        match child.wait() {
+            Ok(status) => info!(pipe = %name, "pipe program exited with {}", status),
+            Err(e) => warn!(pipe = %name, "could not wait on pipe program due to {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    fn encoded(format: PcmFormat, samples: &[f32]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for sample in samples {
+            format.encode(*sample, &mut bytes);
+        }
+
+        bytes
+    }
+
+    // a writer whose blocks end up in the returned receiver instead of a pipe
+    fn writer(channels: usize, pipe_channels: usize, links: Vec<(usize, usize, f32)>) -> (PipeWriter, Receiver<Vec<u8>>) {
+        let (sender, receiver) = sync_channel(PIPE_BACKLOG);
+        let writer = PipeWriter {
+            name: "test".to_owned(),
+            sender,
+            closed: AtomicBool::new(false),
+            format: PcmFormat::F32Le,
+            channels,
+            pipe_channels,
+            links,
+            primary: true,
+            stats: Arc::new(StreamCounters::default()),
+        };
+
+        (writer, receiver)
+    }
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes.chunks(4).map(|sample| f32::from_le_bytes(sample.try_into().unwrap())).collect()
+    }
+
+    // waits for the pipe's thread, which nothing joins
+    fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out waiting for {}!", what);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn formats_encode_little_endian_and_clamp() {
+        assert_eq!(encoded(PcmFormat::F32Le, &[0.5]), 0.5f32.to_le_bytes());
+        assert_eq!(encoded(PcmFormat::F32Le, &[2.0]), 1.0f32.to_le_bytes());
+        assert_eq!(encoded(PcmFormat::S16Le, &[1.0, -1.0, 0.0]), [i16::MAX.to_le_bytes(), (-i16::MAX).to_le_bytes(), [0, 0]].concat());
+        assert_eq!(encoded(PcmFormat::S16Le, &[-3.0]), (-i16::MAX).to_le_bytes());
+        assert_eq!(encoded(PcmFormat::S32Le, &[1.0, -1.0]), [i32::MAX.to_le_bytes(), (-i32::MAX).to_le_bytes()].concat());
+    }
+
+    #[test]
+    fn links_mix_client_channels_into_the_pipe_layout() {
+        // left and right summed into the first pipe channel, right alone at half gain into the third
+        let (writer, receiver) = writer(2, 3, vec![(0, 0, 1.0), (1, 0, 1.0), (1, 2, 0.5)]);
+
+        writer.write(&[0.25, 0.5, 0.125, -0.25]);
+
+        assert_eq!(floats(&receiver.try_recv().unwrap()), vec![0.75, 0.0, 0.25, -0.125, 0.0, -0.125]);
+    }
+
+    #[test]
+    fn path_target_receives_the_pcm() {
+        let path = std::env::temp_dir().join(format!("remoteio-pipe-{}-{}", std::process::id(), rand::random::<u32>()));
+        let sink = PipeSink { target: PipeTarget::Path(path.clone()), format: PcmFormat::S16Le };
+
+        let writer = PipeWriter::open(&sink, 1, 1, vec![(0, 0, 1.0)], 48000, true, Arc::new(StreamCounters::default())).unwrap();
+        writer.write(&[1.0, -1.0]);
+        writer.write(&[0.0]);
+        drop(writer);
+
+        let expected = [i16::MAX.to_le_bytes(), (-i16::MAX).to_le_bytes(), [0, 0]].concat();
+        wait_for("the pcm", || std::fs::read(&path).map(|bytes| bytes.len() >= expected.len()).unwrap_or(false));
+
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn a_pipe_that_went_away_stops_taking_audio() {
+        let path = std::env::temp_dir().join("remoteio-no-such-dir").join("pipe");
+        let sink = PipeSink { target: PipeTarget::Path(path), format: PcmFormat::F32Le };
+
+        let writer = PipeWriter::open(&sink, 1, 1, vec![(0, 0, 1.0)], 48000, true, Arc::new(StreamCounters::default())).unwrap();
+        wait_for("the pipe to close", || {
+            writer.write(&[0.5]);
+            !writer.is_open()
+        });
+    }
+}
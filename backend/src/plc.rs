@@ -0,0 +1,248 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// packet loss concealment for the server playback path
+//
+// there is no codec on the wire yet (plain f32 frames), so gaps are filled by waveform
+// extrapolation: the last pitch period of real audio is repeated and faded out, and when real
+// data comes back the concealed signal is crossfaded into it so there is no click
+
+// longest pitch period searched for, 20ms (50hz)
+static MAX_PERIOD_MS: f32 = 20.0;
+// shortest pitch period searched for, 2.5ms (400hz)
+static MIN_PERIOD_MS: f32 = 2.5;
+// concealment fades to silence over this long
+static FADE_MS: f32 = 60.0;
+// crossfade from concealed audio back into real audio
+static CROSSFADE_MS: f32 = 5.0;
+
+pub struct Concealer {
+    channels: usize,
+    // interleaved history of the most recent real audio, 2 * max period long
+    history: Vec<f32>,
+    min_period: usize,
+    max_period: usize,
+    fade_frames: usize,
+    crossfade_frames: usize,
+    // pitch period (in frames) picked at the start of the current gap, `None` when not concealing
+    period: Option<usize>,
+    // frames concealed since the gap started, drives the fade out and the replay position
+    concealed: usize,
+    concealed_frames: Arc<AtomicU64>,
+    // channel mixdown of `history` for the pitch search, kept so playback never allocates
+    mono: Vec<f32>,
+}
+
+impl Concealer {
+    pub fn new(channels: u16, sample_rate: u32, concealed_frames: Arc<AtomicU64>) -> Self {
+        let frames_per_ms = sample_rate as f32 / 1_000.0;
+        let max_period = ((MAX_PERIOD_MS * frames_per_ms) as usize).max(2);
+
+        Concealer {
+            channels: channels.max(1) as usize,
+            history: vec![],
+            min_period: ((MIN_PERIOD_MS * frames_per_ms) as usize).max(1),
+            max_period,
+            fade_frames: ((FADE_MS * frames_per_ms) as usize).max(1),
+            crossfade_frames: ((CROSSFADE_MS * frames_per_ms) as usize).max(1),
+            period: None,
+            concealed: 0,
+            concealed_frames,
+            mono: Vec::with_capacity(2 * max_period),
+        }
+    }
+
+    // fill `data` from `received`, concealing whatever `received` does not cover
+    pub fn process(&mut self, data: &mut [f32], received: Option<&[f32]>) {
+        let received = received.unwrap_or(&[]);
+        let real = received.len().min(data.len());
+        let real = real - real % self.channels;
+
+        if real > 0 {
+            self.play(&mut data[..real], &received[..real]);
+        }
+
+        if real < data.len() {
+            self.conceal(&mut data[real..]);
+        }
+    }
+
+    fn play(&mut self, data: &mut [f32], received: &[f32]) {
+        data.copy_from_slice(received);
+
+        // coming out of a gap, blend the extrapolated signal into the real one
+        if self.period.is_some() {
+            let frames = (data.len() / self.channels).min(self.crossfade_frames);
+
+            for frame in 0..frames {
+                let weight = (frame + 1) as f32 / (frames + 1) as f32;
+                for channel in 0..self.channels {
+                    let concealed = self.extrapolate(self.concealed + frame, channel);
+                    let sample = &mut data[frame * self.channels + channel];
+                    *sample = *sample * weight + concealed * (1.0 - weight);
+                }
+            }
+
+            self.period = None;
+            self.concealed = 0;
+        }
+
+        self.remember(received);
+    }
+
+    fn conceal(&mut self, data: &mut [f32]) {
+        let frames = data.len() / self.channels;
+
+        if self.period.is_none() {
+            self.period = Some(self.pitch_period());
+            self.concealed = 0;
+        }
+
+        for frame in 0..frames {
+            for channel in 0..self.channels {
+                data[frame * self.channels + channel] = self.extrapolate(self.concealed + frame, channel);
+            }
+        }
+
+        // whatever does not make a whole frame is silence
+        for sample in data[frames * self.channels..].iter_mut() {
+            *sample = 0.0;
+        }
+
+        self.concealed += frames;
+        self.concealed_frames.fetch_add(frames as u64, Ordering::Relaxed);
+    }
+
+    // sample `frame` frames into the gap, repeating the last pitch period with a linear fade
+    fn extrapolate(&self, frame: usize, channel: usize) -> f32 {
+        let history_frames = self.history.len() / self.channels;
+        let period = self.period.unwrap_or(self.max_period).min(history_frames);
+
+        if period == 0 || frame >= self.fade_frames {
+            return 0.0;
+        }
+
+        let gain = 1.0 - frame as f32 / self.fade_frames as f32;
+        let source = history_frames - period + frame % period;
+
+        self.history[source * self.channels + channel] * gain
+    }
+
+    fn remember(&mut self, received: &[f32]) {
+        let keep = 2 * self.max_period * self.channels;
+
+        self.history.extend_from_slice(received);
+        if self.history.len() > keep {
+            self.history.drain(..self.history.len() - keep);
+        }
+    }
+
+    // strongest autocorrelation lag over the channel mixdown of the history, so the repeated
+    // segment lines up with the waveform instead of jumping mid cycle
+    fn pitch_period(&mut self) -> usize {
+        self.mono.clear();
+        self.mono.extend(self.history.chunks(self.channels).map(|frame| frame.iter().sum::<f32>() / self.channels as f32));
+        let mono = &self.mono;
+
+        let max_period = self.max_period.min(mono.len() / 2);
+        if max_period <= self.min_period {
+            return max_period.max(1);
+        }
+
+        let tail = &mono[mono.len() - max_period..];
+        let mut best = (max_period, f32::MIN);
+
+        for period in self.min_period..=max_period {
+            let lagged = &mono[mono.len() - max_period - period..mono.len() - period];
+
+            let correlation = tail.iter().zip(lagged).map(|(a, b)| a * b).sum::<f32>();
+            let energy = lagged.iter().map(|b| b * b).sum::<f32>().sqrt() * tail.iter().map(|a| a * a).sum::<f32>().sqrt();
+
+            let normalized = if energy > 0.0 { correlation / energy } else { 0.0 };
+            if normalized > best.1 {
+                best = (period, normalized);
+            }
+        }
+
+        best.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SAMPLE_RATE: u32 = 48_000;
+    // 200hz, inside the searched pitch range
+    static PERIOD: usize = 240;
+
+    fn sine(start: usize, frames: usize) -> Vec<f32> {
+        (start..start + frames).map(|n| (2.0 * std::f32::consts::PI * n as f32 / PERIOD as f32).sin() * 0.5).collect()
+    }
+
+    fn concealer() -> (Concealer, Arc<AtomicU64>) {
+        let concealed_frames = Arc::new(AtomicU64::new(0));
+        (Concealer::new(1, SAMPLE_RATE, Arc::clone(&concealed_frames)), concealed_frames)
+    }
+
+    #[test]
+    fn gap_continues_the_last_pitch_period_and_fades_out() {
+        let (mut concealer, concealed_frames) = concealer();
+
+        let mut played = vec![0.0; 2048];
+        concealer.process(&mut played, Some(&sine(0, 2048)));
+        assert_eq!(played, sine(0, 2048));
+
+        let mut gap = vec![0.0; PERIOD];
+        concealer.process(&mut gap, None);
+
+        let expected = sine(2048, PERIOD);
+        for (frame, (concealed, real)) in gap.iter().zip(expected.iter()).enumerate() {
+            let gain = 1.0 - frame as f32 / concealer.fade_frames as f32;
+            assert!((concealed - real * gain).abs() < 1e-3, "frame {}: {} instead of {}", frame, concealed, real * gain);
+        }
+        assert_eq!(concealed_frames.load(Ordering::Relaxed), PERIOD as u64);
+
+        // past the fade the gap is silent
+        let mut rest = vec![1.0; concealer.fade_frames];
+        concealer.process(&mut rest, None);
+        assert!(rest[rest.len() - PERIOD..].iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn real_audio_after_a_gap_is_crossfaded_in() {
+        let (mut concealer, _) = concealer();
+
+        let mut played = vec![0.0; 2048];
+        concealer.process(&mut played, Some(&sine(0, 2048)));
+        let mut gap = vec![0.0; 100];
+        concealer.process(&mut gap, None);
+
+        let resumed = vec![0.25; 1024];
+        let mut data = vec![0.0; 1024];
+        concealer.process(&mut data, Some(&resumed));
+
+        // the first frame is mostly the extrapolated signal, so there is no jump out of the gap
+        let crossfade = concealer.crossfade_frames;
+        let continued = sine(2048 + 100, 1)[0] * (1.0 - 100.0 / concealer.fade_frames as f32);
+        let weight = 1.0 / (crossfade + 1) as f32;
+        assert!((data[0] - (0.25 * weight + continued * (1.0 - weight))).abs() < 1e-3);
+
+        assert!(data[crossfade..].iter().all(|sample| *sample == 0.25));
+        assert!(concealer.period.is_none());
+    }
+
+    #[test]
+    fn short_blocks_take_what_was_received_and_conceal_the_rest() {
+        let (mut concealer, concealed_frames) = concealer();
+
+        let mut played = vec![0.0; 2048];
+        concealer.process(&mut played, Some(&sine(0, 2048)));
+
+        let mut data = vec![0.0; 64];
+        concealer.process(&mut data, Some(&sine(2048, 16)));
+
+        assert_eq!(data[..16], sine(2048, 16)[..]);
+        assert_eq!(concealed_frames.load(Ordering::Relaxed), 48);
+    }
+}
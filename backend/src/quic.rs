@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+use crate::fec::{Delivered, FecConfig, FecDecoder, FecEncoder, FecStats, Received};
+
+// QUIC transport for the audio protocol
+//
+// control messages (config, device requests, ...) travel as length prefixed bincode frames on a
+// single reliable bidirectional stream opened by the client, while audio (`BinData`,
+// `BinSourceData` and already encoded `BinFrame`/`BinParity`) is sent as unreliable datagrams so a
+// lost packet never stalls the ones behind it. `BinData` datagrams are numbered and optionally
+// protected with parity, see `fec`
+
+pub type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+pub static QUIC_SCHEME: &str = "quic://";
+pub static QUIC_SERVER_NAME: &str = "localhost";
+
+// bincode overhead of the largest datagram, `BinMessages::BinParity`:
+// u32 variant tag + u64 first seq + u16 count + u32 length xor + u64 vec length
+static DATAGRAM_OVERHEAD: usize = 26;
+
+// control messages are small, a peer announcing more than this is broken or hostile and the
+// control stream is closed rather than allocating whatever it claims
+pub const MAX_CONTROL_FRAME: usize = 1024 * 1024;
+
+pub struct QuicTransport {
+    // held so a client side endpoint lives as long as its connection
+    _endpoint: quinn::Endpoint,
+    connection: quinn::Connection,
+    control: quinn::SendStream,
+    incoming: Option<QuicReceiver>,
+    frame_len: usize,
+    // channels of each source announced with `BinSourceConfig`
+    source_frame_lens: HashMap<u16, usize>,
+    encoder: Arc<Mutex<FecEncoder>>,
+    decoder: Arc<Mutex<FecDecoder>>,
+}
+
+pub struct QuicReceiver(mpsc::UnboundedReceiver<Result<Received, TransportError>>);
+
+impl QuicReceiver {
+    pub async fn recv(&mut self) -> Option<Result<Received, TransportError>> {
+        self.0.recv().await
+    }
+}
+
+// the server presents a throwaway self signed certificate, so there is nothing for the client to
+// verify against. we still get encryption, just not authentication, which is no worse than the
+// plain websocket transport
+struct SkipServerVerification;
+
+impl rustls::client::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+pub fn server_endpoint(address: &str) -> Result<quinn::Endpoint, Box<dyn std::error::Error>> {
+    let address: SocketAddr = address.parse()?;
+
+    let cert = rcgen::generate_simple_self_signed(vec![QUIC_SERVER_NAME.to_owned()])?;
+    let cert_chain = vec![rustls::Certificate(cert.serialize_der()?)];
+    let key = rustls::PrivateKey(cert.serialize_private_key_der());
+
+    let server_config = quinn::ServerConfig::with_single_cert(cert_chain, key)?;
+
+    Ok(quinn::Endpoint::server(server_config, address)?)
+}
+
+pub fn client_endpoint(remote: &SocketAddr) -> Result<quinn::Endpoint, Box<dyn std::error::Error>> {
+    let crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+        .with_no_client_auth();
+
+    let local: SocketAddr = match remote {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+        SocketAddr::V6(_) => "[::]:0".parse()?,
+    };
+
+    let mut endpoint = quinn::Endpoint::client(local)?;
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+
+    Ok(endpoint)
+}
+
+impl QuicTransport {
+    // connect to a `quic://host:port` url
+    pub async fn connect(url: &str) -> Result<QuicTransport, Box<dyn std::error::Error>> {
+        let host = url.strip_prefix(QUIC_SCHEME).unwrap_or(url).trim_end_matches('/');
+        let remote = tokio::net::lookup_host(host).await?.next().ok_or("could not resolve quic host!")?;
+
+        let endpoint = client_endpoint(&remote)?;
+        let connection = endpoint.connect(remote, QUIC_SERVER_NAME)?.await?;
+        let (control, control_reader) = connection.open_bi().await?;
+
+        Ok(QuicTransport::from_parts(endpoint, connection, control, control_reader))
+    }
+
+    // accept the next client on a server endpoint, `None` once the endpoint is closed
+    pub async fn accept(endpoint: &quinn::Endpoint) -> Option<Result<QuicTransport, TransportError>> {
+        let connecting = endpoint.accept().await?;
+
+        Some(QuicTransport::accept_connecting(endpoint, connecting).await)
+    }
+
+    // finish accepting a client `endpoint` already let in
+    pub async fn accept_connecting(endpoint: &quinn::Endpoint, connecting: quinn::Connecting) -> Result<QuicTransport, TransportError> {
+        let connection = connecting.await?;
+        // the client opens the control stream, it becomes visible once the first config is sent
+        let (control, control_reader) = connection.accept_bi().await?;
+
+        Ok(QuicTransport::from_parts(endpoint.clone(), connection, control, control_reader))
+    }
+
+    fn from_parts(endpoint: quinn::Endpoint, connection: quinn::Connection, control: quinn::SendStream, mut control_reader: quinn::RecvStream) -> QuicTransport {
+        let (sender, incoming) = mpsc::unbounded_channel();
+
+        // reading a length prefixed frame is not cancel safe, so both readers get their own task
+        // and funnel into one channel that can be polled from anywhere
+        let control_sender = sender.clone();
+        tokio::spawn(async move {
+            loop {
+                let mut len = [0u8; 4];
+                let frame = match control_reader.read_exact(&mut len).await {
+                    Ok(()) => {
+                        let len = u32::from_be_bytes(len) as usize;
+                        if len > MAX_CONTROL_FRAME {
+                            let _ = control_reader.stop(quinn::VarInt::from_u32(0));
+                            let _ = control_sender.send(Err(format!("control frame of {} bytes is larger than {}", len, MAX_CONTROL_FRAME).into()));
+                            return;
+                        }
+
+                        let mut frame = vec![0u8; len];
+                        control_reader.read_exact(&mut frame).await.map(|_| frame)
+                    },
+                    Err(e) => Err(e)
+                };
+
+                match frame {
+                    Ok(frame) => {
+                        if control_sender.send(Ok(Received::Message(frame))).is_err() {
+                            return;
+                        }
+                    },
+                    Err(quinn::ReadExactError::FinishedEarly) => return,
+                    Err(quinn::ReadExactError::ReadError(e)) => {
+                        let _ = control_sender.send(Err(e.into()));
+                        return;
+                    }
+                }
+            }
+        });
+
+        let encoder = Arc::new(Mutex::new(FecEncoder::new(FecConfig::default())));
+        let decoder = Arc::new(Mutex::new(FecDecoder::default()));
+
+        let datagram_connection = connection.clone();
+        let datagram_encoder = Arc::clone(&encoder);
+        let datagram_decoder = Arc::clone(&decoder);
+        tokio::spawn(async move {
+            loop {
+                match datagram_connection.read_datagram().await {
+                    Ok(datagram) => {
+                        let ready = match bincode::deserialize(&datagram) {
+                            Ok(crate::BinMessages::BinFrame(frame)) => datagram_decoder.lock().unwrap().push_frame(frame),
+                            Ok(crate::BinMessages::BinParity(parity)) => datagram_decoder.lock().unwrap().push_parity(parity),
+                            Ok(crate::BinMessages::BinLossReport(report)) => {
+                                datagram_encoder.lock().unwrap().on_loss_report(report);
+                                continue;
+                            },
+                            // anything else is passed through as is
+                            _ => {
+                                if sender.send(Ok(Received::Message(datagram.to_vec()))).is_err() {
+                                    return;
+                                }
+                                continue;
+                            }
+                        };
+
+                        // frames come out of the decoder in order, handed on as plain `BinData`
+                        for delivered in ready {
+                            let received = match delivered {
+                                Delivered::Frame(data) => bincode::serialize(&crate::BinMessages::BinData(data)).map(Received::Message).map_err(|e| e.into()),
+                                Delivered::Missing(samples) => Ok(Received::Missing(samples)),
+                            };
+                            if sender.send(received).is_err() {
+                                return;
+                            }
+                        }
+
+                        let report = datagram_decoder.lock().unwrap().loss_report();
+                        if let Some(report) = report {
+                            if let Ok(report) = bincode::serialize(&crate::BinMessages::BinLossReport(report)) {
+                                let _ = datagram_connection.send_datagram(Bytes::from(report));
+                            }
+                        }
+                    },
+                    Err(quinn::ConnectionError::ApplicationClosed(_)) | Err(quinn::ConnectionError::LocallyClosed) => return,
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into()));
+                        return;
+                    }
+                }
+            }
+        });
+
+        QuicTransport {
+            _endpoint: endpoint,
+            connection,
+            control,
+            incoming: Some(QuicReceiver(incoming)),
+            frame_len: 1,
+            source_frame_lens: HashMap::new(),
+            encoder,
+            decoder,
+        }
+    }
+
+    pub fn remote_address(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+
+    // next serialized message from either the control stream or a datagram, `None` once the
+    // peer has closed the connection
+    pub async fn recv(&mut self) -> Option<Result<Received, TransportError>> {
+        self.incoming.as_mut()?.recv().await
+    }
+
+    // move the receiving side out, so it can be read without holding on to the sending side
+    pub fn take_receiver(&mut self) -> Option<QuicReceiver> {
+        self.incoming.take()
+    }
+
+    pub async fn send(&mut self, message: &crate::BinMessages) -> Result<(), TransportError> {
+        match message {
+            crate::BinMessages::BinData(data) => self.send_data(data),
+            crate::BinMessages::BinConfig(config) => {
+                self.frame_len = config.channels.max(1) as usize;
+                self.send_control(message).await
+            },
+            crate::BinMessages::BinSourceConfig(source) => {
+                self.source_frame_lens.insert(source.id, source.config.channels.max(1) as usize);
+                self.send_control(message).await
+            },
+            crate::BinMessages::BinSourceData(id, data) => self.send_source_data(*id, data),
+            crate::BinMessages::BinFrame(_) | crate::BinMessages::BinParity(_) => {
+                self.connection.send_datagram(Bytes::from(bincode::serialize(message)?))?;
+                Ok(())
+            },
+            _ => self.send_control(message).await
+        }
+    }
+
+    pub async fn send_control(&mut self, message: &crate::BinMessages) -> Result<(), TransportError> {
+        let frame = bincode::serialize(message)?;
+        if frame.len() > MAX_CONTROL_FRAME {
+            return Err(format!("control frame of {} bytes is larger than {}", frame.len(), MAX_CONTROL_FRAME).into());
+        }
+
+        self.control.write_all(&(frame.len() as u32).to_be_bytes()).await?;
+        self.control.write_all(&frame).await?;
+
+        Ok(())
+    }
+
+    // audio is split on frame boundaries into chunks that fit a single datagram, so a lost
+    // datagram drops whole frames instead of shifting the channel interleaving
+    pub fn send_data(&self, data: &[f32]) -> Result<(), TransportError> {
+        let max_frames = self.max_frames(self.frame_len)?;
+        let mut encoder = self.encoder.lock().unwrap();
+
+        for chunk in data.chunks(max_frames * self.frame_len) {
+            let (frame, parity) = encoder.encode(chunk.to_vec());
+
+            let datagram = bincode::serialize(&crate::BinMessages::BinFrame(frame))?;
+            self.connection.send_datagram(Bytes::from(datagram))?;
+
+            if let Some(parity) = parity {
+                let datagram = bincode::serialize(&crate::BinMessages::BinParity(parity))?;
+                self.connection.send_datagram(Bytes::from(datagram))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // same split for an extra source, without numbering or parity. a lost datagram is a gap the
+    // source's tap conceals like any late block
+    pub fn send_source_data(&self, id: u16, data: &[f32]) -> Result<(), TransportError> {
+        let frame_len = self.source_frame_lens.get(&id).copied().unwrap_or(1);
+        let max_frames = self.max_frames(frame_len)?;
+
+        for chunk in data.chunks(max_frames * frame_len) {
+            let datagram = bincode::serialize(&crate::BinMessages::BinSourceData(id, chunk.to_vec()))?;
+            self.connection.send_datagram(Bytes::from(datagram))?;
+        }
+
+        Ok(())
+    }
+
+    fn max_frames(&self, frame_len: usize) -> Result<usize, TransportError> {
+        let max_size = self.connection.max_datagram_size().ok_or("peer does not accept datagrams!")?;
+
+        match max_size.saturating_sub(DATAGRAM_OVERHEAD) / (4 * frame_len) {
+            0 => Err("datagram too small to carry a single frame!".into()),
+            max_frames => Ok(max_frames)
+        }
+    }
+
+    pub fn set_fec_config(&self, config: FecConfig) {
+        self.encoder.lock().unwrap().set_config(config);
+    }
+
+    pub fn fec_stats(&self) -> FecStats {
+        let sent = self.encoder.lock().unwrap().stats();
+        let received = self.decoder.lock().unwrap().stats();
+
+        FecStats::combine(sent, received)
+    }
+
+    pub fn close(&self, reason: &str) {
+        self.connection.close(0u32.into(), reason.as_bytes());
+    }
+
+    pub async fn close_gracefully(&mut self, reason: &str) {
+        let _ = self.control.finish().await;
+        self.close(reason);
+    }
+}
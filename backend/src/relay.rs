@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::broadcast::{Broadcast, Cast, Subscription};
+use crate::client::ClientTransport;
+use crate::fec::Received;
+use crate::heartbeat::{Heartbeat, HeartbeatConfig};
+use crate::identity::{claim_id, BinClientIdentity, Instance};
+use crate::routing::ClientMatch;
+use crate::server::Client;
+use crate::stats::{StreamCounters, StreamStats};
+
+// passing streams on to another server
+//
+// a hub relays some or all of its clients' streams to an upstream RemoteIO server, where each one
+// shows up as a client of its own. nothing is played or decoded on the way, the encoded blocks of
+// the stream's broadcast go out as they are. a relayed connection says which servers the stream
+// already went through (`BinRelay`), a server that finds itself on that path refuses it so streams
+// can't go around in circles, and every hop notes how long the stream took to reach it so the last
+// server sees the latency of each one
+
+// paths longer than this are refused too, in case a server changed its id along the way
+pub static MAX_HOPS: usize = 8;
+
+// how often relays look for streams that came or went
+pub static RELAY_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+// one server a stream went through before the one it is connected to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BinHop {
+    pub server: String,
+    // half the round trip of the connection the stream came into `server` on, unknown until
+    // the first ping was answered
+    pub latency_ms: Option<f32>,
+}
+
+// a server's id, kept under `config_dir()` so it stays the same across restarts. every server
+// running on the machine gets its own instance number, the id has to tell them apart on one path
+pub fn server_id() -> (String, Instance) {
+    claim_id("server_id", "server")
+}
+
+// why `server` can't take a stream that went through `hops`, if it can't
+pub fn check_path(server: &str, hops: &[BinHop]) -> Result<(), String> {
+    if hops.iter().any(|hop| hop.server == server) {
+        return Err("relay loop, the stream already went through this server".to_owned());
+    }
+    if hops.len() >= MAX_HOPS {
+        return Err(format!("relayed through more than {} servers", MAX_HOPS));
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct RelayConfig {
+    pub url: String,
+    // which clients go upstream, every source when `None`
+    pub matcher: Option<ClientMatch>,
+    // the room to join upstream, its default room when `None`
+    pub room: Option<String>,
+    pub heartbeat: HeartbeatConfig,
+    // wait between attempts while the upstream server is away
+    pub reconnect_interval: Duration,
+}
+
+impl RelayConfig {
+    pub fn new(url: &str) -> Self {
+        RelayConfig {
+            url: url.to_owned(),
+            matcher: None,
+            room: None,
+            heartbeat: HeartbeatConfig::default(),
+            reconnect_interval: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RelayInfo {
+    pub url: String,
+    // per relayed client id, as sent upstream
+    pub streams: Vec<(String, StreamStats)>,
+}
+
+// a stream a relay could send, as the scan found it
+pub struct RelaySource {
+    pub client: Client,
+    // where it has been, this server included
+    pub path: Vec<BinHop>,
+    pub broadcast: Arc<Broadcast>,
+}
+
+// one stream on its way upstream
+struct Forward {
+    path: Arc<Mutex<Vec<BinHop>>>,
+    stats: Arc<StreamCounters>,
+    heartbeat: Arc<Mutex<Option<Heartbeat>>>,
+    stop: watch::Sender<bool>,
+    // taken when the server shuts down, to wait for the upstream connection to close
+    task: Option<JoinHandle<()>>,
+}
+
+impl Drop for Forward {
+    fn drop(&mut self) {
+        let _ = self.stop.send(true);
+    }
+}
+
+impl Forward {
+    fn stats(&self) -> StreamStats {
+        let mut stats = self.stats.snapshot();
+        stats.rtt_ms = self.heartbeat.lock().unwrap().as_ref()
+            .and_then(Heartbeat::rtt)
+            .map(|rtt| rtt.as_secs_f32() * 1_000.0);
+        stats.hops = self.path.lock().unwrap().clone();
+
+        stats
+    }
+}
+
+struct Relay {
+    config: RelayConfig,
+    // by the relayed client's room and id
+    forwards: HashMap<(String, String), Forward>,
+}
+
+#[derive(Clone, Default)]
+pub struct Relays {
+    relays: Arc<Mutex<Vec<Relay>>>,
+}
+
+impl Relays {
+    pub fn add(&self, config: RelayConfig) -> Result<(), Box<dyn Error>> {
+        let mut relays = self.relays.lock().unwrap();
+        if relays.iter().any(|relay| relay.config.url == config.url) {
+            return Err(format!("already relaying to {}", config.url).into());
+        }
+
+        info!(upstream = %config.url, "relay added");
+        relays.push(Relay { config, forwards: HashMap::new() });
+
+        Ok(())
+    }
+
+    // stops every stream it sends
+    pub fn remove(&self, url: &str) -> Result<(), Box<dyn Error>> {
+        let mut relays = self.relays.lock().unwrap();
+        let position = relays.iter().position(|relay| relay.config.url == url).ok_or(format!("not relaying to {}", url))?;
+        relays.remove(position);
+
+        info!(upstream = %url, "relay removed");
+        Ok(())
+    }
+
+    // stops every stream, the returned tasks end once their upstream connections are closed
+    pub fn clear(&self) -> Vec<JoinHandle<()>> {
+        let relays = std::mem::take(&mut *self.relays.lock().unwrap());
+
+        relays.into_iter()
+            .flat_map(|relay| relay.forwards.into_values())
+            .filter_map(|mut forward| forward.task.take())
+            .collect()
+    }
+
+    pub fn infos(&self) -> Vec<RelayInfo> {
+        self.relays.lock().unwrap().iter().map(|relay| RelayInfo {
+            url: relay.config.url.clone(),
+            streams: relay.forwards.values().map(|forward| forward.stats()).zip(relay.forwards.keys())
+                .map(|(stats, (_, id))| (id.clone(), stats))
+                .collect(),
+        }).collect()
+    }
+
+    // start forwarding new streams, stop the ones whose client left and pass on fresh latencies
+    pub fn sync(&self, server: &str, sources: &[RelaySource]) {
+        let mut relays = self.relays.lock().unwrap();
+
+        for relay in relays.iter_mut() {
+            let wanted = |source: &&RelaySource| relay.config.matcher.as_ref().is_none_or(|matcher| matcher.matches(&source.client));
+            let sources = sources.iter().filter(wanted).map(|source| ((source.client.room.clone(), source.client.id.clone()), source)).collect::<HashMap<(String, String), &RelaySource>>();
+
+            // a forward that ended on its own (cut off for falling behind) starts over
+            relay.forwards.retain(|key, forward| sources.contains_key(key) && forward.task.as_ref().is_some_and(|task| !task.is_finished()));
+
+            for (key, source) in sources {
+                if let Some(forward) = relay.forwards.get(&key) {
+                    *forward.path.lock().unwrap() = source.path.clone();
+                    continue;
+                }
+
+                match Relays::start(server, &relay.config, source) {
+                    Ok(forward) => {
+                        relay.forwards.insert(key, forward);
+                    },
+                    Err(e) => warn!(upstream = %relay.config.url, client = %source.client.id, "could not relay due to {}", e)
+                }
+            }
+        }
+    }
+
+    fn start(server: &str, config: &RelayConfig, source: &RelaySource) -> Result<Forward, Box<dyn Error>> {
+        let stats = Arc::new(StreamCounters::default());
+        // device errors of a client's stream stay with its own connection
+        let (device_errors, _) = crate::logging::device_error_channel();
+        let subscription = source.broadcast.subscribe(Arc::clone(&stats), device_errors)?;
+
+        let identity = BinClientIdentity {
+            id: format!("{}/{}", server, source.client.id),
+            name: source.client.name.clone(),
+        };
+        let path = Arc::new(Mutex::new(source.path.clone()));
+        let heartbeat = Arc::new(Mutex::new(None));
+        let (stop, stopped) = watch::channel(false);
+
+        let span = info_span!("relay", upstream = %config.url, client = %source.client.id);
+        let task = tokio::spawn(forward(config.clone(), identity, subscription, Arc::clone(&path), Arc::clone(&stats), Arc::clone(&heartbeat), stopped).instrument(span));
+
+        Ok(Forward { path, stats, heartbeat, stop, task: Some(task) })
+    }
+}
+
+// keep one stream going upstream, reconnecting whenever it drops. ends when stopped or when the
+// stream cuts it off
+async fn forward(config: RelayConfig, identity: BinClientIdentity, mut subscription: Subscription, path: Arc<Mutex<Vec<BinHop>>>, stats: Arc<StreamCounters>, heartbeat_slot: Arc<Mutex<Option<Heartbeat>>>, mut stop: watch::Receiver<bool>) {
+    loop {
+        let (mut transport, mut receiver) = match ClientTransport::open(&config.url).await.map_err(|e| e.to_string()) {
+            Ok(opened) => opened,
+            Err(e) => {
+                warn!("could not connect upstream due to {}, retrying in {:?}", e, config.reconnect_interval);
+                if !wait(&mut stop, &mut subscription, config.reconnect_interval).await {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        let mut sent_path = path.lock().unwrap().clone();
+        let mut handshake = vec![crate::BinMessages::BinIdentify(identity.clone())];
+        if let Some(room) = &config.room {
+            handshake.push(crate::BinMessages::BinJoin(room.clone()));
+        }
+        handshake.push(crate::BinMessages::BinRelay(sent_path.clone()));
+        handshake.push(crate::BinMessages::BinConfig(crate::BinStreamConfig::from(&subscription.config)));
+
+        let mut result = Ok(());
+        for message in handshake.iter() {
+            result = transport.send(message).await.map_err(|e| e.to_string());
+            if result.is_err() {
+                break;
+            }
+        }
+
+        if result.is_ok() {
+            info!("relaying");
+            *heartbeat_slot.lock().unwrap() = Some(Heartbeat::new(config.heartbeat));
+            let mut tick = tokio::time::interval(config.heartbeat.interval);
+
+            result = loop {
+                tokio::select! {
+                    _ = stop.changed() => {
+                        let _ = transport.close().await;
+                        return;
+                    },
+                    cast = subscription.receiver.recv() => {
+                        let sent = match cast {
+                            Some(Cast::Config(stream_config)) => {
+                                subscription.config = stream_config;
+                                transport.send(&crate::BinMessages::BinConfig(crate::BinStreamConfig::from(&subscription.config))).await.map(|_| ())
+                            },
+                            Some(Cast::Block(block)) => transport.send_block(&block).await.map(|_| stats.sent(block.encoded.len() as u64, block.frames)),
+                            None => {
+                                warn!("fell too far behind the stream, stopping");
+                                let _ = transport.close().await;
+                                return;
+                            }
+                        };
+
+                        if let Err(e) = sent {
+                            break Err(e.to_string());
+                        }
+                    },
+                    message = receiver.recv() => {
+                        let message = match message {
+                            Some(Ok(Received::Message(message))) => message,
+                            // only the upstream's heartbeats matter here, not its audio
+                            Some(Ok(Received::Missing(_))) => continue,
+                            Some(Err(e)) => break Err(e.to_string()),
+                            None => break Err("upstream closed the connection".to_owned())
+                        };
+
+                        let deserialized = match bincode::deserialize(&message) {
+                            Ok(deserialized) => deserialized,
+                            Err(_) => continue
+                        };
+                        let reply = heartbeat_slot.lock().unwrap().as_mut().and_then(|heartbeat| heartbeat.on_message(&deserialized));
+                        if let Some(reply) = reply {
+                            if let Err(e) = transport.send(&reply).await {
+                                break Err(e.to_string());
+                            }
+                        }
+                    },
+                    _ = tick.tick() => {
+                        let (expired, ping) = {
+                            let heartbeat = heartbeat_slot.lock().unwrap();
+                            let heartbeat = heartbeat.as_ref().expect("heartbeat was just set!");
+                            (heartbeat.is_expired(), heartbeat.ping())
+                        };
+                        if expired {
+                            break Err("upstream stopped answering".to_owned());
+                        }
+                        if let Err(e) = transport.send(&ping).await {
+                            break Err(e.to_string());
+                        }
+
+                        // latencies further down the path changed
+                        let current = path.lock().unwrap().clone();
+                        if current != sent_path {
+                            if let Err(e) = transport.send(&crate::BinMessages::BinRelay(current.clone())).await {
+                                break Err(e.to_string());
+                            }
+                            sent_path = current;
+                        }
+                    }
+                }
+            };
+        }
+
+        let _ = transport.close().await;
+        *heartbeat_slot.lock().unwrap() = None;
+        if let Err(e) = result {
+            warn!("lost upstream due to {}, reconnecting in {:?}", e, config.reconnect_interval);
+        }
+
+        if !wait(&mut stop, &mut subscription, config.reconnect_interval).await {
+            return;
+        }
+    }
+}
+
+// sit out `duration` while disconnected, dropping the audio that piles up. false once stopped or
+// cut off
+async fn wait(stop: &mut watch::Receiver<bool>, subscription: &mut Subscription, duration: Duration) -> bool {
+    let sleep = tokio::time::sleep(duration);
+    tokio::pin!(sleep);
+
+    loop {
+        tokio::select! {
+            _ = &mut sleep => return true,
+            _ = stop.changed() => return false,
+            cast = subscription.receiver.recv() => match cast {
+                Some(Cast::Config(config)) => subscription.config = config,
+                Some(Cast::Block(_)) => {},
+                None => return false
+            }
+        }
+    }
+}
+
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use crate::metrics::ServerMetrics;
+use crate::mixer::Mixers;
+use crate::pipe::PipeSinks;
+
+// separate groups of clients on one server
+//
+// a client joins a room when it connects and only hears and is heard by the room's own clients.
+// every room mixes on its own, so two rooms routed to the same device open it twice instead of
+// summing into one stream, and routing rules can target a room with `ClientMatch::Room`. the
+// server's input device belongs to the default room, listeners elsewhere subscribe to a client of
+// their own room
+
+// where clients that don't ask for a room end up, it can't be closed
+pub static DEFAULT_ROOM: &str = "default";
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RoomInfo {
+    pub name: String,
+    pub clients: usize,
+}
+
+struct Room {
+    // the room's outputs, by device
+    mixers: Mixers,
+}
+
+#[derive(Clone)]
+pub struct Rooms {
+    rooms: Arc<Mutex<HashMap<String, Room>>>,
+    metrics: Arc<ServerMetrics>,
+    pipes: PipeSinks,
+}
+
+impl Rooms {
+    pub fn new(metrics: Arc<ServerMetrics>, pipes: PipeSinks) -> Self {
+        let rooms = Rooms {
+            rooms: Arc::new(Mutex::new(HashMap::new())),
+            metrics,
+            pipes,
+        };
+        rooms.create(DEFAULT_ROOM).expect("rooms start out empty!");
+
+        rooms
+    }
+
+    pub fn create(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        let mut rooms = self.rooms.lock().unwrap();
+
+        if name.is_empty() {
+            return Err("rooms need a name".into());
+        }
+        if rooms.contains_key(name) {
+            return Err(format!("room {} already exists", name).into());
+        }
+
+        let mixers = Mixers::new(Arc::clone(&self.metrics), self.pipes.clone());
+        rooms.insert(name.to_owned(), Room { mixers });
+
+        Ok(())
+    }
+
+    // forget the room, its clients have to be let go by the caller
+    pub fn close(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        if name == DEFAULT_ROOM {
+            return Err("the default room can't be closed".into());
+        }
+
+        match self.rooms.lock().unwrap().remove(name) {
+            Some(_) => Ok(()),
+            None => Err(format!("no room named {}", name).into())
+        }
+    }
+
+    // the outputs a room's clients play on, `None` if there is no such room
+    pub fn mixers(&self, name: &str) -> Option<Mixers> {
+        self.rooms.lock().unwrap().get(name).map(|room| room.mixers.clone())
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.rooms.lock().unwrap().keys().cloned().collect::<Vec<String>>();
+        names.sort();
+
+        names
+    }
+}
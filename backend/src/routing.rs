@@ -0,0 +1,322 @@
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+use cpal::traits::HostTrait;
+use serde::{Serialize, Deserialize};
+
+use crate::devices::DeviceId;
+use crate::server::Client;
+
+// where each client's audio goes on the server
+//
+// rules are checked in order and the first one that matches a connecting client decides which
+// output devices it plays on, and which of its channels end up on which device channel at what
+// gain. the table is saved as json whenever it changes so it survives restarts
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ClientMatch {
+    // the stable id a client identifies with
+    Id(String),
+    // glob on the client's name, `*` and `?` wildcards, ignoring case
+    Name(String),
+    // a single address (`192.168.1.20`) or a network (`192.168.1.0/24`)
+    SourceIp(String),
+    // every client in the room with this name
+    Room(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChannelLink {
+    // client channel
+    pub from: u16,
+    // device channel, several links into one channel are summed
+    pub to: u16,
+    pub gain: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RouteOutput {
+    // `None` plays on the default output device
+    pub device: Option<DeviceId>,
+    // applied on top of every link's own gain
+    pub gain: f32,
+    // without links channels go straight across, a mono client is sent to every device channel
+    pub links: Option<Vec<ChannelLink>>,
+    // write raw pcm to the pipe sink of this name instead of playing on `device`, see `pipe`
+    #[serde(default)]
+    pub pipe: Option<String>,
+}
+
+// every output a client plays on, the same device may be shared with other clients
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Route {
+    pub outputs: Vec<RouteOutput>,
+}
+
+impl RouteOutput {
+    // the output's device and its id, or the default one (`None`) when it has none or it went away.
+    // fails when there is no default output device to fall back on either
+    pub fn output_device(&self) -> Result<(Option<DeviceId>, cpal::Device), Box<dyn Error>> {
+        let default = || cpal::default_host()
+            .default_output_device()
+            .map(|device| (None, device))
+            .ok_or_else(|| "could not find default output device".into());
+
+        match &self.device {
+            Some(id) => match crate::devices::find_output_device(id) {
+                Ok(device) => Ok((Some(id.clone()), device)),
+                Err(e) => {
+                    tracing::warn!("{}, playing on the default output device", e);
+                    default()
+                }
+            },
+            None => default()
+        }
+    }
+}
+
+impl Default for RouteOutput {
+    fn default() -> Self {
+        RouteOutput {
+            device: None,
+            gain: 1.0,
+            links: None,
+            pipe: None,
+        }
+    }
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        Route {
+            outputs: vec![RouteOutput::default()],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoutingRule {
+    pub matcher: ClientMatch,
+    pub route: Route,
+}
+
+impl ClientMatch {
+    pub fn matches(&self, client: &Client) -> bool {
+        match self {
+            ClientMatch::Id(id) => client.id == *id,
+            ClientMatch::Name(pattern) => glob(&pattern.to_lowercase(), &client.name.to_lowercase()),
+            ClientMatch::SourceIp(network) => match client.url.parse::<SocketAddr>() {
+                Ok(address) => in_network(network, address.ip()),
+                Err(_) => false
+            },
+            ClientMatch::Room(room) => client.room == *room,
+        }
+    }
+}
+
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+
+    // position of the last `*` and where in the text it started matching, for backtracking
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn in_network(network: &str, address: IpAddr) -> bool {
+    let (base, prefix) = match network.split_once('/') {
+        Some((base, prefix)) => (base, prefix.parse::<u32>().ok()),
+        None => (network, None),
+    };
+
+    let base = match base.trim().parse::<IpAddr>() {
+        Ok(base) => base,
+        Err(_) => return false
+    };
+
+    // an ipv4 client on a dual stack socket shows up as ::ffff:a.b.c.d
+    let address = match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        v4 => v4
+    };
+
+    match (base, address) {
+        (IpAddr::V4(base), IpAddr::V4(address)) => {
+            let prefix = prefix.unwrap_or(32).min(32);
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(base) & mask == u32::from(address) & mask
+        },
+        (IpAddr::V6(base), IpAddr::V6(address)) => {
+            let prefix = prefix.unwrap_or(128).min(128);
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(base) & mask == u128::from(address) & mask
+        },
+        _ => false
+    }
+}
+
+#[derive(Default)]
+pub struct RoutingTable {
+    rules: Vec<RoutingRule>,
+    // kept in memory only when unset
+    path: Option<PathBuf>,
+}
+
+impl RoutingTable {
+    // `~/.remoteio/routes.json`
+    pub fn default_path() -> PathBuf {
+        crate::identity::config_dir().join("routes.json")
+    }
+
+    // a missing file is an empty table that will be created on the first change
+    pub fn load(path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        let rules = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into())
+        };
+
+        Ok(RoutingTable { rules, path: Some(path) })
+    }
+
+    pub fn rules(&self) -> &[RoutingRule] {
+        &self.rules
+    }
+
+    pub fn set_rules(&mut self, rules: Vec<RoutingRule>) -> Result<(), Box<dyn Error>> {
+        self.rules = rules;
+        self.save()
+    }
+
+    // the route of the first rule matching `client`, the default route if none does
+    pub fn route_for(&self, client: &Client) -> Route {
+        self.rules
+            .iter()
+            .find(|rule| rule.matcher.matches(client))
+            .map(|rule| rule.route.clone())
+            .unwrap_or_default()
+    }
+
+    // send the client to `device` only, creating its own rule ahead of any pattern rules
+    pub fn set_device(&mut self, id: &str, device: DeviceId) -> Result<(), Box<dyn Error>> {
+        let matcher = ClientMatch::Id(id.to_owned());
+        let route = Route {
+            outputs: vec![RouteOutput { device: Some(device), ..Default::default() }],
+        };
+
+        match self.rules.iter_mut().find(|rule| rule.matcher == matcher) {
+            Some(rule) => rule.route = route,
+            None => self.rules.insert(0, RoutingRule { matcher, route }),
+        }
+
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(())
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(&self.rules)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob("studio", "studio"));
+        assert!(!glob("studio", "studio 2"));
+        assert!(glob("studio ?", "studio 2"));
+        assert!(!glob("studio ?", "studio "));
+        assert!(glob("*", ""));
+        assert!(glob("*", "anything"));
+        assert!(glob("stage*", "stage left"));
+        assert!(glob("*left", "stage left"));
+        assert!(!glob("*left", "stage right"));
+        assert!(glob("**mic**", "the mic"));
+    }
+
+    #[test]
+    fn glob_backtracks_past_early_matches() {
+        // the first `b` the star stops at is the wrong one
+        assert!(glob("a*b", "abab"));
+        assert!(glob("a*bc", "abxbc"));
+        assert!(glob("*a?c", "abcaxc"));
+        assert!(glob("a*b*c", "aXbYbZc"));
+        assert!(!glob("a*b*c", "aXbYbZ"));
+        assert!(!glob("a*b", "abac"));
+    }
+
+    #[test]
+    fn single_addresses_match_only_themselves() {
+        assert!(in_network("192.168.1.20", ip("192.168.1.20")));
+        assert!(!in_network("192.168.1.20", ip("192.168.1.21")));
+        assert!(in_network("fe80::1", ip("fe80::1")));
+        assert!(!in_network("fe80::1", ip("fe80::2")));
+    }
+
+    #[test]
+    fn networks_mask_the_prefix() {
+        assert!(in_network("192.168.1.0/24", ip("192.168.1.200")));
+        assert!(!in_network("192.168.1.0/24", ip("192.168.2.1")));
+        assert!(in_network("10.0.0.0/8", ip("10.255.0.1")));
+        assert!(in_network("172.16.0.0/12", ip("172.31.255.255")));
+        assert!(!in_network("172.16.0.0/12", ip("172.32.0.0")));
+        assert!(in_network("0.0.0.0/0", ip("8.8.8.8")));
+        // past the address length is the whole address
+        assert!(in_network("192.168.1.20/40", ip("192.168.1.20")));
+        assert!(!in_network("192.168.1.20/40", ip("192.168.1.21")));
+
+        assert!(in_network("fd00::/8", ip("fdab::1")));
+        assert!(!in_network("fd00::/8", ip("fe80::1")));
+        assert!(in_network("::/0", ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn ipv4_clients_on_dual_stack_sockets_match_ipv4_networks() {
+        assert!(in_network("192.168.1.0/24", ip("::ffff:192.168.1.7")));
+        assert!(!in_network("192.168.1.0/24", ip("::ffff:192.168.2.7")));
+    }
+
+    #[test]
+    fn families_and_bad_networks_never_match() {
+        assert!(!in_network("192.168.1.0/24", ip("fe80::1")));
+        assert!(!in_network("fe80::/10", ip("192.168.1.1")));
+        assert!(!in_network("not an address", ip("192.168.1.1")));
+        // an unreadable prefix is a single address
+        assert!(in_network("192.168.1.1/x", ip("192.168.1.1")));
+        assert!(!in_network("192.168.1.1/x", ip("192.168.1.2")));
+    }
+}
@@ -1,28 +1,64 @@
 pub use std::convert::TryInto;
-use std::sync::atomic::AtomicBool;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc};
+use std::sync::mpsc::{Receiver, SyncSender};
 use std::time::Duration;
-use axum::Router;
-use axum::extract::{Path, State};
+use axum::{Json, Router};
+use axum::body::StreamBody;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{Html, IntoResponse, Response as HttpResponse};
 use axum::routing::get;
-use tokio::sync::{Mutex, MutexGuard};
+use tokio::sync::{Mutex, watch};
 use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
 
 
 use async_trait::async_trait;
 
 
-use bytes::{Buf, Bytes};
+use serde::{Serialize, Deserialize};
 use cpal::StreamConfig;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use tokio::net::TcpListener;
 use tokio_tungstenite::WebSocketStream;
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use tokio_tungstenite::{accept_hdr_async, tungstenite::Message};
+use tokio_tungstenite::tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 
 use futures::{FutureExt, StreamExt, Future, SinkExt};
-
-
-pub static BUFFER_BATCH_SIZE: usize = 4096;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::fec::{FecStats, Received};
+use crate::heartbeat::{Heartbeat, HeartbeatConfig};
+use crate::metrics::ServerMetrics;
+use crate::devices::DeviceId;
+use crate::routing::{Route, RoutingRule, RoutingTable};
+use crate::mixer::{Mixers, Tap, TapSource, FADE_OUT};
+use crate::rooms::{RoomInfo, Rooms, DEFAULT_ROOM};
+use crate::broadcast::{Block, Broadcast, Broadcasts, Cast, StreamInfo, Subscription, INPUT_STREAM};
+use crate::capture::Captures;
+use crate::sources::{Carrier, Source};
+use crate::browser::{BrowserTransport, BROWSER_PATH, LISTEN_PAGE, SEND_PAGE};
+use crate::http_stream::WAV_CONTENT_TYPE;
+use crate::ingest::{IngestConfig, IngestTransport};
+use crate::identity::Instance;
+use crate::pipe::{PipeSink, PipeSinks};
+use crate::relay::{BinHop, RelayConfig, RelayInfo, RelaySource, Relays, RELAY_SCAN_INTERVAL};
+use crate::quic::{QuicTransport, TransportError};
+use crate::stats::{StreamCounters, StreamStats};
+
+// how long a client the listener accepted has to identify itself and send its config
+static HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// add background tasks for `shutdown` to wait on, dropping the ones that already finished so a
+// long running server doesn't collect a handle for every connection it ever had
+async fn keep_tasks(tasks: &Mutex<Vec<JoinHandle<()>>>, new: impl IntoIterator<Item = JoinHandle<()>>) {
+    let mut tasks = tasks.lock().await;
+    tasks.retain(|task| !task.is_finished());
+    tasks.extend(new);
+}
 
 struct Concurrent {}
 
@@ -46,129 +82,911 @@ impl Concurrent {
 pub trait Server {
     async fn bind(&mut self) -> Result<(), Box<dyn std::error::Error>>;
     async fn list_clients(&self) -> Result<Vec<Client>, Box<dyn std::error::Error>>;
-    async fn disconnect_client(&mut self, url: &str) -> Result<Option<Client>, Box<dyn std::error::Error>>;
-    async fn change_output_device(&mut self, cpos: usize, new_output: cpal::Device) -> Result<(), Box<dyn std::error::Error>>;
+    // clients are addressed by the id they identify with, see `Client::id`
+    async fn disconnect_client(&mut self, id: &str) -> Result<Option<Client>, Box<dyn std::error::Error>>;
+    // moves the client to this one device, saved as a routing rule so it also applies to its
+    // future connections
+    async fn change_output_device(&mut self, id: &str, new_output: &DeviceId) -> Result<(), Box<dyn std::error::Error>>;
+    async fn routing_rules(&self) -> Result<Vec<RoutingRule>, Box<dyn std::error::Error>>;
+    // replaces every rule, connected clients are moved to their new routes right away. rules may
+    // only name pipe sinks added with `add_pipe_sink`
+    async fn set_routing_rules(&mut self, rules: Vec<RoutingRule>) -> Result<(), Box<dyn std::error::Error>>;
+    // what listening clients hear, the default input device until changed
+    async fn change_input_device(&mut self, new_input: &DeviceId) -> Result<(), Box<dyn std::error::Error>>;
+    // what listeners can subscribe to, the server's input and every connected client's audio
+    async fn list_streams(&self) -> Result<Vec<StreamInfo>, Box<dyn std::error::Error>>;
+    // clients join rooms by name when they connect, see `rooms`
+    async fn create_room(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>>;
+    async fn list_rooms(&self) -> Result<Vec<RoomInfo>, Box<dyn std::error::Error>>;
+    // disconnects every client in the room, returns who that was
+    async fn close_room(&mut self, name: &str) -> Result<Vec<Client>, Box<dyn std::error::Error>>;
+    async fn client_stats(&self) -> Result<Vec<(Client, StreamStats)>, Box<dyn std::error::Error>>;
+    // pass clients' streams on to another server as they come and go, see `relay`
+    async fn add_relay(&mut self, config: RelayConfig) -> Result<(), Box<dyn std::error::Error>>;
+    async fn remove_relay(&mut self, url: &str) -> Result<(), Box<dyn std::error::Error>>;
+    async fn list_relays(&self) -> Result<Vec<RelayInfo>, Box<dyn std::error::Error>>;
+    // pull an http audio stream in as a client, see `ingest`. `disconnect_client` stops it
+    async fn add_ingest(&mut self, config: IngestConfig) -> Result<(), Box<dyn std::error::Error>>;
+    // a pipe routing rules can name in `RouteOutput::pipe`, see `pipe`. never reachable over rest
+    async fn add_pipe_sink(&mut self, name: &str, sink: PipeSink) -> Result<(), Box<dyn std::error::Error>>;
+    async fn remove_pipe_sink(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>>;
+    // stop accepting, fade out and close every client, resolves once all background tasks are done
+    async fn shutdown(&mut self) -> Result<(), Box<dyn std::error::Error>>;
 }
-pub struct MetalStream {
-    pub stream: cpal::Stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportKind {
+    WebSocket,
+    Quic,
 }
 
-impl Drop for MetalStream {
-    fn drop(&mut self) {
-        println!("dropping stream!");
+pub struct MetalServer {
+    connections: Arc<Mutex<Vec<Arc<Mutex<Connection>>>>>,
+    address: String,
+    transport: TransportKind,
+    heartbeat: HeartbeatConfig,
+    // flipped once by `shutdown`, every background task watches it
+    shutdown: watch::Sender<bool>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    quic_endpoint: Option<quinn::Endpoint>,
+    metrics: Arc<ServerMetrics>,
+    // where `/metrics` and `/routes` are served, off unless set
+    rest_address: Option<String>,
+    routing: Arc<std::sync::Mutex<RoutingTable>>,
+    rooms: Rooms,
+    captures: Captures,
+    broadcasts: Broadcasts,
+    // tells this server apart on relay paths
+    id: String,
+    // keeps the instance number in `id` ours while the server lives
+    _instance: Instance,
+    relays: Relays,
+    pipes: PipeSinks,
+}
+
+// what connection tasks need from the server, handed to each one the listener or an ingest accepts
+struct ServerShared {
+    connections: Arc<Mutex<Vec<Arc<Mutex<Connection>>>>>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    shutdown: watch::Receiver<bool>,
+    heartbeat: HeartbeatConfig,
+    metrics: Arc<ServerMetrics>,
+    routing: Arc<std::sync::Mutex<RoutingTable>>,
+    rooms: Rooms,
+    captures: Captures,
+    broadcasts: Broadcasts,
+    // this server's id, see `relay`
+    id: String,
+}
+
+#[derive(Clone)]
+struct RestState {
+    connections: Arc<Mutex<Vec<Arc<Mutex<Connection>>>>>,
+    metrics: Arc<ServerMetrics>,
+    routing: Arc<std::sync::Mutex<RoutingTable>>,
+    captures: Captures,
+    broadcasts: Broadcasts,
+    rooms: Rooms,
+    relays: Relays,
+    pipes: PipeSinks,
+    // browsers open their websocket on this port of the page's host
+    server_port: String,
+    // ends audio responses, which would hold up the endpoint's graceful shutdown forever
+    shutdown: watch::Receiver<bool>,
+}
+
+#[derive(Deserialize)]
+struct AudioQuery {
+    room: Option<String>,
+}
+
+// what a listener subscribes to
+enum Stream {
+    Input(Captures),
+    Client(Arc<Broadcast>),
+}
+
+impl Stream {
+    fn subscribe(&self, stats: Arc<StreamCounters>, device_errors: SyncSender<cpal::StreamError>) -> Result<Subscription, Box<dyn std::error::Error>> {
+        match self {
+            Stream::Input(captures) => captures.subscribe(stats, device_errors),
+            Stream::Client(broadcast) => broadcast.subscribe(stats, device_errors)
+        }
     }
 }
 
-impl MetalStream {
-    pub async fn new(connection: &mut Connection) {
-        
-        // Create a delay in case the input and output devices aren't synced.
-        let latency_frames = (150.0 / 1_000.0) * connection.config.sample_rate.0 as f32;
-        let latency_samples = latency_frames as usize * connection.config.channels as usize;
-        
-        let mut buffer = BatchBuffer::new(BUFFER_BATCH_SIZE);
-        buffer.extend(&mut [0.0].repeat(latency_samples).into_iter());
-        
-        connection.buffer = Arc::new(Mutex::new(buffer));
-        connection.stream = None;
-        
-        let output_stream_buffer = Arc::clone(&connection.buffer);
-        let error_stream_is_alive = Arc::clone(&connection.is_alive);
-        
-        let stream = connection
-            .output_device
-            .build_output_stream(
-                &connection.config,
-                move |data: &mut [f32], _| {
-                    
-                    
-                    let mut buffer = output_stream_buffer.blocking_lock();
-                    
+async fn metrics_handler(State(state): State<RestState>) -> String {
+    let clients = MetalServer::collect_stats(&state.connections).await;
 
-                    match buffer.next() {
-                        Some(b) => {
-                            for (b, d) in b.iter().zip(data.iter_mut()) {
-                                *d = b.to_owned();
-                            } 
-                        },
-                        None => {eprintln!("empty buffer!")}
-                    }
-                },
-                move |e| {
+    state.metrics.render(&clients)
+}
 
-                    
-                    *error_stream_is_alive.blocking_lock() = false;
-                    
+async fn get_routes_handler(State(state): State<RestState>) -> Json<Vec<RoutingRule>> {
+    Json(state.routing.lock().unwrap().rules().to_vec())
+}
 
-                    eprintln!("error in stream due to {e}");
-                },
-            Some(Duration::from_secs(5)),
-            )
-            .expect("could not create server output stream!");
+async fn streams_handler(State(state): State<RestState>) -> Json<Vec<StreamInfo>> {
+    Json(MetalServer::collect_streams(&state.connections, &state.captures, &state.broadcasts).await)
+}
 
-        stream.play().expect("could not start server stream!");
+async fn rooms_handler(State(state): State<RestState>) -> Json<Vec<RoomInfo>> {
+    let clients = MetalServer::collect_stats(&state.connections).await;
 
-        connection.stream = Some(MetalStream { stream });
+    Json(MetalServer::room_infos(&state.rooms, clients.iter().map(|(client, _)| client)))
+}
 
+async fn relays_handler(State(state): State<RestState>) -> Json<Vec<RelayInfo>> {
+    Json(state.relays.infos())
+}
+
+async fn listen_handler(State(state): State<RestState>) -> Html<String> {
+    Html(LISTEN_PAGE.replace("{{server_port}}", &state.server_port))
+}
+
+async fn send_handler(State(state): State<RestState>) -> Html<String> {
+    Html(SEND_PAGE.replace("{{server_port}}", &state.server_port))
+}
+
+async fn audio_handler(State(state): State<RestState>, Path(name): Path<String>, Query(query): Query<AudioQuery>) -> HttpResponse {
+    let room = query.room.unwrap_or_else(|| DEFAULT_ROOM.to_owned());
+
+    let stream = match MetalServer::find_stream(&state.connections, &state.captures, &state.broadcasts, &room, &name).await {
+        Ok(stream) => stream,
+        Err(e) => return (StatusCode::NOT_FOUND, e).into_response()
+    };
+    // nothing reads device errors here, the player just hears silence
+    let (device_errors, _) = std::sync::mpsc::sync_channel(1);
+    let subscription = match stream.subscribe(Arc::new(StreamCounters::default()), device_errors).map_err(|e| e.to_string()) {
+        Ok(subscription) => subscription,
+        Err(e) => return (StatusCode::SERVICE_UNAVAILABLE, e).into_response()
+    };
+    info!(stream = %name, room = %room, channels = subscription.config.channels, sample_rate = subscription.config.sample_rate.0, "streaming audio over http");
+
+    let body = StreamBody::new(crate::http_stream::wav_body(subscription, state.shutdown.clone()));
+    ([(header::CONTENT_TYPE, WAV_CONTENT_TYPE), (header::CACHE_CONTROL, "no-cache")], body).into_response()
+}
 
+async fn put_routes_handler(State(state): State<RestState>, Json(rules): Json<Vec<RoutingRule>>) -> (StatusCode, String) {
+    match MetalServer::replace_routes(&state.connections, &state.routing, &state.pipes, rules).await {
+        Ok(()) => (StatusCode::NO_CONTENT, String::new()),
+        Err(e @ RoutesError::UnknownPipe(_)) => (StatusCode::BAD_REQUEST, e.to_string()),
+        Err(e @ RoutesError::Save(_)) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
     }
 }
 
+#[derive(Debug)]
+enum RoutesError {
+    // pipes are set up on the server, rules can only pick one of those
+    UnknownPipe(String),
+    Save(String),
+}
 
-pub struct MetalServer {
-    connections: Arc<Mutex<Vec<Arc<Mutex<Connection>>>>>,
-    address: String
+impl std::fmt::Display for RoutesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoutesError::UnknownPipe(name) => write!(f, "no pipe sink named {}", name),
+            RoutesError::Save(e) => write!(f, "could not save routing rules: {}", e),
+        }
+    }
 }
 
+impl std::error::Error for RoutesError {}
+
 impl Default for MetalServer {
     fn default() -> Self {
-        Self { connections: Default::default(), address: "0.0.0.0:8000".to_owned() }
+        MetalServer::new("0.0.0.0:8000")
     }
 }
 
 impl MetalServer {
     pub fn new(address: &str) -> Self {
+        MetalServer::with_transport(address, TransportKind::WebSocket)
+    }
+
+    // same server, but clients connect over QUIC (`quic://host:port`) instead of websockets
+    pub fn new_quic(address: &str) -> Self {
+        MetalServer::with_transport(address, TransportKind::Quic)
+    }
+
+    // as bound, once `bind` resolved it
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    fn with_transport(address: &str, transport: TransportKind) -> Self {
+        let metrics = Arc::new(ServerMetrics::default());
+        let pipes = PipeSinks::default();
+
+        let (id, instance) = crate::relay::server_id();
+
         MetalServer {
             connections: Arc::new(Mutex::new(vec![])),
-            address: address.to_owned()
+            address: address.to_owned(),
+            transport,
+            heartbeat: HeartbeatConfig::default(),
+            shutdown: watch::channel(false).0,
+            tasks: Arc::new(Mutex::new(vec![])),
+            quic_endpoint: None,
+            rooms: Rooms::new(Arc::clone(&metrics), pipes.clone()),
+            captures: Captures::default(),
+            broadcasts: Broadcasts::default(),
+            id,
+            _instance: instance,
+            relays: Relays::default(),
+            pipes,
+            metrics,
+            rest_address: None,
+            routing: Arc::new(std::sync::Mutex::new(RoutingTable::default())),
+        }
+    }
+
+    // serve prometheus metrics (`GET /metrics`), the routing rules (`GET`/`PUT /routes`), the
+    // streams listeners can pick from (`GET /streams`), the rooms (`GET /rooms`) and the relays with
+    // the streams they send (`GET /relays`) on `address`. `GET /listen` is a page that plays any of
+    // the streams in a browser and `GET /send` one that sends the browser's microphone, both over a
+    // websocket to the server's own address. `GET /audio/<stream>?room=<room>` plays a stream in
+    // media players, see `http_stream`
+    // once bound, e.g. the shared `rest_endpoint`
+    pub fn set_rest_endpoint(&mut self, address: &str) {
+        self.rest_address = Some(address.to_owned());
+    }
+
+    // load routing rules from `path` and save every change back to it, see `RoutingTable::default_path`
+    pub fn set_routing_file(&mut self, path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+        *self.routing.lock().unwrap() = RoutingTable::load(path)?;
+
+        Ok(())
+    }
+
+    // check and save new rules, then move the clients they affect. what both `set_routing_rules`
+    // and the rest endpoint go through
+    async fn replace_routes(connections: &Arc<Mutex<Vec<Arc<Mutex<Connection>>>>>, routing: &Arc<std::sync::Mutex<RoutingTable>>, pipes: &PipeSinks, rules: Vec<RoutingRule>) -> Result<(), RoutesError> {
+        let unknown = rules.iter()
+            .flat_map(|rule| rule.route.outputs.iter())
+            .filter_map(|output| output.pipe.as_ref())
+            .find(|name| pipes.get(name).is_none());
+        if let Some(name) = unknown {
+            return Err(RoutesError::UnknownPipe(name.clone()));
+        }
+
+        routing.lock().unwrap().set_rules(rules).map_err(|e| RoutesError::Save(e.to_string()))?;
+        MetalServer::apply_routes(connections, routing).await;
+
+        Ok(())
+    }
+
+    // move every connected client whose route changed
+    async fn apply_routes(connections: &Arc<Mutex<Vec<Arc<Mutex<Connection>>>>>, routing: &Arc<std::sync::Mutex<RoutingTable>>) {
+        let connections = connections.lock().await;
+
+        for connection in connections.iter() {
+            let mut connection = connection.lock().await;
+            let route = routing.lock().unwrap().route_for(&connection.client);
+
+            if route != connection.route {
+                connection.set_route(route);
+            }
+
+            for source in connection.sources.iter_mut() {
+                let route = routing.lock().unwrap().route_for(&source.client);
+
+                if route != source.route {
+                    source.set_route(route);
+                }
+            }
+        }
+    }
+
+    async fn collect_stats(connections: &Arc<Mutex<Vec<Arc<Mutex<Connection>>>>>) -> Vec<(Client, StreamStats)> {
+        let connections = connections.lock().await;
+
+        let stats = Concurrent::lock_all_ordered(connections.iter().map(|connection| connection.lock()))
+            .await
+            .iter()
+            .flat_map(|connection| {
+                let sources = connection.sources.iter().map(|source| (source.client.clone(), source.stats()));
+                std::iter::once((connection.client.clone(), connection.stats())).chain(sources).collect::<Vec<(Client, StreamStats)>>()
+            })
+            .collect::<Vec<(Client, StreamStats)>>();
+
+        stats
+    }
+
+    async fn collect_streams(connections: &Arc<Mutex<Vec<Arc<Mutex<Connection>>>>>, captures: &Captures, broadcasts: &Broadcasts) -> Vec<StreamInfo> {
+        let mut streams = vec![StreamInfo {
+            name: INPUT_STREAM.to_owned(),
+            source: None,
+            room: Some(DEFAULT_ROOM.to_owned()),
+            listeners: captures.listeners(),
+        }];
+
+        let connections = connections.lock().await;
+        for connection in connections.iter() {
+            let connection = connection.lock().await;
+
+            if connection.client.role != ClientRole::Listener {
+                streams.push(StreamInfo {
+                    name: connection.client.name.clone(),
+                    source: Some(connection.client.id.clone()),
+                    room: Some(connection.client.room.clone()),
+                    listeners: broadcasts.find(&connection.client.room, &connection.client.id).map(|broadcast| broadcast.listeners()).unwrap_or(0),
+                });
+            }
+
+            for source in connection.sources.iter() {
+                streams.push(StreamInfo {
+                    name: source.client.name.clone(),
+                    source: Some(source.client.id.clone()),
+                    room: Some(source.client.room.clone()),
+                    listeners: broadcasts.find(&source.client.room, &source.client.id).map(|broadcast| broadcast.listeners()).unwrap_or(0),
+                });
+            }
+        }
+
+        streams
+    }
+
+    // what a listener in `room` asking for `name` hears: the server's input (default room only), or
+    // the client in the same room with that id or name
+    async fn find_stream(connections: &Arc<Mutex<Vec<Arc<Mutex<Connection>>>>>, captures: &Captures, broadcasts: &Broadcasts, room: &str, name: &str) -> Result<Stream, String> {
+        if name == INPUT_STREAM {
+            return MetalServer::input_stream(captures, room);
+        }
+
+        let connections = connections.lock().await;
+        for connection in connections.iter() {
+            let connection = connection.lock().await;
+            let sources = connection.sources.iter().map(|source| &source.client);
+
+            for client in std::iter::once(&connection.client).chain(sources) {
+                if client.role != ClientRole::Listener && client.room == room && (client.id == name || client.name == name) {
+                    return broadcasts.find(room, &client.id).map(Stream::Client).ok_or_else(|| format!("stream {} has not started", name));
+                }
+            }
+        }
+
+        Err(format!("no stream named {} in room {}", name, room))
+    }
+
+    // the server's input device, which only the default room hears
+    fn input_stream(captures: &Captures, room: &str) -> Result<Stream, String> {
+        match room == DEFAULT_ROOM {
+            true => Ok(Stream::Input(captures.clone())),
+            false => Err(format!("the server's input is only heard in the {} room, not in {}", DEFAULT_ROOM, room))
+        }
+    }
+
+    // every stream coming in, with the hop into this server added to its path
+    async fn collect_relay_sources(connections: &Arc<Mutex<Vec<Arc<Mutex<Connection>>>>>, broadcasts: &Broadcasts, server: &str) -> Vec<RelaySource> {
+        let mut sources = vec![];
+
+        let connections = connections.lock().await;
+        for connection in connections.iter() {
+            let connection = connection.lock().await;
+            if connection.client.role == ClientRole::Listener {
+                continue;
+            }
+
+            let mut path = connection.client.hops.clone();
+            path.push(BinHop {
+                server: server.to_owned(),
+                latency_ms: connection.rtt().map(|rtt| rtt.as_secs_f32() * 500.0),
+            });
+
+            let clients = std::iter::once(&connection.client).chain(connection.sources.iter().map(|source| &source.client));
+            for client in clients {
+                if let Some(broadcast) = broadcasts.find(&client.room, &client.id) {
+                    sources.push(RelaySource { client: client.clone(), path: path.clone(), broadcast });
+                }
+            }
+        }
+
+        sources
+    }
+
+    fn room_infos<'a>(rooms: &Rooms, clients: impl Iterator<Item = &'a Client> + Clone) -> Vec<RoomInfo> {
+        rooms.names().into_iter().map(|name| RoomInfo {
+            clients: clients.clone().filter(|client| client.room == name && client.parent.is_none()).count(),
+            name,
+        }).collect()
+    }
+
+    // applies to connections accepted after the call
+    pub fn set_heartbeat_config(&mut self, config: HeartbeatConfig) {
+        self.heartbeat = config;
+    }
+
+    // finish the handshake, start the output stream and pump incoming frames into its buffer
+    fn shared(&self) -> Arc<ServerShared> {
+        Arc::new(ServerShared {
+            connections: Arc::clone(&self.connections),
+            tasks: Arc::clone(&self.tasks),
+            shutdown: self.shutdown.subscribe(),
+            heartbeat: self.heartbeat,
+            metrics: Arc::clone(&self.metrics),
+            routing: Arc::clone(&self.routing),
+            rooms: self.rooms.clone(),
+            captures: self.captures.clone(),
+            broadcasts: self.broadcasts.clone(),
+            id: self.id.clone(),
+        })
+    }
+
+    // a `handshake_timeout` gives up on clients that don't identify themselves in time
+    async fn accept_connection(shared: &Arc<ServerShared>, name: &str, mut transport: ServerTransport, handshake_timeout: Option<Duration>) {
+        let ServerShared { connections, tasks, shutdown, heartbeat, metrics, routing, rooms, captures, broadcasts, id: server } = &**shared;
+        let heartbeat = *heartbeat;
+
+        let handshake = match handshake_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, Connection::handshake(name, &mut transport)).await
+                .unwrap_or_else(|_| Err(format!("no handshake within {:?}", timeout).into())),
+            None => Connection::handshake(name, &mut transport).await,
+        }.map_err(|e| e.to_string());
+        let (client, config) = match handshake {
+            Ok(handshake) => handshake,
+            Err(e) => {
+                warn!(peer = %name, "handshake failed due to {}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = crate::relay::check_path(server, &client.hops) {
+            warn!(peer = %name, client = %client.id, "refusing relayed stream, {}", e);
+            transport.close(&e).await;
+            return;
+        }
+
+        let mixers = match rooms.mixers(&client.room) {
+            Some(mixers) => mixers,
+            None => {
+                warn!(peer = %name, room = %client.room, "client asked for a room that doesn't exist");
+                transport.close(&format!("no room named {}", client.room)).await;
+                return;
+            }
+        };
+
+        metrics.connected(&client);
+        let connection = match config {
+            Some(config) => {
+                let route = routing.lock().unwrap().route_for(&client);
+                let broadcast = broadcasts.stream(&client.room, &client.id);
+                Connection::new(client, config, transport, route, heartbeat, mixers, broadcast).await
+            },
+            None => {
+                let stream_name = client.stream.clone().unwrap_or_else(|| INPUT_STREAM.to_owned());
+                let stream = match MetalServer::find_stream(connections, captures, broadcasts, &client.room, &stream_name).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!(peer = %name, "could not start listener due to {}", e);
+                        transport.close(&e).await;
+                        return;
+                    }
+                };
+
+                match Connection::new_listener(client, transport, heartbeat, mixers, &stream).await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        warn!(peer = %name, "could not start listener due to {}", e);
+                        return;
+                    }
+                }
+            }
+        };
+
+        let span = connection.lock().await.span.clone();
+        span.in_scope(|| info!("client connected"));
+        let buffer_connection = Arc::clone(&connection);
+        let heartbeat_connection = Arc::clone(&connection);
+        let buffer_shutdown = shutdown.clone();
+        let buffer_captures = captures.clone();
+        let buffer_broadcasts = broadcasts.clone();
+        let buffer_routing = Arc::clone(routing);
+        let buffer_server = server.to_owned();
+        let mut heartbeat_shutdown = shutdown.clone();
+
+        // ping the client and drop it once it goes silent, a half open connection never errors out
+        let heartbeat_task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(heartbeat.interval) => {},
+                    _ = heartbeat_shutdown.changed() => return,
+                }
+
+                let mut ul_connection = heartbeat_connection.lock().await;
+
+                if !*ul_connection.is_alive.lock().await {
+                    return;
+                }
+
+                if ul_connection.heartbeat.is_expired() {
+                    warn!("no heartbeat in {:?}, dropping connection", heartbeat.timeout);
+                    *ul_connection.is_alive.lock().await = false;
+                    return;
+                }
+
+                let ping = ul_connection.heartbeat.ping();
+                if let Err(e) = ul_connection.send(&ping).await {
+                    warn!("could not ping client due to {}", e);
+                    *ul_connection.is_alive.lock().await = false;
+                    return;
+                }
+            }
+        }.instrument(span.clone()));
+
+        //write data from transport to buffer
+        let buffer_task = tokio::spawn(async move {
+            loop {
+                
+                let mut ul_connection = buffer_connection.lock().await;
+
+                if *buffer_shutdown.borrow() {
+                    return;
+                }
+
+                // a failed device takes the connection down with it, the client reconnects
+                let mut device_failed = false;
+                while let Ok(e) = ul_connection.device_errors.try_recv() {
+                    error!("audio device error: {}", e);
+                    device_failed = true;
+                }
+                if device_failed {
+                    *ul_connection.is_alive.lock().await = false;
+                    return;
+                }
+
+                // audio for listeners, queued by whatever publishes the stream
+                let mut casts = vec![];
+                let mut cut_off = false;
+                if let Some(capture) = ul_connection.capture.as_mut() {
+                    loop {
+                        match capture.receiver.try_recv() {
+                            Ok(cast) => casts.push(cast),
+                            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => break,
+                            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
+                                cut_off = true;
+                                break;
+                            }
+                        }
+                    }
+                }
+                for cast in casts.iter() {
+                    let sent = match cast {
+                        Cast::Config(config) => {
+                            info!(channels = config.channels, sample_rate = config.sample_rate.0, "stream format changed");
+                            ul_connection.set_capture_config(config);
+                            ul_connection.send(&crate::BinMessages::BinConfig(crate::BinStreamConfig::from(config))).await
+                        },
+                        Cast::Block(block) => ul_connection.send_block(block).await
+                    };
+
+                    if let Err(e) = sent {
+                        warn!("could not send audio to client due to {}", e);
+                        *ul_connection.is_alive.lock().await = false;
+                        return;
+                    }
+                }
+                if cut_off {
+                    warn!("listener fell too far behind the stream, dropping connection");
+                    ul_connection.transport.close("too slow to keep up with the stream").await;
+                    *ul_connection.is_alive.lock().await = false;
+                    return;
+                }
+                
+
+                match ul_connection.transport.recv().now_or_never() {
+                    // lost on the way, played as concealment so the audio after it stays in time
+                    Some(Some(Ok(Received::Missing(samples)))) => {
+                        for tap in ul_connection.taps.iter() {
+                            tap.push_missing(samples);
+                        }
+                    },
+                    Some(Some(Ok(Received::Message(message)))) => {
+
+                        let deserialized = match bincode::deserialize(&message) {
+                            Ok(deserialized) => deserialized,
+                            Err(e) => {
+                                warn!("could not deserialize client to server message due to {}", e);
+                                continue;
+                            }
+                        };
+
+
+
+                        let frames = match &deserialized {
+                            crate::BinMessages::BinData(data) => data.len() / ul_connection.config.channels.max(1) as usize,
+                            _ => 0
+                        };
+                        // a source's audio is counted by the source
+                        if !matches!(deserialized, crate::BinMessages::BinSourceData(..)) {
+                            ul_connection.stats.received(message.len() as u64, frames as u64);
+                        }
+
+                        if frames > 0 {
+                            let duration = Duration::from_secs_f32(frames as f32 / ul_connection.config.sample_rate.0 as f32);
+                            ul_connection.stats.arrival(duration);
+                        }
+
+                        if let Some(reply) = ul_connection.heartbeat.on_message(&deserialized) {
+                            if let Err(e) = ul_connection.send(&reply).await {
+                                warn!("could not answer ping due to {}", e);
+                            }
+                        }
+
+                        match deserialized {
+                            crate::BinMessages::BinPing(_) | crate::BinMessages::BinPong(_) => {},
+                            // a source that also wants the server's input back, over this same connection
+                            crate::BinMessages::BinListen => {
+                                let listened = MetalServer::input_stream(&buffer_captures, &ul_connection.client.room)
+                                    .and_then(|input| ul_connection.listen(INPUT_STREAM, &input).map_err(|e| e.to_string()));
+
+                                match listened {
+                                    Ok(config) => {
+                                        info!("client is listening too, connection is duplex");
+                                        if let Err(e) = ul_connection.send(&config).await {
+                                            warn!("could not send config to client due to {}", e);
+                                        }
+                                    },
+                                    Err(e) => warn!("could not start listening due to {}", e)
+                                }
+                            },
+                            // other streams are only found when connecting, see `find_stream`
+                            crate::BinMessages::BinSubscribe(name) => warn!(stream = %name, "streams can only be subscribed to when connecting"),
+                            crate::BinMessages::BinJoin(room) => warn!(room = %room, "rooms can only be joined when connecting"),
+                            crate::BinMessages::BinData(data) => {
+                                for tap in ul_connection.taps.iter() {
+                                    tap.push(&data);
+                                }
+
+                                if let Some(broadcast) = &ul_connection.broadcast {
+                                    broadcast.publish(&data);
+                                }
+                            },
+                            // a relay passing on new latencies, or a path that changed under it
+                            crate::BinMessages::BinRelay(hops) => {
+                                if let Err(e) = crate::relay::check_path(&buffer_server, &hops) {
+                                    warn!("dropping relayed stream, {}", e);
+                                    ul_connection.transport.close(&e).await;
+                                    *ul_connection.is_alive.lock().await = false;
+                                    return;
+                                }
+
+                                for source in ul_connection.sources.iter_mut() {
+                                    source.client.hops = hops.clone();
+                                }
+                                ul_connection.client.hops = hops;
+                            },
+                            crate::BinMessages::BinSourceConfig(bin_source) => {
+                                let connection = &mut *ul_connection;
+
+                                match connection.sources.iter_mut().find(|source| source.id == bin_source.id) {
+                                    Some(source) => source.set_config(&bin_source),
+                                    None if bin_source.id == 0 => warn!("source 0 is the main stream, ignoring it"),
+                                    None if connection.client.role == ClientRole::Listener => warn!("listeners can't send sources"),
+                                    None => {
+                                        let client = crate::sources::source_client(&connection.client, &bin_source);
+                                        let route = buffer_routing.lock().unwrap().route_for(&client);
+                                        let broadcast = buffer_broadcasts.stream(&client.room, &client.id);
+
+                                        let carrier = Carrier {
+                                            mixers: connection.mixers.clone(),
+                                            fade_out: Arc::clone(&connection.fade_out),
+                                            device_errors: connection.device_error_sender.clone(),
+                                            span: connection.span.clone(),
+                                        };
+                                        let source = Source::new(&bin_source, client, route, broadcast, carrier);
+                                        connection.sources.push(source);
+                                    }
+                                }
+                            },
+                            crate::BinMessages::BinSourceData(id, data) => {
+                                match ul_connection.sources.iter().find(|source| source.id == id) {
+                                    Some(source) => source.push(&data, message.len() as u64),
+                                    None => debug!(source = id, "audio for a source that was never configured")
+                                }
+                            },
+                            crate::BinMessages::BinSourceClose(id) => {
+                                ul_connection.sources.retain(|source| source.id != id);
+                                info!(source = id, "source removed");
+                            },
+                            crate::BinMessages::BinConfig(config) => {
+                                info!(channels = config.channels, sample_rate = config.sample_rate, "received config");
+                                let config = cpal::StreamConfig {
+                                    channels: config.channels,
+                                    sample_rate: cpal::SampleRate(config.sample_rate),
+                                    buffer_size: cpal::BufferSize::Default
+                                };
+
+                                if let Some(broadcast) = &ul_connection.broadcast {
+                                    broadcast.set_config(&config);
+                                }
+
+                                ul_connection.config = config;
+                                ul_connection.open_outputs();
+                            },
+                            crate::BinMessages::BinIdentify(_) => warn!("clients can only identify when connecting"),
+                            // the transport unwraps these, they only get here from a confused peer
+                            crate::BinMessages::BinFrame(_) | crate::BinMessages::BinParity(_) | crate::BinMessages::BinLossReport(_) => warn!("ignoring a datagram message sent as a control message"),
+                            crate::BinMessages::BinHello | crate::BinMessages::BinDevicesRequest | crate::BinMessages::BinDevicesResponse(_) => warn!("ignoring a message the server does not handle"),
+                        };
+
+                        
+
+                    },
+                    Some(Some(Err(e))) => {
+                        
+                        *ul_connection.is_alive.lock().await = false;
+                        
+
+                        warn!("error reading from client due to {}", e);
+                        return;
+                    },
+                    Some(None) => {
+                        
+                        
+                        *ul_connection.is_alive.lock().await = false;
+                        
+
+                        info!("client closed the connection");
+                        return;
+                    },
+                    None => {
+                        continue;
+                    }
+                }
+            }
+        }.instrument(span));
+
+        keep_tasks(tasks, [heartbeat_task, buffer_task]).await;
+
+        let mut connections = connections.lock().await;
+        let id = connection.lock().await.client.id.clone();
+
+        let mut existing = None;
+        for (i, other) in connections.iter().enumerate() {
+            if other.lock().await.client.id == id {
+                existing = Some(i);
+                break;
+            }
+        }
+
+        // the same client again, most likely the old connection is half open and not reaped yet
+        match existing {
+            Some(i) => {
+                let replaced = std::mem::replace(&mut connections[i], connection);
+                let mut replaced = replaced.lock().await;
+
+                replaced.close_outputs();
+                replaced.transport.close("replaced by a new connection").await;
+                *replaced.is_alive.lock().await = false;
+                info!(parent: &replaced.span, "replaced by a new connection from the same client");
+            },
+            None => connections.push(connection)
         }
     }
 }
 
-pub struct BatchBuffer<T> {
-    batch_size: usize,
-    buffer: Vec<T>
+enum Listener {
+    WebSocket(TcpListener),
+    Quic(quinn::Endpoint),
+}
+
+// a client the listener accepted whose transport isn't set up yet
+enum Incoming {
+    WebSocket(TcpStream),
+    Quic(quinn::Endpoint, quinn::Connecting),
 }
 
-impl<T: Clone> BatchBuffer<T> {
-    pub fn new(batch_size: usize) -> BatchBuffer<T> {
-        BatchBuffer {
-            batch_size,
-            buffer: vec![]
+impl Listener {
+    // next client, `None` once the listener can't accept anymore. setting up its transport waits
+    // on the client, so that is left to `Incoming::transport`
+    async fn accept(&self) -> Option<Result<Incoming, TransportError>> {
+        match self {
+            Listener::WebSocket(listener) => Some(match listener.accept().await {
+                Ok((tcp_stream, _)) => Ok(Incoming::WebSocket(tcp_stream)),
+                Err(e) => Err(e.into())
+            }),
+            Listener::Quic(endpoint) => {
+                Some(Ok(Incoming::Quic(endpoint.clone(), endpoint.accept().await?)))
+            }
         }
     }
+}
 
-    pub fn next(&mut self) -> Option<Vec<T>> {
-        
-        let removed = self.buffer.get(0..self.batch_size)?.to_owned();
+impl Incoming {
+    async fn transport(self) -> Result<(String, ServerTransport), TransportError> {
+        match self {
+            Incoming::WebSocket(tcp_stream) => {
+                let name = tcp_stream.peer_addr()?.to_string();
+
+                // the path the websocket was opened on says which framing the client speaks
+                let mut path = String::new();
+                let websocket = accept_hdr_async(tcp_stream, RequestPath(&mut path)).await?;
+
+                if path.starts_with(BROWSER_PATH) {
+                    Ok((name, ServerTransport::Browser(BrowserTransport::new(websocket))))
+                } else {
+                    Ok((name, ServerTransport::WebSocket(websocket)))
+                }
+            },
+            Incoming::Quic(endpoint, connecting) => {
+                let quic = QuicTransport::accept_connecting(&endpoint, connecting).await?;
+                Ok((quic.remote_address().to_string(), ServerTransport::Quic(quic)))
+            }
+        }
+    }
+}
+
+// keeps the path of a websocket request. a callback type rather than a closure, the handshake's
+// error response is far too big for a closure's result
+struct RequestPath<'a>(&'a mut String);
+
+impl Callback for RequestPath<'_> {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        *self.0 = request.uri().path().to_owned();
+        Ok(response)
+    }
+}
 
-        self.buffer.reverse();
-        self.buffer.truncate(self.batch_size);
-        self.buffer.reverse();
+// the wire a server side connection receives frames on
+pub enum ServerTransport {
+    WebSocket(WebSocketStream<TcpStream>),
+    Quic(QuicTransport),
+    // a websocket from a web page, see `browser`
+    Browser(BrowserTransport),
+    // a stream the server fetches itself, see `ingest`
+    Ingest(IngestTransport),
+}
 
-        if removed.is_empty() {
-            return None;
-        } else {
-            return Some(removed);
+impl ServerTransport {
+    pub async fn recv(&mut self) -> Option<Result<Received, TransportError>> {
+        let message = match self {
+            ServerTransport::WebSocket(websocket) => match websocket.next().await? {
+                Ok(message) => Ok(message.into_data()),
+                Err(e) => Err(e.into())
+            },
+            ServerTransport::Quic(quic) => return quic.recv().await,
+            ServerTransport::Browser(browser) => browser.recv().await?,
+            ServerTransport::Ingest(ingest) => ingest.recv().await?
+        };
+
+        Some(message.map(Received::Message))
+    }
+
+    pub async fn send(&mut self, message: &crate::BinMessages) -> Result<(), TransportError> {
+        match self {
+            ServerTransport::WebSocket(websocket) => {
+                websocket.send(Message::binary(bincode::serialize(message)?)).await?;
+                Ok(())
+            },
+            ServerTransport::Quic(quic) => quic.send(message).await,
+            ServerTransport::Browser(browser) => browser.send(message).await,
+            ServerTransport::Ingest(ingest) => ingest.send(message)
         }
     }
 
-    pub fn extend(&mut self, iter: &mut dyn Iterator<Item = T>) {
-        self.buffer.extend(iter);
+    // audio serialized once for every listener of a stream, QUIC still splits it into datagrams
+    pub async fn send_block(&mut self, block: &Block) -> Result<(), TransportError> {
+        match self {
+            ServerTransport::WebSocket(websocket) => {
+                websocket.send(Message::binary(block.encoded.clone())).await?;
+                Ok(())
+            },
+            ServerTransport::Quic(quic) => quic.send_data(&block.samples),
+            ServerTransport::Browser(browser) => browser.send_block(block).await,
+            // nothing listens on an ingest
+            ServerTransport::Ingest(_) => Ok(())
+        }
     }
 
-    pub fn push(&mut self, to_push: T) {
-        self.buffer.push(to_push);
+    // tell the client why it is being let go
+    pub async fn close(&mut self, reason: &str) {
+        match self {
+            ServerTransport::WebSocket(websocket) => {
+                let _ = websocket.close(Some(CloseFrame { code: CloseCode::Away, reason: reason.to_owned().into() })).await;
+            },
+            ServerTransport::Quic(quic) => quic.close(reason),
+            ServerTransport::Browser(browser) => browser.close(Some(reason)).await,
+            ServerTransport::Ingest(ingest) => ingest.close()
+        }
     }
 }
 
@@ -177,87 +995,321 @@ unsafe impl std::marker::Send for Connection {}
 
 pub struct Connection {
     client: Client,
-    output_device: cpal::Device,
-    buffer: Arc<Mutex<BatchBuffer<f32>>>,
-    stream: Option<MetalStream>,
+    route: Route,
+    // one per route output, each queues its own copy of the incoming audio
+    taps: Vec<Arc<Tap>>,
+    mixers: Mixers,
+    // where this client's audio is published for listeners, sources only
+    broadcast: Option<Arc<Broadcast>>,
+    // the client's other capture devices, each a source of its own
+    sources: Vec<Source>,
+    // the stream this connection plays, listening and duplex clients only
+    capture: Option<Subscription>,
+    // the same counters as `stats` unless the connection is duplex, then audio going out is
+    // counted apart from audio coming in
+    capture_stats: Arc<StreamCounters>,
     config: StreamConfig,
-    websocket: WebSocketStream<TcpStream>,
-    is_alive: Arc<Mutex<bool>>
+    transport: ServerTransport,
+    is_alive: Arc<Mutex<bool>>,
+    concealed_frames: Arc<AtomicU64>,
+    heartbeat: Heartbeat,
+    fade_out: Arc<AtomicBool>,
+    stats: Arc<StreamCounters>,
+    device_error_sender: SyncSender<cpal::StreamError>,
+    device_errors: Receiver<cpal::StreamError>,
+    // peer address and output device, every task for this connection logs inside it
+    span: tracing::Span,
 }
 
 impl Drop for Connection {
     fn drop(&mut self) {
-        println!("dropping connection");
+        debug!(parent: &self.span, "dropping connection");
 
-        match &self.stream {
-            Some(stream) => {
-                let _ = stream.stream.pause();
-            },
-            None => {
+        self.close_outputs();
 
-            }
+        // websockets need a task to close, every path that lets a connection go closes it first.
+        // one dropped without that still has its tcp connection dropped with it
+        match &mut self.transport {
+            ServerTransport::WebSocket(_) | ServerTransport::Browser(_) => {},
+            ServerTransport::Quic(quic) => quic.close("connection dropped"),
+            ServerTransport::Ingest(ingest) => ingest.close()
         }
-
-        let _ = self.websocket.close(None);
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Clone, Debug)]
 pub struct Client {
+    // peer address, changes with every connection
     pub url: String,
+    // stable across reconnects, clients that don't identify get their url
+    pub id: String,
+    pub name: String,
+    pub role: ClientRole,
+    // the stream a listening or duplex client hears
+    pub stream: Option<String>,
+    pub room: String,
+    // the client whose connection carries this one, for its extra sources
+    pub parent: Option<String>,
+    // the servers a relayed client's stream came through, see `relay`
+    pub hops: Vec<BinHop>,
+}
 
+// which way the audio goes
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientRole {
+    // captures and sends, played on the server's outputs
+    Source,
+    // plays a stream, the server's input or another client's audio
+    Listener,
+    // both at once over one connection
+    Duplex,
 }
 
 impl Connection {
-    pub async fn new(address: &str, tcp_stream: TcpStream) -> Result<Arc<Mutex<Connection>>, Box<dyn std::error::Error>> {
+    // frames of audio the output stream had to make up because nothing arrived in time
+    pub fn concealed_frames(&self) -> u64 {
+        self.concealed_frames.load(Ordering::Relaxed)
+    }
 
-        let output_device = cpal::default_host().default_output_device().expect("could not find default input device!");
-        let client = Client {
-            url: address.to_owned()
-        };
+    // round trip to the client, measured from the last answered ping
+    pub fn rtt(&self) -> Option<Duration> {
+        self.heartbeat.rtt()
+    }
 
-        let mut websocket = 
-            accept_async(tcp_stream)
-            .await
-            .expect("could not establish websocket stream!");
+    // parity recovery counters, only lossy transports have them
+    pub fn fec_stats(&self) -> Option<FecStats> {
+        match &self.transport {
+            ServerTransport::Quic(quic) => Some(quic.fec_stats()),
+            _ => None
+        }
+    }
 
-        let config_message = 
-            websocket
-            .next()
-            .await
-            .expect("could not get config message!").expect("error getting config message!")
-            .into_data();
+    pub fn stats(&self) -> StreamStats {
+        let mut stats = self.stats.snapshot();
+        stats.concealed_frames = self.concealed_frames();
+        stats.rtt_ms = self.rtt().map(|rtt| rtt.as_secs_f32() * 1_000.0);
+        stats.fec = self.fec_stats();
+        stats.hops = self.client.hops.clone();
+        stats.buffer_ms = stats.buffer_frames as f32 * 1_000.0 / self.config.sample_rate.0.max(1) as f32;
+
+        if self.client.role == ClientRole::Duplex {
+            let mut reverse = self.capture_stats.snapshot();
+            reverse.rtt_ms = stats.rtt_ms;
+            stats.reverse = Some(Box::new(reverse));
+        }
 
-        let bin_config = match bincode::deserialize(&config_message).expect("could not deserialize config!") {
-            crate::BinMessages::BinConfig(config) => config,
-            _ => panic!("unexpected message between client and server!")
-        };
+        stats
+    }
+
+    // send and count it, audio goes through `send_block`
+    async fn send(&mut self, message: &crate::BinMessages) -> Result<(), TransportError> {
+        let size = bincode::serialized_size(message).unwrap_or(0);
+
+        self.transport.send(message).await?;
+        self.stats.sent(size, 0);
+
+        Ok(())
+    }
 
-        let config = StreamConfig {
-            buffer_size: cpal::BufferSize::Default, 
-            channels: bin_config.channels, 
-            sample_rate: cpal::SampleRate(bin_config.sample_rate)
+    // a block of the stream this connection listens to
+    async fn send_block(&mut self, block: &Block) -> Result<(), TransportError> {
+        self.transport.send_block(block).await?;
+        self.capture_stats.sent(block.encoded.len() as u64, block.frames);
+
+        Ok(())
+    }
+
+    // who is connecting and what they send, an optional identity and room followed by the stream
+    // config. listeners send `BinListen` or `BinSubscribe` instead and get no config, they play a stream
+    pub async fn handshake(address: &str, transport: &mut ServerTransport) -> Result<(Client, Option<StreamConfig>), Box<dyn std::error::Error>> {
+        let mut client = Client {
+            url: address.to_owned(),
+            id: address.to_owned(),
+            name: address.to_owned(),
+            role: ClientRole::Source,
+            stream: None,
+            room: DEFAULT_ROOM.to_owned(),
+            parent: None,
+            hops: vec![],
         };
 
-        let connection = Arc::new(Mutex::new(Connection {
+        loop {
+            let message = match transport.recv().await {
+                Some(Ok(Received::Message(message))) => message,
+                // audio lost before the config, there is nothing to conceal yet
+                Some(Ok(Received::Missing(_))) => continue,
+                Some(Err(e)) => return Err(e.to_string().into()),
+                None => return Err("connection closed during handshake".into())
+            };
+
+            match bincode::deserialize(&message)? {
+                crate::BinMessages::BinIdentify(identity) => {
+                    client.id = identity.id;
+                    client.name = identity.name;
+                },
+                crate::BinMessages::BinJoin(room) => client.room = room,
+                crate::BinMessages::BinRelay(hops) => client.hops = hops,
+                crate::BinMessages::BinConfig(bin_config) => {
+                    let config = StreamConfig {
+                        buffer_size: cpal::BufferSize::Default, 
+                        channels: bin_config.channels, 
+                        sample_rate: cpal::SampleRate(bin_config.sample_rate)
+                    };
+
+                    return Ok((client, Some(config)));
+                },
+                crate::BinMessages::BinListen => {
+                    client.role = ClientRole::Listener;
+                    client.stream = Some(INPUT_STREAM.to_owned());
+
+                    return Ok((client, None));
+                },
+                crate::BinMessages::BinSubscribe(name) => {
+                    client.role = ClientRole::Listener;
+                    client.stream = Some(name);
+
+                    return Ok((client, None));
+                },
+                // QUIC datagrams race the control stream, audio that beats the config has nothing
+                // to be played with yet
+                crate::BinMessages::BinData(_) | crate::BinMessages::BinFrame(_) | crate::BinMessages::BinParity(_) | crate::BinMessages::BinSourceData(..) | crate::BinMessages::BinLossReport(_) | crate::BinMessages::BinPing(_) | crate::BinMessages::BinPong(_) => {
+                    debug!(peer = %address, "skipping audio that arrived before the config");
+                },
+                _ => return Err("unexpected message between client and server!".into())
+            }
+        }
+    }
+
+    // stop playing on every output (or stop listening), devices nobody else uses are closed
+    pub fn close_outputs(&mut self) {
+        for tap in self.taps.drain(..) {
+            self.mixers.detach(&tap);
+        }
+        self.capture = None;
+    }
+
+    // (re)start playing on every output of the route, an output that can't be opened is skipped
+    pub fn open_outputs(&mut self) {
+        self.close_outputs();
+
+        for (i, output) in self.route.outputs.iter().enumerate() {
+            let source = TapSource {
+                config: self.config.clone(),
+                primary: i == 0,
+                stats: Arc::clone(&self.stats),
+                concealed_frames: Arc::clone(&self.concealed_frames),
+                fade_out: Arc::clone(&self.fade_out),
+                device_errors: self.device_error_sender.clone(),
+                echo_reference: None,
+            };
+
+            match self.mixers.attach(output, &source) {
+                Ok(tap) => self.taps.push(tap),
+                Err(e) => warn!(parent: &self.span, "could not play on output {} due to {}", i, e)
+            }
+        }
+
+        let devices = self.taps.iter().map(|tap| tap.device()).collect::<Vec<&str>>().join(", ");
+        self.span.record("device", devices.as_str());
+    }
+
+    // switch to other outputs, gains or channel links
+    pub fn set_route(&mut self, route: Route) {
+        self.route = route;
+        self.open_outputs();
+
+        self.span.in_scope(|| info!(outputs = self.taps.len(), "route changed"));
+    }
+
+    fn build(client: Client, config: StreamConfig, transport: ServerTransport, route: Route, heartbeat: HeartbeatConfig, mixers: Mixers) -> Connection {
+        let span = info_span!("connection", peer = %client.url, client = %client.name, id = %client.id, room = %client.room, device = tracing::field::Empty);
+        let (device_error_sender, device_errors) = crate::logging::device_error_channel();
+        let stats = Arc::new(StreamCounters::default());
+
+        Connection {
             client,
-            output_device,
-            websocket,
-            buffer: Arc::new(Mutex::new(BatchBuffer::new(BUFFER_BATCH_SIZE))),
-            stream: None,
-            config: config,
+            route,
+            taps: vec![],
+            mixers,
+            broadcast: None,
+            sources: vec![],
+            capture: None,
+            capture_stats: Arc::clone(&stats),
+            transport,
+            config,
             is_alive: Arc::new(Mutex::new(true)),
-        }));
+            concealed_frames: Arc::new(AtomicU64::new(0)),
+            heartbeat: Heartbeat::new(heartbeat),
+            fade_out: Arc::new(AtomicBool::new(false)),
+            stats,
+            device_error_sender,
+            device_errors,
+            span,
+        }
+    }
+
+    // a client sending audio, played on its route and published on `broadcast` for listeners
+    pub async fn new(client: Client, config: StreamConfig, transport: ServerTransport, route: Route, heartbeat: HeartbeatConfig, mixers: Mixers, broadcast: Arc<Broadcast>) -> Arc<Mutex<Connection>> {
+        broadcast.set_config(&config);
+
+        let mut connection = Connection::build(client, config, transport, route, heartbeat, mixers);
+        connection.broadcast = Some(broadcast);
+        let connection = Arc::new(Mutex::new(connection));
+
+        connection.lock().await.open_outputs();
+
+        connection
+    }
+
+    // start sending `stream`, the `BinConfig` to tell the client before any audio
+    fn listen(&mut self, name: &str, stream: &Stream) -> Result<crate::BinMessages, Box<dyn std::error::Error>> {
+        if self.capture.is_none() {
+            if self.client.role == ClientRole::Source {
+                self.client.role = ClientRole::Duplex;
+                self.capture_stats = Arc::new(StreamCounters::default());
+            }
 
-        {
-            let ul_connection = &mut connection.lock().await;
-            ul_connection.stream = None;
+            self.capture = Some(stream.subscribe(Arc::clone(&self.capture_stats), self.device_error_sender.clone())?);
+            self.client.stream = Some(name.to_owned());
+        }
 
-            MetalStream::new(ul_connection).await;
+        let config = &self.capture.as_ref().expect("capture was just set!").config;
+        if self.client.role == ClientRole::Listener {
+            self.config = config.clone();
         }
 
+        Ok(crate::BinMessages::BinConfig(crate::BinStreamConfig::from(config)))
+    }
+
+    // the stream's format changed under a listening connection
+    fn set_capture_config(&mut self, config: &StreamConfig) {
+        let role = self.client.role;
+
+        if let Some(capture) = self.capture.as_mut() {
+            capture.config = config.clone();
+
+            if role == ClientRole::Listener {
+                self.config = capture.config.clone();
+            }
+        }
+    }
+
+    // a client playing a stream, it is told the stream's config before any audio
+    async fn new_listener(client: Client, transport: ServerTransport, heartbeat: HeartbeatConfig, mixers: Mixers, stream: &Stream) -> Result<Arc<Mutex<Connection>>, Box<dyn std::error::Error>> {
+        let no_outputs = Route { outputs: vec![] };
+        // replaced with the capture's config by `listen`
+        let placeholder = StreamConfig { channels: 0, sample_rate: cpal::SampleRate(0), buffer_size: cpal::BufferSize::Default };
+        let mut connection = Connection::build(client, placeholder, transport, no_outputs, heartbeat, mixers);
+
+        let name = connection.client.stream.clone().unwrap_or_else(|| INPUT_STREAM.to_owned());
+        let bin_config = connection.listen(&name, stream)?;
+        connection.span.record("device", name.as_str());
+
+        if let Err(e) = connection.send(&bin_config).await {
+            return Err(e.to_string().into());
+        }
 
-        return Ok(connection);
+        Ok(Arc::new(Mutex::new(connection)))
     }
 }
 
@@ -265,150 +1317,178 @@ impl Connection {
 impl Server for MetalServer {
     // spawn some task that runs the server connection in background
     async fn bind(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let listener = TcpListener::bind(self.address.clone()).await.unwrap();
-        println!("Listening on: {}", self.address);
+        let listener = match self.transport {
+            TransportKind::WebSocket => Listener::WebSocket(TcpListener::bind(self.address.clone()).await.unwrap()),
+            TransportKind::Quic => Listener::Quic(crate::quic::server_endpoint(&self.address)?),
+        };
+        // port 0 leaves the pick to the system, clients need the one it picked
+        self.address = match &listener {
+            Listener::WebSocket(listener) => listener.local_addr()?,
+            Listener::Quic(endpoint) => endpoint.local_addr()?,
+        }.to_string();
+        info!(address = %self.address, transport = ?self.transport, "listening");
         
-        let connections = &mut self.connections;
-        let listener_connections = Arc::clone(&connections);
-        let liveness_connections = Arc::clone(&connections);
-
-        //async tasks
-        tokio::spawn(async move {
-            // remove dead connections
-            tokio::spawn(async move {
-                loop {
-                    
-                    let mut ul_connections = liveness_connections.lock().await;
-                    
-
-                    
-                    let ul_connections_ul = Concurrent::lock_all_ordered(
-                        ul_connections
-                        .iter()
-                        .map(|connection| connection.lock())
-                    )
-                    .await;
-                    
-                    
-
-                    let mut to_keep: Vec<bool> = vec![];
+        if let Listener::Quic(endpoint) = &listener {
+            self.quic_endpoint = Some(endpoint.clone());
+        }
 
-                    for mut connection in ul_connections_ul {
-                        
-                        if *connection.is_alive.lock().await {
-                            to_keep.push(true);
-                        } else {
-                            to_keep.push(false);
-                            connection.stream = None;
-                        }
-                    }
+        let listener_shared = self.shared();
+        let connections = &mut self.connections;
+        let liveness_connections = Arc::clone(connections);
+        let mut listener_shutdown = self.shutdown.subscribe();
+        let liveness_shutdown = self.shutdown.subscribe();
+
+        if let Some(rest_address) = &self.rest_address {
+            let app = Router::new()
+                .route("/metrics", get(metrics_handler))
+                .route("/routes", get(get_routes_handler).put(put_routes_handler))
+                .route("/streams", get(streams_handler))
+                .route("/rooms", get(rooms_handler))
+                .route("/relays", get(relays_handler))
+                .route("/listen", get(listen_handler))
+                .route("/send", get(send_handler))
+                .route("/audio/:stream", get(audio_handler))
+                .with_state(RestState {
+                    connections: Arc::clone(connections),
+                    metrics: Arc::clone(&self.metrics),
+                    routing: Arc::clone(&self.routing),
+                    captures: self.captures.clone(),
+                    broadcasts: self.broadcasts.clone(),
+                    rooms: self.rooms.clone(),
+                    relays: self.relays.clone(),
+                    pipes: self.pipes.clone(),
+                    server_port: self.address.rsplit(':').next().unwrap_or_default().to_owned(),
+                    shutdown: self.shutdown.subscribe(),
+                });
+            let mut rest_shutdown = self.shutdown.subscribe();
 
-                    // actual removal
-                    let mut keeper_iter = to_keep.iter();
+            let rest_server = axum::Server::try_bind(&rest_address.parse()?)?
+                .serve(app.into_make_service())
+                .with_graceful_shutdown(async move {
+                    let _ = rest_shutdown.changed().await;
+                });
+            info!("serving rest endpoint on http://{}", rest_address);
 
-                    ul_connections.retain(|_| *keeper_iter.next().expect("error with iterator this should not happen!"));
+            let rest_task = tokio::spawn(async move {
+                if let Err(e) = rest_server.await {
+                    error!("rest endpoint failed due to {}", e);
                 }
             });
-            // manage connections
-            loop {                
+            keep_tasks(&self.tasks, [rest_task]).await;
+        }
+
+        //async tasks
+        // remove dead connections
+        let liveness_task = tokio::spawn(async move {
+            loop {
                 
+                let mut ul_connections = liveness_connections.lock().await;
 
-                let tcp_stream = match listener.accept().await {
-                    Ok((tcp_stream, _)) => tcp_stream,
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        continue;
-                    }
-                };
+                if *liveness_shutdown.borrow() {
+                    return;
+                }
+                
 
                 
-                let mut ul_connections = listener_connections.lock().await;
+                let ul_connections_ul = Concurrent::lock_all_ordered(
+                    ul_connections
+                    .iter()
+                    .map(|connection| connection.lock())
+                )
+                .await;
                 
-
-                let name = tcp_stream.peer_addr().unwrap().to_string();
                 
-                let connection = Connection::new(&name, tcp_stream).await.expect("could not create connection!");
-                let buffer_connection = Arc::clone(&connection);
-
-                //write data from websocket to buffer
-                tokio::spawn(async move {
-                    loop {
-                        
-                        let mut ul_connection = buffer_connection.lock().await;
-                        
-
-                        match ul_connection.websocket.next().now_or_never() {
-                            Some(Some(Ok(websocket_message))) => {
-
-                                let deserialized = match bincode::deserialize(&websocket_message.into_data()) {
-                                    Ok(deserialized) => deserialized,
-                                    Err(e) => {
-                                        eprintln!("could not deserialize client to server message on server due to {}", e);
-                                        continue;
-                                    }
-                                };
-
 
+                let mut to_keep: Vec<bool> = vec![];
 
-                                match deserialized {
-                                    crate::BinMessages::BinData(data) => {
-                                        ul_connection.buffer.lock().await.extend(&mut data.into_iter());
-                                    },
-                                    crate::BinMessages::BinConfig(config) => {
-                                        println!("received config!");
-                                        let config = cpal::StreamConfig {
-                                            channels: config.channels,
-                                            sample_rate: cpal::SampleRate(config.sample_rate),
-                                            buffer_size: cpal::BufferSize::Default
-                                        };
-
-                                        match &ul_connection.stream {
-                                            Some(stream) => {
-                                                stream.stream.pause().expect("could not pause server stream!");
-                                            }
-                                            None => {}
-                                        }
+                for mut connection in ul_connections_ul {
+                    
+                    if *connection.is_alive.lock().await {
+                        to_keep.push(true);
+                    } else {
+                        info!(parent: &connection.span, "client disconnected");
+                        to_keep.push(false);
+                        connection.close_outputs();
+                        connection.transport.close("connection lost").await;
+                    }
+                }
 
-                                        MetalStream::new(&mut ul_connection).await;
-                                    },
-                                    _ => todo!("have not implemented this part of communication deserialization yet!")
-                                };
+                // actual removal
+                let mut keeper_iter = to_keep.iter();
 
-                                
+                ul_connections.retain(|_| *keeper_iter.next().expect("error with iterator this should not happen!"));
+            }
+        });
+        // manage connections
+        let listener_task = tokio::spawn(async move {
+            loop {                
+                
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    _ = listener_shutdown.changed() => return,
+                };
 
-                            },
-                            Some(Some(Err(e))) => {
-                                
-                                *ul_connection.is_alive.lock().await = false;
-                                
+                let incoming = match accepted {
+                    Some(Ok(incoming)) => incoming,
+                    Some(Err(e)) => {
+                        warn!("could not accept client due to {}", e);
+                        continue;
+                    },
+                    None => {
+                        info!("listener closed, no longer accepting connections");
+                        return;
+                    }
+                };
 
-                                eprintln!("error writing server output due to {}", e);
+                // the handshake waits on the client, so every one gets its own task and a stuck
+                // client holds up neither the next one nor shutdown
+                let shared = Arc::clone(&listener_shared);
+                let mut shutdown = listener_shutdown.clone();
+                let handshake_task = tokio::spawn(async move {
+                    let accept = async {
+                        let (name, transport) = match tokio::time::timeout(HANDSHAKE_TIMEOUT, incoming.transport()).await {
+                            Ok(Ok(accepted)) => accepted,
+                            Ok(Err(e)) => {
+                                warn!("could not accept client due to {}", e);
                                 return;
                             },
-                            Some(None) => {
-                                
-                                
-                                *ul_connection.is_alive.lock().await = false;
-                                
-
-                                println!("Received None, stream is dead");
+                            Err(_) => {
+                                warn!("client did not set up its connection within {:?}", HANDSHAKE_TIMEOUT);
                                 return;
-                            },
-                            None => {
-                                continue;
                             }
-                        }
-                    }
-                });
+                        };
 
+                        MetalServer::accept_connection(&shared, &name, transport, Some(HANDSHAKE_TIMEOUT)).await
+                    };
 
-
-                
-                
-                ul_connections.push(connection);
+                    tokio::select! {
+                        _ = accept => {},
+                        _ = shutdown.changed() => {},
+                    }
+                });
+                keep_tasks(&listener_shared.tasks, [handshake_task]).await;
             }
         });
         
+        // hand relays the streams they should be sending
+        let relay_connections = Arc::clone(&self.connections);
+        let relay_broadcasts = self.broadcasts.clone();
+        let relay_relays = self.relays.clone();
+        let relay_id = self.id.clone();
+        let mut relay_shutdown = self.shutdown.subscribe();
+        let relay_task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(RELAY_SCAN_INTERVAL) => {},
+                    _ = relay_shutdown.changed() => return,
+                }
+
+                let sources = MetalServer::collect_relay_sources(&relay_connections, &relay_broadcasts, &relay_id).await;
+                relay_relays.sync(&relay_id, &sources);
+            }
+        });
+
+        keep_tasks(&self.tasks, [liveness_task, listener_task, relay_task]).await;
 
         Ok(())
     }
@@ -424,15 +1504,19 @@ impl Server for MetalServer {
             )
         ).await
         .iter()
-        .map(
-            |connection| connection.client.clone()
+        .flat_map(
+            |connection| std::iter::once(connection.client.clone()).chain(connection.sources.iter().map(|source| source.client.clone()))
         ).collect::<Vec<Client>>());
         
 
         return ret;
     }
 
-    async fn disconnect_client(&mut self, url: &str) -> Result<Option<Client>, Box<dyn std::error::Error>> {
+    async fn client_stats(&self) -> Result<Vec<(Client, StreamStats)>, Box<dyn std::error::Error>> {
+        Ok(MetalServer::collect_stats(&self.connections).await)
+    }
+
+    async fn disconnect_client(&mut self, id: &str) -> Result<Option<Client>, Box<dyn std::error::Error>> {
         
         let mut ul_connections = self.connections.lock().await;
         
@@ -450,20 +1534,19 @@ impl Server for MetalServer {
             .collect::<Vec<(usize, Client)>>();
         
 
-        match clients.iter().find(|(_, client)| client.url == url) {
-            Some((i, client)) => {
-        
-                let removed = ul_connections.remove(i.to_owned());
-                
-                match &removed.lock().await.stream {
-                    Some(stream) => {let _ = stream.stream.pause();},
-                    None => {}
-                }
-                
+        if let Some((i, client)) = clients.iter().find(|(_, client)| client.id == id) {
+            let removed = ul_connections.remove(i.to_owned());
+            let mut removed = removed.lock().await;
 
-                return Ok(Some(client.clone()));
-            },
-            None => {}
+            removed.close_outputs();
+
+            removed.transport.close("disconnected by server").await;
+            info!(parent: &removed.span, "disconnected by server");
+
+            // stops its heartbeat too
+            *removed.is_alive.lock().await = false;
+
+            return Ok(Some(client.clone()));
         }
 
         return Ok(None);
@@ -473,29 +1556,167 @@ impl Server for MetalServer {
         
     }
 
-    async fn change_output_device(&mut self, cpos: usize,  new_output: cpal::Device) -> Result<(), Box<dyn std::error::Error>> {        
+    async fn change_output_device(&mut self, id: &str,  new_output: &DeviceId) -> Result<(), Box<dyn std::error::Error>> {        
+        // fail before saving a rule for a device that isn't there
+        crate::devices::find_output_device(new_output)?;
+
+        self.routing.lock().unwrap().set_device(id, new_output.clone())?;
+        MetalServer::apply_routes(&self.connections, &self.routing).await;
+
+        Ok(())
+    }
+
+    async fn routing_rules(&self) -> Result<Vec<RoutingRule>, Box<dyn std::error::Error>> {
+        Ok(self.routing.lock().unwrap().rules().to_vec())
+    }
+
+    async fn set_routing_rules(&mut self, rules: Vec<RoutingRule>) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(MetalServer::replace_routes(&self.connections, &self.routing, &self.pipes, rules).await?)
+    }
+
+    async fn change_input_device(&mut self, new_input: &DeviceId) -> Result<(), Box<dyn std::error::Error>> {
+        // fail here rather than on the next listener
+        crate::devices::find_input_device(new_input)?;
+
+        self.captures.set_device(Some(new_input.clone()))
+    }
+
+    async fn list_streams(&self) -> Result<Vec<StreamInfo>, Box<dyn std::error::Error>> {
+        Ok(MetalServer::collect_streams(&self.connections, &self.captures, &self.broadcasts).await)
+    }
+
+    async fn create_room(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.rooms.create(name)?;
+        info!(room = %name, "room created");
+
+        Ok(())
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<RoomInfo>, Box<dyn std::error::Error>> {
+        let clients = self.list_clients().await?;
+
+        Ok(MetalServer::room_infos(&self.rooms, clients.iter()))
+    }
+
+    async fn close_room(&mut self, name: &str) -> Result<Vec<Client>, Box<dyn std::error::Error>> {
+        // fail before letting anyone go
+        if name == DEFAULT_ROOM {
+            return Err("the default room can't be closed".into());
+        }
+        if self.rooms.mixers(name).is_none() {
+            return Err(format!("no room named {}", name).into());
+        }
+
         let mut ul_connections = self.connections.lock().await;
-        let rx_connection = ul_connections.get_mut(cpos).expect("could not get connection in change_output_device!");
-
-        {
-            let mut connection = rx_connection.lock().await;
-            
-            
-            match &connection.stream {
-                Some(stream) => {let _ = stream.stream.pause();}
-                None => {}
-            };
+        let mut closed = vec![];
+        let mut kept = vec![];
+
+        for connection in ul_connections.drain(..) {
+            let mut ul_connection = connection.lock().await;
+
+            if ul_connection.client.room != name {
+                drop(ul_connection);
+                kept.push(connection);
+                continue;
+            }
 
-            connection.stream = None;
-            
-            connection.output_device = new_output;
+            ul_connection.close_outputs();
+            ul_connection.transport.close("room closed").await;
+            *ul_connection.is_alive.lock().await = false;
+            info!(parent: &ul_connection.span, "room closed");
 
-            connection.stream = None;
-            MetalStream::new(&mut connection).await;
+            closed.push(ul_connection.client.clone());
         }
-        
+        *ul_connections = kept;
+
+        self.rooms.close(name)?;
+        info!(room = %name, clients = closed.len(), "room closed");
+
+        Ok(closed)
+    }
+
+    async fn add_relay(&mut self, config: RelayConfig) -> Result<(), Box<dyn std::error::Error>> {
+        self.relays.add(config)
+    }
+
+    async fn remove_relay(&mut self, url: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.relays.remove(url)
+    }
+
+    async fn list_relays(&self) -> Result<Vec<RelayInfo>, Box<dyn std::error::Error>> {
+        Ok(self.relays.infos())
+    }
+
+    async fn add_pipe_sink(&mut self, name: &str, sink: PipeSink) -> Result<(), Box<dyn std::error::Error>> {
+        self.pipes.add(name, sink)
+    }
+
+    async fn remove_pipe_sink(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.pipes.remove(name)?;
+        MetalServer::apply_routes(&self.connections, &self.routing).await;
 
         Ok(())
     }
-}
 
+    async fn add_ingest(&mut self, config: IngestConfig) -> Result<(), Box<dyn std::error::Error>> {
+        let url = config.url.clone();
+        let transport = ServerTransport::Ingest(IngestTransport::open(config).map_err(|e| e.to_string())?);
+        info!(url = %url, "ingesting stream");
+
+        let shared = self.shared();
+        let mut shutdown = self.shutdown.subscribe();
+
+        // the handshake finishes once the stream first answers, which may take a few reconnects
+        let ingest_task = tokio::spawn(async move {
+            tokio::select! {
+                _ = MetalServer::accept_connection(&shared, &url, transport, None) => {},
+                _ = shutdown.changed() => {},
+            }
+        });
+        keep_tasks(&self.tasks, [ingest_task]).await;
+
+        Ok(())
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        info!(address = %self.address, "shutting down server");
+
+        // upstream servers are told right away, not after the fade
+        let relay_tasks = self.relays.clear();
+        keep_tasks(&self.tasks, relay_tasks).await;
+
+        // stops the listener, the reaper and every connection task
+        self.shutdown.send_replace(true);
+
+        let connections = std::mem::take(&mut *self.connections.lock().await);
+
+        // fade everyone out together, then give the fade time to reach the speakers
+        for connection in connections.iter() {
+            connection.lock().await.fade_out.store(true, Ordering::Relaxed);
+        }
+        tokio::time::sleep(FADE_OUT * 2).await;
+
+        for connection in connections.iter() {
+            let mut connection = connection.lock().await;
+
+            connection.close_outputs();
+
+            connection.transport.close("server shutting down").await;
+            *connection.is_alive.lock().await = false;
+        }
+
+        let tasks = std::mem::take(&mut *self.tasks.lock().await);
+        for task in tasks {
+            let _ = task.await;
+        }
+
+        if let Some(endpoint) = self.quic_endpoint.take() {
+            endpoint.close(0u32.into(), b"server shutting down");
+            endpoint.wait_idle().await;
+        }
+
+        info!(address = %self.address, "server shut down");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::time::Duration;
+
+use cpal::StreamConfig;
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn};
+
+use crate::broadcast::Broadcast;
+use crate::mixer::{Mixers, Tap, TapSource};
+use crate::routing::Route;
+use crate::server::Client;
+use crate::stats::{StreamCounters, StreamStats};
+
+// more than one capture device over one client connection
+//
+// a client's first device is its main stream, sent with `BinConfig` and `BinData` as always. every
+// other device is a numbered source, announced with `BinSourceConfig` and sent as `BinSourceData`
+// tagged with its id. on the server each source is a client of its own, `<client id>/<source id>`,
+// with its own config, route, outputs and broadcast, so routing rules, listeners and stats treat it
+// like any other client. it lives and dies with the connection carrying it
+
+// what a source sends, before its first block and whenever its device changes
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BinSource {
+    // picked by the client, unique on its connection. `0` is the main stream and never used here
+    pub id: u16,
+    // shown after the client's name, usually the device
+    pub label: String,
+    pub config: crate::BinStreamConfig,
+}
+
+pub fn stream_config(config: &crate::BinStreamConfig) -> StreamConfig {
+    StreamConfig {
+        channels: config.channels,
+        sample_rate: cpal::SampleRate(config.sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    }
+}
+
+// the client a source shows up as, next to the one carrying it
+pub fn source_client(parent: &Client, source: &BinSource) -> Client {
+    Client {
+        url: parent.url.clone(),
+        id: format!("{}/{}", parent.id, source.id),
+        name: format!("{}/{}", parent.name, source.label),
+        role: parent.role,
+        stream: None,
+        room: parent.room.clone(),
+        parent: Some(parent.id.clone()),
+        hops: parent.hops.clone(),
+    }
+}
+
+// what a source shares with the connection carrying it, it goes quiet and fails with it
+pub struct Carrier {
+    pub mixers: Mixers,
+    pub fade_out: Arc<AtomicBool>,
+    pub device_errors: SyncSender<cpal::StreamError>,
+    pub span: tracing::Span,
+}
+
+// one extra source on the server, played on its route and published for listeners
+pub struct Source {
+    pub id: u16,
+    pub client: Client,
+    pub config: StreamConfig,
+    pub route: Route,
+    taps: Vec<Arc<Tap>>,
+    mixers: Mixers,
+    broadcast: Arc<Broadcast>,
+    stats: Arc<StreamCounters>,
+    concealed_frames: Arc<AtomicU64>,
+    fade_out: Arc<AtomicBool>,
+    device_errors: SyncSender<cpal::StreamError>,
+    span: tracing::Span,
+}
+
+impl Source {
+    pub fn new(source: &BinSource, client: Client, route: Route, broadcast: Arc<Broadcast>, carrier: Carrier) -> Self {
+        let Carrier { mixers, fade_out, device_errors, span } = carrier;
+        let config = stream_config(&source.config);
+        broadcast.set_config(&config);
+
+        let mut source = Source {
+            id: source.id,
+            span: tracing::info_span!(parent: &span, "source", id = %client.id, device = tracing::field::Empty),
+            client,
+            config,
+            route,
+            taps: vec![],
+            mixers,
+            broadcast,
+            stats: Arc::new(StreamCounters::default()),
+            concealed_frames: Arc::new(AtomicU64::new(0)),
+            fade_out,
+            device_errors,
+        };
+        source.open_outputs();
+        source.span.in_scope(|| info!(channels = source.config.channels, sample_rate = source.config.sample_rate.0, "source added"));
+
+        source
+    }
+
+    // the client switched this source to another device
+    pub fn set_config(&mut self, source: &BinSource) {
+        self.config = stream_config(&source.config);
+        self.broadcast.set_config(&self.config);
+        self.open_outputs();
+
+        self.span.in_scope(|| info!(channels = self.config.channels, sample_rate = self.config.sample_rate.0, "source config changed"));
+    }
+
+    pub fn set_route(&mut self, route: Route) {
+        self.route = route;
+        self.open_outputs();
+    }
+
+    // a block as it came off the wire, `size` bytes of it
+    pub fn push(&self, data: &[f32], size: u64) {
+        let frames = data.len() / self.config.channels.max(1) as usize;
+        self.stats.received(size, frames as u64);
+        self.stats.arrival(Duration::from_secs_f32(frames as f32 / self.config.sample_rate.0.max(1) as f32));
+
+        for tap in self.taps.iter() {
+            tap.push(data);
+        }
+        self.broadcast.publish(data);
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        let mut stats = self.stats.snapshot();
+        stats.concealed_frames = self.concealed_frames.load(Ordering::Relaxed);
+        stats.buffer_ms = stats.buffer_frames as f32 * 1_000.0 / self.config.sample_rate.0.max(1) as f32;
+
+        stats
+    }
+
+    fn close_outputs(&mut self) {
+        for tap in self.taps.drain(..) {
+            self.mixers.detach(&tap);
+        }
+    }
+
+    fn open_outputs(&mut self) {
+        self.close_outputs();
+
+        for (i, output) in self.route.outputs.iter().enumerate() {
+            let source = TapSource {
+                config: self.config.clone(),
+                primary: i == 0,
+                stats: Arc::clone(&self.stats),
+                concealed_frames: Arc::clone(&self.concealed_frames),
+                fade_out: Arc::clone(&self.fade_out),
+                device_errors: self.device_errors.clone(),
+                echo_reference: None,
+            };
+
+            match self.mixers.attach(output, &source) {
+                Ok(tap) => self.taps.push(tap),
+                Err(e) => warn!(parent: &self.span, "could not play on output {} due to {}", i, e)
+            }
+        }
+
+        let devices = self.taps.iter().map(|tap| tap.device()).collect::<Vec<&str>>().join(", ");
+        self.span.record("device", devices.as_str());
+    }
+}
+
+impl Drop for Source {
+    fn drop(&mut self) {
+        self.close_outputs();
+    }
+}
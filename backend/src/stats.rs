@@ -0,0 +1,281 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::fec::FecStats;
+use crate::relay::BinHop;
+
+// live counters for one end of a connection
+//
+// everything that is touched from an audio callback is a relaxed atomic, so metering never takes
+// a lock on the realtime thread. `snapshot` turns the counters into a plain `StreamStats`
+
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+pub struct ChannelLevel {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct StreamStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    // over the last second or so, zero once nothing has arrived for a while
+    pub receive_bitrate_bps: f32,
+    // smoothed variation in packet spacing, rfc 3550 style
+    pub jitter_ms: f32,
+    // frames waiting to be played (server) or sent (client)
+    pub buffer_frames: u64,
+    // the same, as playback time
+    pub buffer_ms: f32,
+    pub underruns: u64,
+    pub overruns: u64,
+    pub concealed_frames: u64,
+    pub device_errors: u64,
+    pub rtt_ms: Option<f32>,
+    // levels of the last block played (server) or captured (client), per channel
+    pub levels: Vec<ChannelLevel>,
+    pub fec: Option<FecStats>,
+    // the other direction of a duplex connection, counted on its own
+    pub reverse: Option<Box<StreamStats>>,
+    // the servers a relayed stream went through before this one, empty for direct connections
+    pub hops: Vec<BinHop>,
+}
+
+// f32 stored as its bit pattern so it can live in an atomic
+#[derive(Default)]
+struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+pub struct StreamCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    frames_sent: AtomicU64,
+    frames_received: AtomicU64,
+    jitter_ms: AtomicF32,
+    buffer_frames: AtomicU64,
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+    device_errors: AtomicU64,
+    peaks: [AtomicF32; MAX_METERED_CHANNELS],
+    rms: [AtomicF32; MAX_METERED_CHANNELS],
+    metered_channels: AtomicU64,
+    // nanoseconds since `EPOCH` plus one, zero before the first packet. the client timestamps on
+    // its capture callback, so these are atomics too
+    last_arrival: AtomicU64,
+    last_duration: AtomicU64,
+    // taken by the network task counting bytes, never by an audio callback
+    rate: Mutex<Option<RateWindow>>,
+    receive_bitrate_bps: AtomicF32,
+}
+
+// levels past this many channels are not metered
+pub static MAX_METERED_CHANNELS: usize = 8;
+
+// bitrate is recomputed once per window
+static RATE_WINDOW: Duration = Duration::from_secs(1);
+
+struct RateWindow {
+    started: Instant,
+    bytes: u64,
+}
+
+// what arrival times are measured from
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+impl StreamCounters {
+    pub fn sent(&self, bytes: u64, frames: u64) {
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        self.frames_sent.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, bytes: u64, frames: u64) {
+        self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+        self.frames_received.fetch_add(frames, Ordering::Relaxed);
+
+        let mut rate = self.rate.lock().unwrap();
+        let window = rate.get_or_insert_with(|| RateWindow { started: Instant::now(), bytes: 0 });
+        window.bytes += bytes;
+
+        let elapsed = window.started.elapsed();
+        if elapsed >= RATE_WINDOW {
+            self.receive_bitrate_bps.store(window.bytes as f32 * 8.0 / elapsed.as_secs_f32());
+            *window = RateWindow { started: Instant::now(), bytes: 0 };
+        }
+    }
+
+    pub fn underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn overrun(&self) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn device_error(&self) {
+        self.device_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_buffer_frames(&self, frames: u64) {
+        self.buffer_frames.store(frames, Ordering::Relaxed);
+    }
+
+    // a packet holding `duration` worth of audio arrived now, compare its spacing with the
+    // previous packet's length
+    pub fn arrival(&self, duration: Duration) {
+        let now = EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64 + 1;
+
+        let last_arrival = self.last_arrival.swap(now, Ordering::Relaxed);
+        let last_duration = self.last_duration.swap(duration.as_nanos() as u64, Ordering::Relaxed);
+        if last_arrival == 0 {
+            return;
+        }
+
+        let spacing = now.saturating_sub(last_arrival) as f32 / 1_000_000.0;
+        let deviation = (spacing - last_duration as f32 / 1_000_000.0).abs();
+
+        let jitter = self.jitter_ms.load();
+        self.jitter_ms.store(jitter + (deviation - jitter) / 16.0);
+    }
+
+    // peak and rms of an interleaved block, safe to call from the audio callback
+    pub fn meter(&self, data: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        let metered = channels.min(MAX_METERED_CHANNELS);
+        let frames = data.len() / channels;
+
+        if frames == 0 {
+            return;
+        }
+
+        for channel in 0..metered {
+            let mut peak = 0.0f32;
+            let mut sum = 0.0f32;
+
+            for sample in data.iter().skip(channel).step_by(channels) {
+                peak = peak.max(sample.abs());
+                sum += sample * sample;
+            }
+
+            self.peaks[channel].store(peak);
+            self.rms[channel].store((sum / frames as f32).sqrt());
+        }
+
+        self.metered_channels.store(metered as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StreamStats {
+        let metered = self.metered_channels.load(Ordering::Relaxed) as usize;
+
+        // a window that never closed means the stream went quiet
+        let stalled = self.rate.lock().unwrap().as_ref().is_none_or(|window| window.started.elapsed() >= 2 * RATE_WINDOW);
+        let receive_bitrate_bps = if stalled { 0.0 } else { self.receive_bitrate_bps.load() };
+
+        StreamStats {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            frames_sent: self.frames_sent.load(Ordering::Relaxed),
+            frames_received: self.frames_received.load(Ordering::Relaxed),
+            receive_bitrate_bps,
+            jitter_ms: self.jitter_ms.load(),
+            buffer_frames: self.buffer_frames.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+            device_errors: self.device_errors.load(Ordering::Relaxed),
+            levels: (0..metered)
+                .map(|channel| ChannelLevel { peak: self.peaks[channel].load(), rms: self.rms[channel].load() })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_add_up() {
+        let counters = StreamCounters::default();
+        counters.sent(100, 10);
+        counters.sent(50, 5);
+        counters.received(20, 2);
+        counters.underrun();
+        counters.overrun();
+        counters.overrun();
+        counters.device_error();
+        counters.set_buffer_frames(480);
+
+        let stats = counters.snapshot();
+        assert_eq!((stats.bytes_sent, stats.frames_sent), (150, 15));
+        assert_eq!((stats.bytes_received, stats.frames_received), (20, 2));
+        assert_eq!((stats.underruns, stats.overruns, stats.device_errors), (1, 2, 1));
+        assert_eq!(stats.buffer_frames, 480);
+    }
+
+    #[test]
+    fn meter_measures_each_channel() {
+        let counters = StreamCounters::default();
+        // left alternates between 0.5 and -0.5, right is silent
+        counters.meter(&[0.5, 0.0, -0.5, 0.0, 0.5, 0.0, -0.5, 0.0], 2);
+
+        let levels = counters.snapshot().levels;
+        assert_eq!(levels.len(), 2);
+        assert_eq!((levels[0].peak, levels[0].rms), (0.5, 0.5));
+        assert_eq!((levels[1].peak, levels[1].rms), (0.0, 0.0));
+    }
+
+    #[test]
+    fn meter_stops_at_the_metered_channels() {
+        let counters = StreamCounters::default();
+        let channels = MAX_METERED_CHANNELS + 2;
+        counters.meter(&vec![0.25; channels * 4], channels);
+
+        assert_eq!(counters.snapshot().levels.len(), MAX_METERED_CHANNELS);
+    }
+
+    #[test]
+    fn a_partial_block_is_not_metered() {
+        let counters = StreamCounters::default();
+        counters.meter(&[1.0], 2);
+
+        assert!(counters.snapshot().levels.is_empty());
+    }
+
+    #[test]
+    fn bitrate_is_zero_until_a_window_closes() {
+        let counters = StreamCounters::default();
+        assert_eq!(counters.snapshot().receive_bitrate_bps, 0.0);
+
+        counters.received(1000, 1);
+        assert_eq!(counters.snapshot().receive_bitrate_bps, 0.0);
+    }
+
+    #[test]
+    fn jitter_follows_late_packets() {
+        let counters = StreamCounters::default();
+        // packets claiming no audio at all, any spacing between them is deviation
+        counters.arrival(Duration::ZERO);
+        assert_eq!(counters.snapshot().jitter_ms, 0.0);
+
+        std::thread::sleep(Duration::from_millis(20));
+        counters.arrival(Duration::ZERO);
+
+        // one sixteenth of the 20ms or more the second packet came after the first
+        assert!(counters.snapshot().jitter_ms >= 20.0 / 16.0, "jitter did not pick up the late packet!");
+    }
+}
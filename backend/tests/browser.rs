@@ -0,0 +1,71 @@
+use futures::{SinkExt, StreamExt};
+use remoteio_backend::browser::{decode_samples, encode_samples, BROWSER_PATH};
+use remoteio_backend::server::{ClientRole, MetalServer, Server};
+use tokio::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+// stands in for the browser pages: one sends a microphone, one listens to it, both speaking the
+// browser framing to a real server on loopback. no audio devices needed
+#[tokio::test]
+async fn microphone_reaches_speaker() {
+    let mut server = MetalServer::new("127.0.0.1:0");
+    server.bind().await.expect("could not bind server!");
+
+    let url = format!("ws://{}{}", server.address(), BROWSER_PATH);
+
+    let (mut microphone, _) = tokio_tungstenite::connect_async(&url).await.expect("could not connect microphone!");
+    microphone.send(Message::text(r#"{"type":"identify","id":"browser-mic","name":"browser mic"}"#)).await.expect("could not identify!");
+    microphone.send(Message::text(r#"{"type":"config","channels":1,"sample_rate":48000}"#)).await.expect("could not send config!");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (mut speaker, _) = tokio_tungstenite::connect_async(&url).await.expect("could not connect speaker!");
+    speaker.send(Message::text(r#"{"type":"identify","id":"browser-speaker","name":"browser speaker"}"#)).await.expect("could not identify!");
+    speaker.send(Message::text(r#"{"type":"subscribe","stream":"browser-mic"}"#)).await.expect("could not subscribe!");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let clients = server.list_clients().await.expect("could not list clients!");
+    assert!(clients.iter().any(|client| client.id == "browser-mic" && client.role == ClientRole::Source), "microphone is not a source!");
+    assert!(clients.iter().any(|client| client.id == "browser-speaker" && client.role == ClientRole::Listener), "speaker is not a listener!");
+
+    for _ in 0..10 {
+        microphone.send(Message::binary(encode_samples(&[0.25; 1024]))).await.expect("could not send samples!");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let mut received_samples = 0;
+    while let Ok(Some(message)) = tokio::time::timeout(Duration::from_millis(500), speaker.next()).await {
+        if let Message::Binary(bytes) = message.expect("error receiving message!") {
+            let samples = decode_samples(&bytes);
+            assert!(samples.iter().all(|sample| *sample == 0.25), "samples changed on the way!");
+
+            received_samples += samples.len();
+        }
+    }
+
+    assert_eq!(received_samples, 10 * 1024, "samples went missing!");
+    server.shutdown().await.expect("could not shut down server!");
+}
+
+// a connection that never finishes its websocket handshake must not keep the listener from
+// accepting anyone else
+#[tokio::test]
+async fn stalled_connection_does_not_block_others() {
+    let mut server = MetalServer::new("127.0.0.1:0");
+    server.bind().await.expect("could not bind server!");
+
+    let _stalled = tokio::net::TcpStream::connect(server.address()).await.expect("could not open stalled connection!");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let url = format!("ws://{}{}", server.address(), BROWSER_PATH);
+    let (mut microphone, _) = tokio::time::timeout(Duration::from_secs(2), tokio_tungstenite::connect_async(&url)).await
+        .expect("listener is stuck on the stalled connection!")
+        .expect("could not connect microphone!");
+    microphone.send(Message::text(r#"{"type":"identify","id":"browser-mic","name":"browser mic"}"#)).await.expect("could not identify!");
+    microphone.send(Message::text(r#"{"type":"config","channels":1,"sample_rate":48000}"#)).await.expect("could not send config!");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let clients = server.list_clients().await.expect("could not list clients!");
+    assert!(clients.iter().any(|client| client.id == "browser-mic"), "microphone was not accepted!");
+
+    server.shutdown().await.expect("could not shut down server!");
+}
@@ -0,0 +1,88 @@
+use remoteio_backend::fec::Received;
+use remoteio_backend::quic::{self, QuicTransport, MAX_CONTROL_FRAME, QUIC_SERVER_NAME};
+use remoteio_backend::{BinMessages, BinStreamConfig};
+use tokio::time::Duration;
+
+// a client and a server talking over the QUIC transport on loopback: the handshake, a config each
+// way on the control stream and audio as datagrams. no audio devices needed
+#[tokio::test]
+async fn config_and_data_round_trip() {
+    let endpoint = quic::server_endpoint("127.0.0.1:0").expect("could not bind quic endpoint!");
+    let address = endpoint.local_addr().expect("endpoint has no address!");
+
+    let server = tokio::spawn(async move {
+        let mut transport = QuicTransport::accept(&endpoint).await.expect("endpoint closed!").expect("could not accept quic client!");
+
+        let mut config = None;
+        let mut received = vec![];
+        while let Some(message) = transport.recv().await {
+            let message = match message.expect("error receiving message!") {
+                Received::Message(message) => message,
+                Received::Missing(samples) => panic!("{} samples went missing on loopback!", samples)
+            };
+            match bincode::deserialize(&message).expect("could not deserialize message!") {
+                BinMessages::BinConfig(client_config) => {
+                    config = Some((client_config.channels, client_config.sample_rate));
+                    // answered on the same control stream
+                    transport.send(&BinMessages::BinConfig(client_config)).await.expect("could not answer config!");
+                },
+                BinMessages::BinData(data) => received.extend(data),
+                _ => {}
+            }
+        }
+
+        (config, received)
+    });
+
+    let mut client = QuicTransport::connect(&format!("quic://{}", address)).await.expect("could not connect quic client!");
+
+    client.send(&BinMessages::BinConfig(BinStreamConfig { channels: 2, sample_rate: 48000, buffer_size: 4096 })).await.expect("could not send config!");
+
+    let answer = tokio::time::timeout(Duration::from_secs(5), client.recv()).await
+        .expect("server did not answer the config!")
+        .expect("connection closed!")
+        .expect("error receiving answer!");
+    let Received::Message(answer) = answer else { panic!("expected a message back, got {:?}", answer) };
+    match bincode::deserialize(&answer).expect("could not deserialize answer!") {
+        BinMessages::BinConfig(answer) => assert_eq!((answer.channels, answer.sample_rate), (2, 48000), "config changed on the way!"),
+        other => panic!("expected a config back, got {:?}", other)
+    }
+
+    // bigger than a datagram, so it gets split on frame boundaries
+    for _ in 0..10 {
+        client.send(&BinMessages::BinData(vec![0.25; 2048])).await.expect("could not send data!");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    client.close_gracefully("done").await;
+
+    let (received_config, received) = tokio::time::timeout(Duration::from_secs(5), server).await
+        .expect("server did not see the client close!")
+        .expect("server task panicked!");
+    assert_eq!(received_config, Some((2, 48000)), "server did not get the config!");
+    assert!(received.iter().all(|sample| *sample == 0.25), "samples changed on the way!");
+    assert_eq!(received.len(), 10 * 2048, "samples went missing!");
+}
+
+// a control frame announcing more than `MAX_CONTROL_FRAME` ends the connection's control stream
+// with an error instead of being allocated
+#[tokio::test]
+async fn oversized_control_frame_is_refused() {
+    let endpoint = quic::server_endpoint("127.0.0.1:0").expect("could not bind quic endpoint!");
+    let address = endpoint.local_addr().expect("endpoint has no address!");
+
+    let server = tokio::spawn(async move {
+        let mut transport = QuicTransport::accept(&endpoint).await.expect("endpoint closed!").expect("could not accept quic client!");
+        transport.recv().await
+    });
+
+    let client = quic::client_endpoint(&address).expect("could not create client endpoint!");
+    let connection = client.connect(address, QUIC_SERVER_NAME).expect("could not start connecting!").await.expect("could not connect!");
+    let (mut control, _control_reader) = connection.open_bi().await.expect("could not open control stream!");
+    control.write_all(&(MAX_CONTROL_FRAME as u32 + 1).to_be_bytes()).await.expect("could not send frame length!");
+
+    let received = tokio::time::timeout(Duration::from_secs(5), server).await
+        .expect("server kept waiting for the frame!")
+        .expect("server task panicked!");
+    assert!(matches!(received, Some(Err(_))), "oversized frame was not refused!");
+}
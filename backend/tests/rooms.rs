@@ -0,0 +1,90 @@
+use futures::{SinkExt, StreamExt};
+use remoteio_backend::broadcast::INPUT_STREAM;
+use remoteio_backend::identity::BinClientIdentity;
+use remoteio_backend::rooms::DEFAULT_ROOM;
+use remoteio_backend::server::{MetalServer, Server};
+use remoteio_backend::{BinMessages, BinStreamConfig};
+use tokio::net::TcpStream;
+use tokio::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// opens a client on the server and sends it `messages`, the handshake being the first of them
+async fn client(server: &MetalServer, messages: &[BinMessages]) -> Socket {
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}", server.address())).await.expect("could not connect!");
+    for message in messages {
+        socket.send(Message::binary(bincode::serialize(message).unwrap())).await.expect("could not send handshake!");
+    }
+
+    socket
+}
+
+fn identify(id: &str) -> BinMessages {
+    BinMessages::BinIdentify(BinClientIdentity { id: id.to_owned(), name: id.to_owned() })
+}
+
+// the next message from the server, `None` once it closed the connection
+async fn next(socket: &mut Socket) -> Option<BinMessages> {
+    loop {
+        match tokio::time::timeout(Duration::from_millis(500), socket.next()).await.expect("server went quiet!")? {
+            Ok(Message::Binary(bytes)) => return Some(bincode::deserialize(&bytes).expect("could not deserialize message!")),
+            Ok(Message::Close(_)) | Err(_) => return None,
+            Ok(_) => continue,
+        }
+    }
+}
+
+// why the server closed the connection, once it did
+async fn close_reason(socket: &mut Socket) -> String {
+    loop {
+        match tokio::time::timeout(Duration::from_millis(500), socket.next()).await.expect("server went quiet!") {
+            Some(Ok(Message::Close(frame))) => return frame.map(|frame| frame.reason.into_owned()).unwrap_or_default(),
+            Some(Ok(_)) => continue,
+            _ => return String::new(),
+        }
+    }
+}
+
+// a guide speaking in room `a` is heard by a listener in `a` and by no one in the default room, and
+// the server's own input stays in the default room
+#[tokio::test]
+async fn clients_only_hear_their_own_room() {
+    let mut server = MetalServer::new("127.0.0.1:0");
+    server.bind().await.expect("could not bind server!");
+    server.create_room("a").await.expect("could not create room!");
+
+    let mut guide = client(&server, &[
+        identify("guide"),
+        BinMessages::BinJoin("a".to_owned()),
+        BinMessages::BinConfig(BinStreamConfig { channels: 1, sample_rate: 48000, buffer_size: 4096 }),
+    ]).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut outsider = client(&server, &[identify("outsider"), BinMessages::BinSubscribe("guide".to_owned())]).await;
+    assert_eq!(close_reason(&mut outsider).await, "no stream named guide in room default");
+
+    let mut input_listener = client(&server, &[identify("input"), BinMessages::BinJoin("a".to_owned()), BinMessages::BinListen]).await;
+    assert_eq!(close_reason(&mut input_listener).await, "the server's input is only heard in the default room, not in a");
+
+    let mut insider = client(&server, &[identify("insider"), BinMessages::BinJoin("a".to_owned()), BinMessages::BinSubscribe("guide".to_owned())]).await;
+    assert!(matches!(next(&mut insider).await, Some(BinMessages::BinConfig(_))), "listener in the room was not told the config!");
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    guide.send(Message::binary(bincode::serialize(&BinMessages::BinData(vec![0.25; 480])).unwrap())).await.expect("could not send samples!");
+    match next(&mut insider).await {
+        Some(BinMessages::BinData(samples)) => assert!(samples.iter().all(|sample| *sample == 0.25), "samples changed on the way!"),
+        other => panic!("expected samples, got {:?}", other)
+    }
+
+    let streams = server.list_streams().await.expect("could not list streams!");
+    let input = streams.iter().find(|stream| stream.name == INPUT_STREAM).expect("the server's input is not listed!");
+    assert_eq!(input.room.as_deref(), Some(DEFAULT_ROOM));
+
+    let closed = server.close_room("a").await.expect("could not close room!");
+    assert_eq!(closed.len(), 2, "room had the guide and the insider!");
+    assert!(next(&mut insider).await.is_none(), "closing the room left its listener connected!");
+
+    server.shutdown().await.expect("could not shut down server!");
+}
@@ -0,0 +1,19 @@
+use remoteio_backend::routing::{ClientMatch, Route, RouteOutput, RoutingRule};
+use remoteio_backend::server::{MetalServer, Server};
+
+// pipes only exist on the server, a rule naming one that was never added is refused and not saved
+#[tokio::test]
+async fn rules_naming_an_unknown_pipe_are_refused() {
+    let mut server = MetalServer::new("127.0.0.1:0");
+
+    let rule = RoutingRule {
+        matcher: ClientMatch::Id("someone".to_owned()),
+        route: Route { outputs: vec![RouteOutput { pipe: Some("nowhere".to_owned()), ..Default::default() }] },
+    };
+
+    let refused = server.set_routing_rules(vec![rule.clone()]).await.expect_err("unknown pipe was accepted!");
+    assert_eq!(refused.to_string(), "no pipe sink named nowhere");
+
+    let rules = server.routing_rules().await.expect("could not read routing rules!");
+    assert!(!rules.contains(&rule), "refused rule was saved!");
+}
@@ -3,9 +3,17 @@
 
 use std::{sync::Arc, time::Duration};
 
-use remoteio_backend::client::Client;
+use remoteio_backend::client::{Client, LinkConfig};
 use remoteio_backend::server::Server;
+use remoteio_backend::stats::StreamStats;
+use remoteio_backend::broadcast::StreamInfo;
+use remoteio_backend::rooms::RoomInfo;
+use remoteio_backend::relay::{RelayConfig, RelayInfo};
+use remoteio_backend::ingest::IngestConfig;
+use remoteio_backend::devices::{DeviceId, DeviceInfo};
+use remoteio_backend::routing::{RoutingRule, RoutingTable};
 use tokio::sync::{Mutex, MutexGuard};
+use tauri::Manager;
 
 use cpal::{Device, traits::{DeviceTrait, HostTrait}};
 
@@ -19,29 +27,107 @@ fn greet(name: &str) -> String {
 
 
 #[tauri::command]
-async fn get_server_connections(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<String>, String> {
+async fn get_server_connections(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<remoteio_backend::server::Client>, String> {
 
     let ul_state = state.lock().await;
 
-    let clients = match ul_state.server_state.list_clients().await {
-        Ok(clients) => clients,
-        Err(e) => panic!("panicking when getting server connections due to {}", e),
-    };
+    ul_state.server_state.list_clients().await.map_err(|e| e.to_string())
+}
+
+
+#[tauri::command]
+async fn get_server_stats(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<(String, StreamStats)>, String> {
 
-    return Ok(clients.iter().map(|client| client.url.clone()).collect::<Vec<String>>());
+    let ul_state = state.lock().await;
+
+    let stats = ul_state.server_state.client_stats().await.map_err(|e| e.to_string())?;
+
+    return Ok(stats.into_iter().map(|(client, stats)| (client.id, stats)).collect::<Vec<(String, StreamStats)>>());
 }
 
 
 #[tauri::command]
-async fn get_server_devices(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<String>, String> {
-    return Ok(
-        cpal::default_host()
-        .output_devices()
-        .expect("could not get output devices!")
-        .map(|device|device.name().expect("could not get name!"))
-        .collect::<Vec<String>>()
-    );
-    
+async fn get_server_devices(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<DeviceInfo>, String> {
+    return remoteio_backend::devices::output_devices().map_err(|e| e.to_string());
+}
+
+#[tauri::command]
+async fn get_server_input_devices(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<DeviceInfo>, String> {
+    return remoteio_backend::devices::input_devices().map_err(|e| e.to_string());
+}
+
+#[tauri::command]
+async fn get_server_streams(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<StreamInfo>, String> {
+    let ul_state = state.lock().await;
+
+    ul_state.server_state.list_streams().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_server_rooms(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<RoomInfo>, String> {
+    let ul_state = state.lock().await;
+
+    ul_state.server_state.list_rooms().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_server_room(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, name: String) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    ul_state.server_state.create_room(&name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn close_server_room(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, name: String) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    ul_state.server_state.close_room(&name).await.map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_server_relays(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<RelayInfo>, String> {
+    let ul_state = state.lock().await;
+
+    ul_state.server_state.list_relays().await.map_err(|e| e.to_string())
+}
+
+// relay every stream to `url`
+#[tauri::command]
+async fn add_server_relay(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, url: String) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    ul_state.server_state.add_relay(RelayConfig::new(&url)).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_server_relay(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, url: String) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    ul_state.server_state.remove_relay(&url).await.map_err(|e| e.to_string())
+}
+
+// pull the http audio stream at `url` in as a client
+#[tauri::command]
+async fn add_server_ingest(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, url: String) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    ul_state.server_state.add_ingest(IngestConfig::new(&url)).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn disconnect_server_client(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, id: String) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    ul_state.server_state.disconnect_client(&id).await.map(|_| ()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn change_server_input_device(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, device: DeviceId) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    ul_state.server_state.change_input_device(&device).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -55,24 +141,91 @@ async fn get_client_connections(state: tauri::State<'_, Arc<Mutex<ProgramState>>
 }
 
 #[tauri::command]
-async fn get_client_devices(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<String>, String> {
-    return Ok(
-        cpal::default_host()
-        .input_devices()
-        .expect("could not get input devices!")
-        .map(|device|device.name().expect("could not get name!"))
-        .collect::<Vec<String>>()
-    );
+async fn get_client_stats(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<(String, StreamStats)>, String> {
+    
+    let mut ul_state = state.lock().await;
+
+    let mut stats = vec![];
+    for client in ul_state.client_server_connections.iter_mut() {
+        stats.push((client.name().await, client.stats().await));
+    }
+
+    return Ok(stats);
+}
+
+// per client, the extra servers it sends to
+#[tauri::command]
+async fn get_client_links(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<Vec<(String, StreamStats)>>, String> {
+    let ul_state = state.lock().await;
+
+    Ok(ul_state.client_server_connections.iter().map(|client| client.link_stats()).collect())
+}
+
+#[tauri::command]
+async fn add_client_server(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, cpos: usize, address: String) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    let client = ul_state.client_server_connections.get_mut(cpos).ok_or("no such client")?;
+    client.add_server(&address, LinkConfig::default()).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn connect_client(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, address: String) -> Result<(), String> { 
+async fn remove_client_server(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, cpos: usize, address: String) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    let client = ul_state.client_server_connections.get_mut(cpos).ok_or("no such client")?;
+    client.remove_server(&address).map_err(|e| e.to_string())
+}
+
+// per client, the id and device of every extra source
+#[tauri::command]
+async fn get_client_sources(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<Vec<(u16, String)>>, String> {
+    let ul_state = state.lock().await;
+
+    Ok(ul_state.client_server_connections.iter().map(|client| client.sources()).collect())
+}
+
+#[tauri::command]
+async fn add_client_source(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, cpos: usize, device: DeviceId) -> Result<u16, String> {
+    let mut ul_state = state.lock().await;
+
+    let client = ul_state.client_server_connections.get_mut(cpos).ok_or("no such client")?;
+    let device = remoteio_backend::devices::find_input_device(&device).map_err(|e| e.to_string())?;
+    client.add_source(device).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_client_source(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, cpos: usize, id: u16) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    let client = ul_state.client_server_connections.get_mut(cpos).ok_or("no such client")?;
+    client.remove_source(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_client_devices(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<DeviceInfo>, String> {
+    return remoteio_backend::devices::input_devices().map_err(|e| e.to_string());
+}
+
+#[tauri::command]
+async fn connect_client(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, address: String, name: String, room: String, talkback: bool) -> Result<(), String> { 
     let mut ul_state = state.lock().await;
 
     //default device should be changed 
     let default_device = cpal::default_host().default_input_device().expect("could not get default input device!");
 
     let mut client = remoteio_backend::client::Metal2RemoteClient::new(default_device);
+    if !name.is_empty() {
+        client.set_name(&name);
+    }
+    if !room.is_empty() {
+        client.set_room(Some(&room));
+    }
+    // hear the server's input back on the default output device
+    if talkback {
+        let output = cpal::default_host().default_output_device().ok_or("could not get default output device!")?;
+        client.set_return_device(Some(output)).map_err(|e| e.to_string())?;
+    }
     client.connect(&address).await.expect("could not connect client to given address!");
 
     ul_state.client_server_connections.push(client);
@@ -83,7 +236,7 @@ async fn connect_client(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, addre
 #[tauri::command]
 async fn client_disconnect_client(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, cpos: usize) -> Result<(), String> { 
 
-    println!("client disconnect client {}", cpos);
+    tracing::info!("client disconnect client {}", cpos);
     let mut ul_state = state.lock().await;
 
     let connections = &mut ul_state.client_server_connections;
@@ -94,28 +247,60 @@ async fn client_disconnect_client(state: tauri::State<'_, Arc<Mutex<ProgramState
 }
 
 #[tauri::command]
-async fn change_server_output_device(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, cpos: usize, dname: String) -> Result<(), String> {
-    let device = cpal::default_host().output_devices().expect("could not get output devices!").filter(|device| device.name().expect("could not get device name!") == dname).next().expect("could not find server output device!");
- 
+async fn change_server_output_device(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, id: String, device: DeviceId) -> Result<(), String> {
     let mut ul_state = state.lock().await;
-    ul_state.server_state.change_output_device(cpos, device).await.expect("could not change output device!");
+    ul_state.server_state.change_output_device(&id, &device).await.map_err(|e| e.to_string())?;
 
     Ok(())
 }
 
 #[tauri::command]
-async fn change_client_input_device(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, cpos: usize, dname: String) -> Result<(), String> {
+async fn get_routing_rules(state: tauri::State<'_, Arc<Mutex<ProgramState>>>) -> Result<Vec<RoutingRule>, String> {
+    let ul_state = state.lock().await;
+
+    ul_state.server_state.routing_rules().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_routing_rules(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, rules: Vec<RoutingRule>) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    ul_state.server_state.set_routing_rules(rules).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn change_client_input_device(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, cpos: usize, device: DeviceId) -> Result<(), String> {
     let mut ul_state = state.lock().await;
 
     let client = ul_state.client_server_connections.get_mut(cpos).expect("could not get client from tauri clients state");
     
-    let device = cpal::default_host().input_devices().expect("could not get input devices!").filter(|device| device.name().expect("could not get device name!") == dname).next().expect("could not find client input device!");
+    let device = remoteio_backend::devices::find_input_device(&device).map_err(|e| e.to_string())?;
     client.change_source_device(device).await.expect("could not change source device!");
 
     
     Ok(())
 }
 
+#[tauri::command]
+async fn change_client_output_device(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, cpos: usize, device: DeviceId) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    let client = ul_state.client_server_connections.get_mut(cpos).ok_or("no such client")?;
+
+    let device = remoteio_backend::devices::find_output_device(&device).map_err(|e| e.to_string())?;
+    client.set_return_device(Some(device)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_client_echo_cancellation(state: tauri::State<'_, Arc<Mutex<ProgramState>>>, cpos: usize, enabled: bool) -> Result<(), String> {
+    let mut ul_state = state.lock().await;
+
+    let client = ul_state.client_server_connections.get_mut(cpos).ok_or("no such client")?;
+    client.set_echo_cancellation(enabled);
+
+    Ok(())
+}
+
 
 #[derive(Default)]
 pub struct ProgramState {
@@ -125,8 +310,11 @@ pub struct ProgramState {
 
 #[tokio::main]
 async fn main() {
+    let _log_guard = remoteio_backend::logging::init(&remoteio_backend::logging::LogConfig::from_env()).expect("could not set up logging!");
 
     let mut server_state = remoteio_backend::server::MetalServer::new("0.0.0.0:8000");
+    server_state.set_rest_endpoint(remoteio_shared::CONFIG.rest_endpoint);
+    server_state.set_routing_file(RoutingTable::default_path()).expect("could not load routing rules!");
     let _ = server_state.bind().await;
 
     let state = 
@@ -142,15 +330,53 @@ async fn main() {
         .invoke_handler(tauri::generate_handler![
             greet, 
             get_server_connections,
+            get_server_stats,
             get_server_devices,
+            get_server_input_devices,
+            get_server_streams,
+            get_server_rooms,
+            create_server_room,
+            close_server_room,
+            get_server_relays,
+            add_server_relay,
+            remove_server_relay,
+            add_server_ingest,
+            disconnect_server_client,
+            change_server_input_device,
             get_client_connections,
+            get_client_stats,
+            get_client_links,
+            add_client_server,
+            remove_client_server,
+            get_client_sources,
+            add_client_source,
+            remove_client_source,
             get_client_devices,
             connect_client,
             client_disconnect_client,
             change_server_output_device,
             change_client_input_device,
+            change_client_output_device,
+            set_client_echo_cancellation,
+            get_routing_rules,
+            set_routing_rules,
             ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                // close clients properly instead of leaving them to time out
+                let state = Arc::clone(&*app_handle.state::<Arc<Mutex<ProgramState>>>());
+                let runtime = tokio::runtime::Handle::current();
+
+                std::thread::spawn(move || {
+                    runtime.block_on(async move {
+                        let _ = state.lock().await.server_state.shutdown().await;
+                    })
+                })
+                .join()
+                .expect("could not shut down server!");
+            }
+        });
 
 }
@@ -18,17 +18,101 @@ function App() {
 
   const [serverConnections, setServerConnections] = useState([]);
   const [serverDevices, setServerDevices] = useState([]);
+  const [serverInputDevices, setServerInputDevices] = useState([]);
+  const [serverStats, setServerStats] = useState([]);
+  const [serverStreams, setServerStreams] = useState([]);
+  const [serverRooms, setServerRooms] = useState([]);
+  const [serverRelays, setServerRelays] = useState([]);
   const [clientConnections, setClientConnections] = useState([]);
+  const [clientStats, setClientStats] = useState([]);
+  const [clientLinks, setClientLinks] = useState([]);
+  const [clientSources, setClientSources] = useState([]);
   const [clientDevices, setClientDevices] = useState([]);
 
   async function getServerConnections() {
     return await invoke("get_server_connections");
   }
 
+  async function getServerStats() {
+    return await invoke("get_server_stats");
+  }
+
+  async function getClientStats() {
+    return await invoke("get_client_stats");
+  }
+
+  async function getClientLinks() {
+    return await invoke("get_client_links");
+  }
+
+  async function addClientServer(cpos, address) {
+    return await invoke("add_client_server", { cpos, address });
+  }
+
+  async function removeClientServer(cpos, address) {
+    return await invoke("remove_client_server", { cpos, address });
+  }
+
+  async function getClientSources() {
+    return await invoke("get_client_sources");
+  }
+
+  async function addClientSource(cpos, device) {
+    return await invoke("add_client_source", { cpos, device });
+  }
+
+  async function removeClientSource(cpos, id) {
+    return await invoke("remove_client_source", { cpos, id });
+  }
+
   async function getServerDevices() {
     return await invoke("get_server_devices");
   }
 
+  async function getServerInputDevices() {
+    return await invoke("get_server_input_devices");
+  }
+
+  async function getServerStreams() {
+    return await invoke("get_server_streams");
+  }
+
+  async function getServerRooms() {
+    return await invoke("get_server_rooms");
+  }
+
+  async function createServerRoom(name) {
+    return await invoke("create_server_room", { name });
+  }
+
+  async function closeServerRoom(name) {
+    return await invoke("close_server_room", { name });
+  }
+
+  async function getServerRelays() {
+    return await invoke("get_server_relays");
+  }
+
+  async function addServerRelay(url) {
+    return await invoke("add_server_relay", { url });
+  }
+
+  async function removeServerRelay(url) {
+    return await invoke("remove_server_relay", { url });
+  }
+
+  async function addServerIngest(url) {
+    return await invoke("add_server_ingest", { url });
+  }
+
+  async function disconnectServerClient(id) {
+    return await invoke("disconnect_server_client", { id });
+  }
+
+  async function changeServerInputDevice(device) {
+    return await invoke("change_server_input_device", { device });
+  }
+
   async function getClientConnections() {
     return await invoke("get_client_connections");
 
@@ -38,8 +122,8 @@ function App() {
     return await invoke("get_client_devices");
   }
 
-  async function connectClient(address) {
-    return await invoke("connect_client", { address });
+  async function connectClient(address, name, room, talkback) {
+    return await invoke("connect_client", { address, name, room, talkback });
 
   }
 
@@ -48,12 +132,28 @@ function App() {
 
   }
 
-  async function changeServerOutputDevice(cpos, dname) {
-    return await invoke("change_server_output_device", { cpos, dname });
+  async function changeServerOutputDevice(id, device) {
+    return await invoke("change_server_output_device", { id, device });
+  }
+
+  async function changeClientInputDevice(cpos, device) {
+    return await invoke("change_client_input_device", { cpos, device });
+  }
+
+  async function changeClientOutputDevice(cpos, device) {
+    return await invoke("change_client_output_device", { cpos, device });
+  }
+
+  async function setClientEchoCancellation(cpos, enabled) {
+    return await invoke("set_client_echo_cancellation", { cpos, enabled });
+  }
+
+  async function getRoutingRules() {
+    return await invoke("get_routing_rules");
   }
 
-  async function changeClientInputDevice(cpos, dname) {
-    return await invoke("change_client_input_device", { cpos, dname });
+  async function setRoutingRules(rules) {
+    return await invoke("set_routing_rules", { rules });
   }
 
 
@@ -61,7 +161,15 @@ function App() {
     async function update() {
       setServerConnections(await getServerConnections());
       setServerDevices(await getServerDevices());
+      setServerInputDevices(await getServerInputDevices());
+      setServerStats(await getServerStats());
+      setServerStreams(await getServerStreams());
+      setServerRooms(await getServerRooms());
+      setServerRelays(await getServerRelays());
       setClientConnections(await getClientConnections());
+      setClientStats(await getClientStats());
+      setClientLinks(await getClientLinks());
+      setClientSources(await getClientSources());
       setClientDevices(await getClientDevices());
     }
 
@@ -85,10 +193,20 @@ function App() {
 
           <Client
             clientConnections={clientConnections}
+            clientStats={clientStats}
+            clientLinks={clientLinks}
+            clientSources={clientSources}
             clientDevices={clientDevices}
+            clientOutputDevices={serverDevices}
             connectClient={connectClient}
             clientDisconnectClient={clientDisconnectClient}
-            changeClientInputDevice={changeClientInputDevice}>
+            changeClientInputDevice={changeClientInputDevice}
+            changeClientOutputDevice={changeClientOutputDevice}
+            setClientEchoCancellation={setClientEchoCancellation}
+            addClientServer={addClientServer}
+            removeClientServer={removeClientServer}
+            addClientSource={addClientSource}
+            removeClientSource={removeClientSource}>
           </Client>
 
         </div>
@@ -96,8 +214,22 @@ function App() {
         <div label="Server">
           <Server
             serverConnections={serverConnections}
+            serverStats={serverStats}
             serverDevices={serverDevices}
-            changeServerOutputDevice={changeServerOutputDevice}>
+            serverInputDevices={serverInputDevices}
+            serverStreams={serverStreams}
+            serverRooms={serverRooms}
+            createServerRoom={createServerRoom}
+            closeServerRoom={closeServerRoom}
+            serverRelays={serverRelays}
+            addServerRelay={addServerRelay}
+            removeServerRelay={removeServerRelay}
+            addServerIngest={addServerIngest}
+            disconnectServerClient={disconnectServerClient}
+            changeServerOutputDevice={changeServerOutputDevice}
+            changeServerInputDevice={changeServerInputDevice}
+            getRoutingRules={getRoutingRules}
+            setRoutingRules={setRoutingRules}>
           </Server>
         </div>
 
@@ -1,14 +1,25 @@
 import { useState, useEffect } from "react";
 import React, { Component } from 'react';
 import PropTypes from 'prop-types';
+import Stats from './Stats';
 
 class Client extends Component {
     static propTypes = {
         clientConnections: PropTypes.instanceOf(Array).isRequired,
         clientDevices: PropTypes.instanceOf(Array).isRequired,
+        clientOutputDevices: PropTypes.instanceOf(Array).isRequired,
+        clientStats: PropTypes.instanceOf(Array).isRequired,
+        clientLinks: PropTypes.instanceOf(Array).isRequired,
+        clientSources: PropTypes.instanceOf(Array).isRequired,
         connectClient: PropTypes.func.isRequired,
         clientDisconnectClient: PropTypes.func.isRequired,
-        changeClientInputDevice: PropTypes.func.isRequired
+        changeClientInputDevice: PropTypes.func.isRequired,
+        changeClientOutputDevice: PropTypes.func.isRequired,
+        setClientEchoCancellation: PropTypes.func.isRequired,
+        addClientServer: PropTypes.func.isRequired,
+        removeClientServer: PropTypes.func.isRequired,
+        addClientSource: PropTypes.func.isRequired,
+        removeClientSource: PropTypes.func.isRequired
     };
 
     constructor(props) {
@@ -16,6 +27,13 @@ class Client extends Component {
 
         this.state = {
             activeClientText: 'ws://0.0.0.0:8000',
+            clientName: '',
+            room: '',
+            talkback: false,
+            // client position -> echo cancellation on
+            echoCancellation: {},
+            // client position -> address typed in to also send to
+            linkText: {},
         };
     }
 
@@ -26,20 +44,44 @@ class Client extends Component {
             props: {
                 clientConnections,
                 clientDevices,
+                clientOutputDevices,
+                clientStats,
+                clientLinks,
+                clientSources,
                 connectClient,
                 clientDisconnectClient,
-                changeClientInputDevice
+                changeClientInputDevice,
+                changeClientOutputDevice,
+                setClientEchoCancellation,
+                addClientServer,
+                removeClientServer,
+                addClientSource,
+                removeClientSource
             },
         } = this;
 
         let setActiveClientText = (text) => { this.setState({ activeClientText: text }) }
+        let setClientName = (text) => { this.setState({ clientName: text }) }
+        let setRoom = (text) => { this.setState({ room: text }) }
+        let setEchoCancellation = (ci, enabled) => {
+            setClientEchoCancellation(ci, enabled)
+                .then(() => this.setState({ echoCancellation: { ...this.state.echoCancellation, [ci]: enabled } }))
+                .catch(alert);
+        }
+        let setLinkText = (ci, text) => { this.setState({ linkText: { ...this.state.linkText, [ci]: text } }) }
 
 
         return (
             <div>
                 <div>
                     <input onChange={(e) => setActiveClientText(e.target.value)} value={this.state.activeClientText}></input>
-                    <button onClick={() => connectClient(this.state.activeClientText)}>connect</button>
+                    <input placeholder="name (optional)" onChange={(e) => setClientName(e.target.value)} value={this.state.clientName}></input>
+                    <input placeholder="room (optional)" onChange={(e) => setRoom(e.target.value)} value={this.state.room}></input>
+                    <label>
+                        <input type="checkbox" checked={this.state.talkback} onChange={(e) => this.setState({ talkback: e.target.checked })}></input>
+                        talkback
+                    </label>
+                    <button onClick={() => connectClient(this.state.activeClientText, this.state.clientName, this.state.room, this.state.talkback).catch(alert)}>connect</button>
                 </div>
 
                 <ul>
@@ -52,17 +94,71 @@ class Client extends Component {
                                 <button onClick={() => clientDisconnectClient(ci)}>
                                     disconnect
                                 </button>
+                                <Stats stats={(clientStats[ci] || [])[1]}></Stats>
+                                <div>
+                                    <input placeholder="also send to" onChange={(e) => setLinkText(ci, e.target.value)} value={this.state.linkText[ci] || ''}></input>
+                                    <button onClick={() => addClientServer(ci, this.state.linkText[ci] || '').then(() => setLinkText(ci, '')).catch(alert)}>add</button>
+                                </div>
+                                <ul>
+                                    {
+                                        (clientLinks[ci] || []).map(([url, stats]) => (
+                                            <li key={url}>
+                                                {url}
+                                                <button onClick={() => removeClientServer(ci, url).catch(alert)}>
+                                                    remove
+                                                </button>
+                                                <Stats stats={stats}></Stats>
+                                            </li>
+                                        ))
+                                    }
+                                </ul>
                                 <ul>
                                     {
                                         clientDevices.map((device) => (
-                                            <li>
-                                                <button onClick={() => changeClientInputDevice(ci, device)}>
-                                                    {device}
+                                            <li key={`${device.id.host}:${device.id.name}#${device.id.index}`}>
+                                                <button onClick={() => changeClientInputDevice(ci, device.id).catch(alert)}>
+                                                    {device.name}{device.id.index > 0 ? ` (${device.id.index + 1})` : ""}{device.is_default ? " (default)" : ""}
+                                                </button>
+                                                <button onClick={() => addClientSource(ci, device.id).catch(alert)}>
+                                                    also capture
+                                                </button>
+                                            </li>
+                                        ))
+                                    }
+                                </ul>
+                                <ul>
+                                    {
+                                        (clientSources[ci] || []).map(([id, device]) => (
+                                            <li key={id}>
+                                                source {id}: {device}
+                                                <button onClick={() => removeClientSource(ci, id).catch(alert)}>
+                                                    remove
                                                 </button>
                                             </li>
                                         ))
                                     }
                                 </ul>
+                                {
+                                    (clientStats[ci] || [])[1]?.reverse &&
+                                    <label>
+                                        <input type="checkbox" checked={!!this.state.echoCancellation[ci]} onChange={(e) => setEchoCancellation(ci, e.target.checked)}></input>
+                                        cancel echo
+                                    </label>
+                                }
+                                {
+                                    (clientStats[ci] || [])[1]?.reverse &&
+                                    <ul>
+                                        {
+                                            clientOutputDevices.map((device) => (
+                                                <li key={`${device.id.host}:${device.id.name}#${device.id.index}`}>
+                                                    <button onClick={() => changeClientOutputDevice(ci, device.id).catch(alert)}>
+                                                        hear on {device.name}{device.id.index > 0 ? ` (${device.id.index + 1})` : ""}{device.is_default ? " (default)" : ""}
+                                                    </button>
+                                                </li>
+                                            ))
+                                        }
+                                    </ul>
+                                }
                             </li>
                         ))
                     }
@@ -0,0 +1,57 @@
+import React, { Component } from 'react';
+import PropTypes from 'prop-types';
+
+// routing rules as json, only loaded on demand so polling doesn't overwrite edits in progress
+class Routes extends Component {
+    static propTypes = {
+        getRoutingRules: PropTypes.func.isRequired,
+        setRoutingRules: PropTypes.func.isRequired,
+    };
+
+    constructor(props) {
+        super(props);
+        this.state = { text: "" };
+    }
+
+    componentDidMount() {
+        this.reload();
+    }
+
+    async reload() {
+        const rules = await this.props.getRoutingRules();
+        this.setState({ text: JSON.stringify(rules, null, 2) });
+    }
+
+    async save() {
+        let rules;
+        try {
+            rules = JSON.parse(this.state.text);
+        } catch (e) {
+            alert(`routing rules are not valid json: ${e}`);
+            return;
+        }
+
+        await this.props.setRoutingRules(rules).catch(alert);
+        await this.reload();
+    }
+
+    render() {
+        return (
+            <div className="routes">
+                <h3>Routing rules</h3>
+                <textarea
+                    rows={12}
+                    cols={60}
+                    value={this.state.text}
+                    onChange={(e) => this.setState({ text: e.target.value })}>
+                </textarea>
+                <div>
+                    <button onClick={() => this.save()}>Save</button>
+                    <button onClick={() => this.reload().catch(alert)}>Reload</button>
+                </div>
+            </div>
+        )
+    }
+}
+
+export default Routes;
@@ -1,21 +1,61 @@
 import React, { Component } from 'react';
 import PropTypes from 'prop-types';
+import Stats from './Stats';
+import Routes from './Routes';
 
 class Server extends Component {
     static propTypes = {
         serverConnections: PropTypes.instanceOf(Array).isRequired,
         serverDevices: PropTypes.instanceOf(Array).isRequired,
+        serverInputDevices: PropTypes.instanceOf(Array).isRequired,
+        serverStats: PropTypes.instanceOf(Array).isRequired,
+        serverStreams: PropTypes.instanceOf(Array).isRequired,
+        serverRooms: PropTypes.instanceOf(Array).isRequired,
+        createServerRoom: PropTypes.func.isRequired,
+        closeServerRoom: PropTypes.func.isRequired,
+        serverRelays: PropTypes.instanceOf(Array).isRequired,
+        addServerRelay: PropTypes.func.isRequired,
+        removeServerRelay: PropTypes.func.isRequired,
+        addServerIngest: PropTypes.func.isRequired,
+        disconnectServerClient: PropTypes.func.isRequired,
         changeServerOutputDevice: PropTypes.func.isRequired,
+        changeServerInputDevice: PropTypes.func.isRequired,
+        getRoutingRules: PropTypes.func.isRequired,
+        setRoutingRules: PropTypes.func.isRequired,
     };
 
 
 
+    constructor(props) {
+        super(props);
+
+        this.state = {
+            roomName: '',
+            relayUrl: '',
+            ingestUrl: '',
+        };
+    }
+
     render() {
         const {
             props: {
                 serverConnections,
                 serverDevices,
-                changeServerOutputDevice
+                serverInputDevices,
+                serverStats,
+                serverStreams,
+                serverRooms,
+                createServerRoom,
+                closeServerRoom,
+                serverRelays,
+                addServerRelay,
+                removeServerRelay,
+                addServerIngest,
+                disconnectServerClient,
+                changeServerOutputDevice,
+                changeServerInputDevice,
+                getRoutingRules,
+                setRoutingRules
             },
         } = this;
 
@@ -23,17 +63,19 @@ class Server extends Component {
             <div>
                 <ul>
                     {
-                        serverConnections.map((conn, cpos) => (
-                            <li>
-                                <button>
-                                    {conn}
+                        serverConnections.map((conn) => (
+                            <li key={conn.id}>
+                                <button title={`${conn.url} in room ${conn.room}`}>
+                                    {conn.name}{conn.role === "Listener" ? ` (listening to ${conn.stream})` : ""}{conn.role === "Duplex" ? " (talkback)" : ""}
                                 </button>
+                                {conn.url.startsWith("http://") ? <button onClick={() => disconnectServerClient(conn.id).catch(alert)}>stop</button> : null}
+                                <Stats stats={(serverStats.find(([id]) => id === conn.id) || [])[1]}></Stats>
                                 <ul>
                                     {
-                                        serverDevices.map((device) => (
-                                            <li>
-                                                <button onClick={() => changeServerOutputDevice(cpos, device)}>
-                                                    {device}
+                                        conn.role === "Listener" ? null : serverDevices.map((device) => (
+                                            <li key={`${device.id.host}:${device.id.name}#${device.id.index}`}>
+                                                <button onClick={() => changeServerOutputDevice(conn.id, device.id).catch(alert)}>
+                                                    {device.name}{device.id.index > 0 ? ` (${device.id.index + 1})` : ""}{device.is_default ? " (default)" : ""}
                                                 </button>
                                             </li>))
                                     }
@@ -42,6 +84,69 @@ class Server extends Component {
                         ))
                     }
                 </ul>
+                <h3>Listeners hear</h3>
+                <ul>
+                    {
+                        serverInputDevices.map((device) => (
+                            <li key={`${device.id.host}:${device.id.name}#${device.id.index}`}>
+                                <button onClick={() => changeServerInputDevice(device.id).catch(alert)}>
+                                    {device.name}{device.id.index > 0 ? ` (${device.id.index + 1})` : ""}{device.is_default ? " (default)" : ""}
+                                </button>
+                            </li>))
+                    }
+                </ul>
+                <h3>Rooms</h3>
+                <div>
+                    <input placeholder="room name" onChange={(e) => this.setState({ roomName: e.target.value })} value={this.state.roomName}></input>
+                    <button onClick={() => createServerRoom(this.state.roomName).then(() => this.setState({ roomName: '' })).catch(alert)}>create</button>
+                </div>
+                <ul>
+                    {
+                        serverRooms.map((room) => (
+                            <li key={room.name}>
+                                {room.name}: {room.clients} connected
+                                {room.name === "default" ? null : <button onClick={() => closeServerRoom(room.name).catch(alert)}>close</button>}
+                            </li>))
+                    }
+                </ul>
+                <h3>Streams</h3>
+                <ul>
+                    {
+                        serverStreams.map((stream) => (
+                            <li key={stream.source || stream.name} title={stream.source || "server input"}>
+                                {stream.name}{stream.room ? ` (${stream.room})` : ""}: {stream.listeners} listening
+                            </li>))
+                    }
+                </ul>
+                <h3>Relays</h3>
+                <div>
+                    <input placeholder="ws://upstream:8000" onChange={(e) => this.setState({ relayUrl: e.target.value })} value={this.state.relayUrl}></input>
+                    <button onClick={() => addServerRelay(this.state.relayUrl).then(() => this.setState({ relayUrl: '' })).catch(alert)}>relay to</button>
+                </div>
+                <ul>
+                    {
+                        serverRelays.map((relay) => (
+                            <li key={relay.url}>
+                                {relay.url}: {relay.streams.length} streams
+                                <button onClick={() => removeServerRelay(relay.url).catch(alert)}>remove</button>
+                                <ul>
+                                    {
+                                        relay.streams.map(([id, stats]) => (
+                                            <li key={id}>
+                                                {id}
+                                                <Stats stats={stats}></Stats>
+                                            </li>))
+                                    }
+                                </ul>
+                            </li>))
+                    }
+                </ul>
+                <h3>Pull in</h3>
+                <div>
+                    <input placeholder="http://radio:8000/live.wav" onChange={(e) => this.setState({ ingestUrl: e.target.value })} value={this.state.ingestUrl}></input>
+                    <button onClick={() => addServerIngest(this.state.ingestUrl).then(() => this.setState({ ingestUrl: '' })).catch(alert)}>pull in</button>
+                </div>
+                <Routes getRoutingRules={getRoutingRules} setRoutingRules={setRoutingRules}></Routes>
             </div>
         )
     }
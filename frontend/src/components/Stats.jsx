@@ -0,0 +1,66 @@
+import React, { Component } from 'react';
+import PropTypes from 'prop-types';
+
+class Stats extends Component {
+    static propTypes = {
+        stats: PropTypes.object,
+    };
+
+
+
+    render() {
+        const {
+            props: {
+                stats
+            },
+        } = this;
+
+        if (!stats) {
+            return null;
+        }
+
+        const rtt = stats.rtt_ms === null ? "N/A" : `${stats.rtt_ms.toFixed(1)}ms`;
+        const kib = (bytes) => (bytes / 1024).toFixed(0);
+
+        return (
+            <div className="stats">
+                <div>
+                    rtt {rtt} | jitter {stats.jitter_ms.toFixed(1)}ms | buffered {stats.buffer_frames} frames
+                </div>
+                <div>
+                    sent {kib(stats.bytes_sent)}KiB | received {kib(stats.bytes_received)}KiB | underruns {stats.underruns} | overruns {stats.overruns} | concealed {stats.concealed_frames} frames
+                </div>
+                {
+                    stats.fec &&
+                    <div>
+                        fec {stats.fec.enabled ? "on" : "off"} | loss {(stats.fec.loss_rate * 100).toFixed(1)}% | recovered {stats.fec.frames_recovered} | lost {stats.fec.frames_lost}
+                    </div>
+                }
+                {
+                    stats.hops.length > 0 &&
+                    <div>
+                        relayed via {stats.hops.map((hop) => `${hop.server.slice(0, 8)} (${hop.latency_ms === null ? "N/A" : `${hop.latency_ms.toFixed(1)}ms`})`).join(" > ")}
+                    </div>
+                }
+                {
+                    stats.levels.map((level, channel) => (
+                        <div>
+                            <meter min="0" max="1" value={level.peak}></meter>
+                            <meter min="0" max="1" value={level.rms}></meter>
+                            {" "}ch{channel}
+                        </div>
+                    ))
+                }
+                {
+                    stats.reverse &&
+                    <div>
+                        return audio
+                        <Stats stats={stats.reverse}></Stats>
+                    </div>
+                }
+            </div>
+        )
+    }
+}
+
+export default Stats;
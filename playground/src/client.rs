@@ -1,10 +1,11 @@
 use cpal::traits::HostTrait;
 use remoteio_backend::client::Client;
-use remoteio_backend::server::Server;
 
 #[tokio::main]
 async fn main() {
-    let mut host = cpal::default_host().default_input_device().expect("could not get default input device!");
+    let _log_guard = remoteio_backend::logging::init(&remoteio_backend::logging::LogConfig::from_env()).expect("could not set up logging!");
+
+    let host = cpal::default_host().default_input_device().expect("could not get default input device!");
     let mut client = remoteio_backend::client::Metal2RemoteClient::new(host);
 
 
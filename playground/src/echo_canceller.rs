@@ -0,0 +1,81 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use remoteio_backend::aec::{CaptureEcho, EchoConfig, EchoReference};
+
+// runs the echo canceller on synthetic audio: noisy far end speech through a made up room, with a
+// near end talker joining for the last quarter. no audio devices needed, exits with an error if the echo isn't
+// cancelled or the talker is
+static SAMPLE_RATE: u32 = 48000;
+static BLOCK: usize = 480;
+
+// deterministic noise in [-1, 1)
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+        (self.0 >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+}
+
+fn power(samples: &[f32]) -> f32 {
+    samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32
+}
+
+fn main() {
+    let seconds = 8;
+    let frames = SAMPLE_RATE as usize * seconds;
+    let mut noise = Noise(1);
+
+    // far end: noise with a syllable-ish envelope
+    let far: Vec<f32> = (0..frames)
+        .map(|n| {
+            let t = n as f32 / SAMPLE_RATE as f32;
+            0.3 * noise.next() * (0.6 + 0.4 * (2.0 * std::f32::consts::PI * 3.0 * t).sin())
+        })
+        .collect();
+
+    // room: 5ms of buffering and flight, then a decaying tail
+    let delay = SAMPLE_RATE as usize / 200;
+    let room: Vec<f32> = (0..1200).map(|k| 0.05 * (-(k as f32) / 200.0).exp() * noise.next()).collect();
+
+    let echo: Vec<f32> = (0..frames)
+        .map(|n| room.iter().enumerate().filter(|(k, _)| n >= delay + k).map(|(k, h)| h * far[n - delay - k]).sum())
+        .collect();
+
+    // near end talks over the last quarter
+    let near: Vec<f32> = (0..frames)
+        .map(|n| match n >= frames * 3 / 4 {
+            true => 0.2 * (2.0 * std::f32::consts::PI * 220.0 * n as f32 / SAMPLE_RATE as f32).sin(),
+            false => 0.0
+        })
+        .collect();
+
+    let reference = EchoReference::default();
+    let mut writer = reference.writer(SAMPLE_RATE);
+    let mut canceller = CaptureEcho::new(EchoConfig::default(), 1, SAMPLE_RATE, reference.clone(), Arc::new(AtomicBool::new(true)));
+
+    let mut output = Vec::with_capacity(frames);
+    for start in (0..frames).step_by(BLOCK) {
+        // the output callback runs before the capture that hears it
+        writer.push(&far[start..start + BLOCK]);
+
+        let capture: Vec<f32> = (start..start + BLOCK).map(|n| echo[n] + near[n]).collect();
+        output.extend_from_slice(canceller.process(&capture));
+    }
+
+    // echo only, the last second before the near end starts talking
+    let converged = frames * 3 / 4 - SAMPLE_RATE as usize..frames * 3 / 4;
+    let erle = 10.0 * (power(&echo[converged.clone()]) / power(&output[converged])).log10();
+    println!("echo attenuated by {:.1} dB", erle);
+
+    // during double talk what's left should be the talker
+    let talking = frames * 3 / 4..frames;
+    let residual: Vec<f32> = talking.clone().map(|n| output[n] - near[n]).collect();
+    let kept = 10.0 * (power(&near[talking.clone()]) / power(&residual)).log10();
+    println!("near end kept {:.1} dB above residual echo", kept);
+
+    assert!(erle > 20.0, "echo not cancelled!");
+    assert!(kept > 15.0, "near end talker damaged!");
+}
@@ -0,0 +1,19 @@
+use cpal::traits::HostTrait;
+use remoteio_backend::client::Client;
+
+// plays the server's input device, or the stream named by the second argument, on this machine's
+// default output
+#[tokio::main]
+async fn main() {
+    let _log_guard = remoteio_backend::logging::init(&remoteio_backend::logging::LogConfig::from_env()).expect("could not set up logging!");
+
+    let url = std::env::args().nth(1).unwrap_or_else(|| "ws://0.0.0.0:8000".to_owned());
+
+    let device = cpal::default_host().default_output_device().expect("could not get default output device!");
+    let mut client = remoteio_backend::client::Remote2MetalClient::new(device);
+    client.set_stream(std::env::args().nth(2).as_deref());
+
+    client.connect(&url).await.expect("could not connect to server!");
+
+    tokio::signal::ctrl_c().await.expect("could not listen for ctrl-c!");
+}
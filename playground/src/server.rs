@@ -1,10 +1,14 @@
-use remoteio_backend::client::Client;
 use remoteio_backend::server::Server;
 
 #[tokio::main]
 async fn main() {
+    let _log_guard = remoteio_backend::logging::init(&remoteio_backend::logging::LogConfig::from_env()).expect("could not set up logging!");
+
     let mut server = remoteio_backend::server::MetalServer::new("0.0.0.0:8000");
+    server.set_rest_endpoint(remoteio_shared::CONFIG.rest_endpoint);
+    server.set_routing_file(remoteio_backend::routing::RoutingTable::default_path()).expect("could not load routing rules!");
     let _ = server.bind().await; 
 
-    loop{}
+    tokio::signal::ctrl_c().await.expect("could not listen for ctrl-c!");
+    server.shutdown().await.expect("could not shut down server!");
 }
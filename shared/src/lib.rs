@@ -1,5 +1,4 @@
 use serde::Deserialize;
-use std::{fs, path::Path};
 
 #[derive(Deserialize, Debug)]
 pub struct RemoteIOConfig {
@@ -8,9 +7,12 @@ pub struct RemoteIOConfig {
     pub server_endpoint: &'static str
 }
 
-pub static config: RemoteIOConfig  = RemoteIOConfig {
+pub static CONFIG: RemoteIOConfig = RemoteIOConfig {
     rest_endpoint: "0.0.0.0:3000",
     server_endpoint: "0.0.0.0:8000",
     ws_endpoint: "ws://0.0.0.0:8000"
 
-};
\ No newline at end of file
+};
+
+// the name this had before, kept so existing users still build
+pub use self::CONFIG as config;